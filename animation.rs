@@ -0,0 +1,226 @@
+use crate::matrix::Matrix;
+use crate::matrix::Polar;
+use crate::matrix::polar;
+use crate::transform::Transform2D;
+
+// Animation
+// An `Animation` moves a quilt through a list of keyframe transforms, producing a
+// transform for every frame in between. Interpolating the entries of two matrices
+// directly looks wrong: halfway through a quarter turn the average of the two rotation
+// matrices is a rotation by 45 degrees which also shrinks the image to 71% of its size,
+// and halfway through a half turn the image collapses to a point.
+//
+// Instead every keyframe is broken into three parts, each interpolated on its own terms:
+//
+//  1. Where it moves a pivot point, usually the center of the image, interpolated along
+//     a straight line. Rotations about the pivot then keep it still throughout.
+//  2. The rotation of the polar decomposition of its linear part, interpolated by angle
+//     the short way around the circle.
+//  3. The stretch of the polar decomposition, a symmetric matrix, interpolated entry by
+//     entry. Reflections live in the stretch, so they play out as the image flattening
+//     and unfolding again.
+//
+// Projective keyframes have no such decomposition and fall back to interpolating the
+// entries of their matrices.
+
+/// How the progress through a segment of an animation speeds up and slows down.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Easing {
+    /// Constant speed.
+    #[default]
+    Linear,
+    /// Starts slowly and speeds up.
+    EaseIn,
+    /// Starts quickly and slows down to a stop.
+    EaseOut,
+    /// Starts slowly, speeds up and slows down to a stop.
+    EaseInOut
+}
+
+impl Easing {
+    /// Maps the fraction of time `t` elapsed in a segment to the fraction of the way from
+    /// one keyframe to the next, both from 0 to 1.
+    pub fn ease(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => if t < 0.5 { 4.0 * t * t * t } else { 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0 }
+        }
+    }
+}
+
+/// A transform the animation passes through, along with the easing of the segment which
+/// arrives at it. The easing of the first keyframe is unused.
+#[derive(Clone, Debug)]
+pub struct Keyframe { pub transform: Transform2D, pub easing: Easing }
+
+pub struct Animation {
+    keyframes: Vec<Keyframe>,
+    /// The point whose path between keyframes is a straight line.
+    pivot: (f64, f64),
+    /// The number of frames from one keyframe to the next, counting both.
+    segment_frames: usize
+}
+
+impl Animation {
+    /// An animation through `keyframes` in which each pair of neighbouring keyframes is
+    /// `segment_frames` frames apart, counting both. Panics if there are no keyframes or
+    /// `segment_frames` is less than 2.
+    pub fn new(keyframes: Vec<Keyframe>, pivot: (f64, f64), segment_frames: usize) -> Self {
+        assert!(!keyframes.is_empty(), "an animation needs at least one keyframe");
+        assert!(segment_frames >= 2, "a segment needs at least its two keyframes");
+        Self { keyframes, pivot, segment_frames }
+    }
+
+    /// An animation of a single frame showing `transform`.
+    pub fn still(transform: Transform2D) -> Self {
+        Self::new(vec![Keyframe { transform, easing: Easing::Linear }], (0.0, 0.0), 2)
+    }
+
+    /// The number of frames, in which every keyframe between two segments appears once.
+    pub fn frame_count(&self) -> usize {
+        (self.keyframes.len() - 1) * (self.segment_frames - 1) + 1
+    }
+
+    /// The transform of frame `i`. Frames past the end hold the last keyframe.
+    pub fn frame(&self, i: usize) -> Transform2D {
+        let steps = self.segment_frames - 1;
+        let segment = i / steps;
+        if segment + 1 >= self.keyframes.len() {
+            return self.keyframes[self.keyframes.len() - 1].transform.clone();
+        }
+        let (from, to) = (&self.keyframes[segment], &self.keyframes[segment + 1]);
+        let t = to.easing.ease((i % steps) as f64 / steps as f64);
+        interpolate(&from.transform, &to.transform, t, self.pivot)
+    }
+}
+
+/// The transform a fraction `t` of the way from `a` to `b`, where `pivot` moves in a
+/// straight line. `t` may lie outside 0 to 1 to overshoot either end.
+pub fn interpolate(a: &Transform2D, b: &Transform2D, t: f64, pivot: (f64, f64)) -> Transform2D {
+    let (ma, mb) = (a.matrix(), b.matrix());
+    let lerp = |x: f64, y: f64| x + t * (y - x);
+    if !a.is_affine() || !b.is_affine() {
+        let (wa, wb) = (normalizer(ma), normalizer(mb));
+        return Transform2D::from_rows(std::array::from_fn(|r| {
+            std::array::from_fn(|c| lerp(ma[(r, c)] / wa, mb[(r, c)] / wb))
+        }));
+    }
+
+    let (pa, pb) = (linear_part(ma), linear_part(mb));
+    let (Polar { rotation: ra, stretch: sa }, Polar { rotation: rb, stretch: sb }) = (polar(&pa), polar(&pb));
+    let (theta_a, theta_b) = (angle(&ra), angle(&rb));
+    // The difference of the angles wrapped into -180 to 180 degrees, so as to turn the
+    // short way around.
+    let turn = (theta_b - theta_a + std::f64::consts::PI).rem_euclid(std::f64::consts::TAU) - std::f64::consts::PI;
+    let (sin, cos) = (theta_a + t * turn).sin_cos();
+    let stretch: [[f64; 2]; 2] = std::array::from_fn(|r| std::array::from_fn(|c| lerp(sa[(r, c)], sb[(r, c)])));
+    let linear = Transform2D::from_rows([
+        [cos * stretch[0][0] - sin * stretch[1][0], cos * stretch[0][1] - sin * stretch[1][1], 0.0],
+        [sin * stretch[0][0] + cos * stretch[1][0], sin * stretch[0][1] + cos * stretch[1][1], 0.0],
+        [0.0, 0.0, 1.0]
+    ]);
+
+    // Where each keyframe sends the pivot, relative to the pivot itself.
+    let (x, y) = pivot;
+    let shift = |m: &Matrix<f64>| {
+        (m[(0, 0)] * x + m[(0, 1)] * y + m[(0, 2)] - x, m[(1, 0)] * x + m[(1, 1)] * y + m[(1, 2)] - y)
+    };
+    let ((dxa, dya), (dxb, dyb)) = (shift(ma), shift(mb));
+    Transform2D::translate(-x, -y)
+        .then(linear)
+        .then(Transform2D::translate(x + lerp(dxa, dxb), y + lerp(dya, dyb)))
+}
+
+/// The number to divide a projective matrix by so that its bottom right entry is 1, as
+/// any nonzero multiple of it describes the same transform.
+fn normalizer(m: &Matrix<f64>) -> f64 {
+    if m[(2, 2)] != 0.0 { m[(2, 2)] } else { 1.0 }
+}
+
+fn linear_part(m: &Matrix<f64>) -> Matrix<f64> {
+    Matrix::literal([[m[(0, 0)], m[(0, 1)]], [m[(1, 0)], m[(1, 1)]]])
+}
+
+/// The angle in radians of the 2x2 rotation matrix `r`.
+fn angle(r: &Matrix<f64>) -> f64 {
+    r[(1, 0)].atan2(r[(0, 0)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn keyframe(transform: Transform2D) -> Keyframe {
+        Keyframe { transform, easing: Easing::Linear }
+    }
+
+    fn apply_to((x, y): (f64, f64), t: &Transform2D) -> (f64, f64) {
+        let m = t.matrix();
+        let w = m[(2, 0)] * x + m[(2, 1)] * y + m[(2, 2)];
+        ((m[(0, 0)] * x + m[(0, 1)] * y + m[(0, 2)]) / w, (m[(1, 0)] * x + m[(1, 1)] * y + m[(1, 2)]) / w)
+    }
+
+    fn assert_close(a: &Transform2D, b: &Transform2D) {
+        for r in 0..3 {
+            for c in 0..3 {
+                assert!((a.matrix()[(r, c)] - b.matrix()[(r, c)]).abs() < 1e-9, "expected\n{}got\n{}", b.matrix(), a.matrix());
+            }
+        }
+    }
+
+    #[test]
+    fn half_turn_passes_through_a_quarter_turn() {
+        let pivot = (40.0, 30.0);
+        let animation = Animation::new(vec![keyframe(Transform2D::identity()), keyframe(Transform2D::rotate_about(pivot, PI))], pivot, 3);
+        let middle = animation.frame(1);
+        let m = middle.matrix();
+        // Either way around is a quarter turn, neither shrunk nor sheared.
+        assert!(m[(0, 0)].abs() < 1e-12 && m[(1, 1)].abs() < 1e-12, "{m}");
+        assert!((m[(1, 0)].abs() - 1.0).abs() < 1e-12 && (m[(0, 1)] + m[(1, 0)]).abs() < 1e-12, "{m}");
+        let (x, y) = apply_to(pivot, &middle);
+        assert!((x - pivot.0).abs() < 1e-9 && (y - pivot.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pivot_moves_in_a_straight_line() {
+        let pivot = (5.0, 7.0);
+        let end = Transform2D::rotate(0.7).then(Transform2D::scale(2.0, 0.5)).then(Transform2D::translate(30.0, -10.0));
+        let (ex, ey) = apply_to(pivot, &end);
+        let animation = Animation::new(vec![keyframe(Transform2D::identity()), keyframe(end)], pivot, 5);
+        for i in 0..5 {
+            let t = i as f64 / 4.0;
+            let (x, y) = apply_to(pivot, &animation.frame(i));
+            assert!((x - (pivot.0 + t * (ex - pivot.0))).abs() < 1e-9, "frame {i}");
+            assert!((y - (pivot.1 + t * (ey - pivot.1))).abs() < 1e-9, "frame {i}");
+        }
+    }
+
+    #[test]
+    fn keyframes_fall_on_frames() {
+        let keyframes = [
+            Transform2D::translate(3.0, 4.0),
+            Transform2D::rotate(0.5).then(Transform2D::translate(-8.0, 2.0)),
+            Transform2D::scale(2.0, 0.5).then(Transform2D::shear_x(0.3))
+        ];
+        let animation = Animation::new(keyframes.iter().cloned().map(keyframe).collect(), (1.0, 2.0), 4);
+        assert_eq!(animation.frame_count(), 7);
+        for (i, k) in keyframes.iter().enumerate() {
+            assert_close(&animation.frame(3 * i), k);
+        }
+        assert_close(&animation.frame(10), &keyframes[2]);
+        assert_eq!(Animation::still(Transform2D::identity()).frame_count(), 1);
+    }
+
+    #[test]
+    fn easings_start_and_stop_on_the_keyframes() {
+        for easing in [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut] {
+            assert_eq!(easing.ease(0.0), 0.0, "{easing:?}");
+            assert_eq!(easing.ease(1.0), 1.0, "{easing:?}");
+        }
+        assert_eq!(Easing::EaseInOut.ease(0.5), 0.5);
+        assert!(Easing::EaseIn.ease(0.25) < 0.25 && Easing::EaseOut.ease(0.25) > 0.25);
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+use std::time::Instant;
+use crate::matrix::Matrix;
+use crate::matrix::matmul;
+use crate::matrix::matmul_naive;
+use crate::matrix::matmul_threaded;
+
+// Benchmarks
+// `--benchmark` times `matmul` and `matmul_threaded` against `matmul_naive`, first on
+// the shapes this program multiplies when transforming an image and then on square
+// matrices. Every product is checked against `matmul_naive` before it is reported.
+
+struct Case {
+    name: &'static str,
+    rowc: usize,
+    inner: usize,
+    colc: usize,
+    /// How many times to run each kernel. The fastest run is reported.
+    repeats: usize
+}
+
+const CASES: [Case; 5] = [
+    Case { name: "3x3 * 3x1000000 (locmat)", rowc: 3, inner: 3, colc: 1_000_000, repeats: 5 },
+    Case { name: "5x5 * 5x1000000 (colmat)", rowc: 5, inner: 5, colc: 1_000_000, repeats: 5 },
+    Case { name: "64x64 * 64x64", rowc: 64, inner: 64, colc: 64, repeats: 50 },
+    Case { name: "256x256 * 256x256", rowc: 256, inner: 256, colc: 256, repeats: 3 },
+    Case { name: "512x512 * 512x512", rowc: 512, inner: 512, colc: 512, repeats: 1 }
+];
+
+/// Runs every benchmark case and prints a table of timings to stdout. `threads` is
+/// passed to `matmul_threaded`.
+pub fn run_benchmarks(threads: usize) {
+    if cfg!(debug_assertions) {
+        println!("note: built without optimizations, use `cargo run --release -- --benchmark`");
+    }
+    println!("{:<26} {:>12} {:>20} {:>20}", "product", "naive", "matmul",
+        format!("threaded ({})", threads));
+
+    let mut seed = 1;
+    for case in &CASES {
+        let left = sample_matrix(case.rowc, case.inner, &mut seed);
+        let right = sample_matrix(case.inner, case.colc, &mut seed);
+
+        let mut expected: Matrix<f64> = Matrix::new(case.rowc, case.colc);
+        let naive = time(case.repeats, || matmul_naive(&left, &right, &mut expected));
+
+        let mut output: Matrix<f64> = Matrix::new(case.rowc, case.colc);
+        let serial = time(case.repeats, || matmul(&left, &right, &mut output));
+        assert!(output == expected, "matmul disagrees with matmul_naive on {}", case.name);
+
+        let mut output: Matrix<f64> = Matrix::new(case.rowc, case.colc);
+        let threaded = time(case.repeats, || matmul_threaded(&left, &right, &mut output, threads));
+        assert!(output == expected, "matmul_threaded disagrees with matmul_naive on {}", case.name);
+
+        println!("{:<26} {:>12} {:>20} {:>20}", case.name, millis(naive),
+            format!("{} ({:.1}x)", millis(serial), speedup(naive, serial)),
+            format!("{} ({:.1}x)", millis(threaded), speedup(naive, threaded)));
+    }
+}
+
+/// The fastest of `repeats` runs of `f`.
+fn time(repeats: usize, mut f: impl FnMut()) -> Duration {
+    (0..repeats)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn millis(d: Duration) -> String {
+    format!("{:.2} ms", d.as_secs_f64() * 1000.0)
+}
+
+fn speedup(baseline: Duration, d: Duration) -> f64 {
+    baseline.as_secs_f64() / d.as_secs_f64().max(f64::EPSILON)
+}
+
+/// A matrix of values in [-1, 1) from a linear congruential generator, so that every
+/// run multiplies the same matrices.
+fn sample_matrix(rowc: usize, colc: usize, seed: &mut u64) -> Matrix<f64> {
+    let mut m: Matrix<f64> = Matrix::new(rowc, colc);
+    for col in 0..colc {
+        for value in m.get_col_mut(col) {
+            *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            *value = (*seed >> 11) as f64 / (1u64 << 52) as f64 - 1.0;
+        }
+    }
+    m
+}
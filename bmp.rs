@@ -2,51 +2,401 @@ use crate::error::AnyError;
 use crate::raster::Raster;
 use crate::raster::Pixel;
 
+/// The reasons a BMP file can be rejected by [`read_bmp`].
+#[derive(Debug)]
+pub enum BmpError {
+    /// The file does not begin with the two bytes `BM`.
+    BadSignature([u8; 2]),
+    /// The image header is not one of the CORE, INFO, V2, V3, V4 or V5 headers.
+    UnsupportedHeader(u32),
+    /// The color planes field must always be 1.
+    UnsupportedPlanes(u16),
+    UnsupportedBitsPerPixel(u16),
+    /// The compression type is unknown, or does not make sense for the bits per pixel.
+    UnsupportedCompression(u32),
+    /// The width is negative, or either dimension is zero.
+    InvalidDimensions { width: i32, height: i32 },
+    /// A palettized pixel refers to a color which is not in the palette.
+    BadPaletteIndex(u8),
+    /// The image is too large to be held in memory on this platform.
+    DimensionOverflow,
+    /// A compressed image claims more than [`MAX_RLE_PIXELS`] pixels.
+    TooLarge { width: usize, height: usize },
+    /// The file ended before all of the pixel data could be read.
+    Truncated
+}
+
+impl std::fmt::Display for BmpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BmpError::BadSignature(sig) =>
+                write!(f, "not a BMP file (signature {:02x} {:02x}, expected \"BM\")", sig[0], sig[1]),
+            BmpError::UnsupportedHeader(size) =>
+                write!(f, "unsupported image header of {} bytes", size),
+            BmpError::UnsupportedPlanes(n) =>
+                write!(f, "unsupported number of color planes: {}", n),
+            BmpError::UnsupportedBitsPerPixel(bpp) =>
+                write!(f, "unsupported bits per pixel: {}", bpp),
+            BmpError::UnsupportedCompression(c) =>
+                write!(f, "unsupported compression type: {}", c),
+            BmpError::InvalidDimensions { width, height } =>
+                write!(f, "invalid image dimensions: {}x{}", width, height),
+            BmpError::BadPaletteIndex(i) =>
+                write!(f, "palette index {} is out of range", i),
+            BmpError::DimensionOverflow =>
+                write!(f, "image dimensions too large for this platform"),
+            BmpError::TooLarge { width, height } =>
+                write!(f, "compressed image of {}x{} pixels exceeds the limit of {} pixels",
+                    width, height, MAX_RLE_PIXELS),
+            BmpError::Truncated =>
+                write!(f, "file ends before the end of the pixel data")
+        }
+    }
+}
+
+impl std::error::Error for BmpError {}
+
+// Sizes of the known image headers. Each is a strict extension of the one before it,
+// except for the CORE header which uses 16-bit dimensions.
+const CORE_HEADER: u32 = 12;
+const INFO_HEADER: u32 = 40;
+const V2_HEADER: u32 = 52;
+const V3_HEADER: u32 = 56;
+const V4_HEADER: u32 = 108;
+const V5_HEADER: u32 = 124;
+
+// Compression types
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// The most pixels a compressed image may have. Escapes let a few bytes of RLE data skip
+/// over whole rows, so unlike uncompressed images the size of the file puts no bound on
+/// the size of the image.
+pub const MAX_RLE_PIXELS: usize = 1 << 26;
+
 // routines for reading BMP files
 
 pub fn read_bmp<R>(input: &mut R) -> Result<Raster, AnyError>
 where R: std::io::Read + std::io::Seek
+{
+    // Running off the end of the file is a property of the file, not of the
+    // reader, so report it as such.
+    read_bmp_unchecked(input).map_err(|e| match e {
+        AnyError::IO(e) if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            AnyError::from(BmpError::Truncated),
+        other => other
+    })
+}
+
+fn read_bmp_unchecked<R>(input: &mut R) -> Result<Raster, AnyError>
+where R: std::io::Read + std::io::Seek
 {
     // read the file header
-    assert_eq!(read_text(input, 2)?, "BM");
-    let img_size = read_u32_le(input)?;
-    assert_eq!(read_u16_le(input)?, 0);
-    assert_eq!(read_u16_le(input)?, 0);
-    let offset = read_u32_le(input)?;  
-
-    // read the image header
-    let img_head_size = read_u32_le(input)?;
-    let width = usize::try_from(read_u32_le(input)?)
-        .expect("width too large to fit into usize on this platform");
-    let (porder, height) = read_height_field(input)?;
-    assert_eq!(read_u16_le(input)?, 1);
-    let bpp /* bits per pixel */ = read_u16_le(input)?;
-    assert_eq!(bpp, 24, "only 24 bits per pixel is supported");
-    let compression_t = read_u32_le(input)?;
-    assert_eq!(compression_t, 0, "only uncompressed images supported");
+    let mut signature: [u8; 2] = [0; 2];
+    input.read_exact(&mut signature)?;
+    if &signature != b"BM" { return Err(BmpError::BadSignature(signature).into()); }
+    let _img_size = read_u32_le(input)?;
+    // The two reserved fields are application specific, their values are irrelevant.
+    let _reserved1 = read_u16_le(input)?;
+    let _reserved2 = read_u16_le(input)?;
+    let offset = read_u32_le(input)?;
+
+    let info = read_image_header(input)?;
 
     input.seek(std::io::SeekFrom::Start(u64::from(offset)))?;
+    let mut data: Vec<u8> = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    // Check the claimed dimensions against the data before allocating any memory for them.
+    let size = info.height.checked_mul(info.width).ok_or(BmpError::DimensionOverflow)?;
+    match info.compression {
+        BI_RLE8 | BI_RLE4 => if size > MAX_RLE_PIXELS {
+            return Err(BmpError::TooLarge { width: info.width, height: info.height }.into());
+        },
+        _ => if data.len() < uncompressed_len(&info).ok_or(BmpError::DimensionOverflow)? {
+            return Err(BmpError::Truncated.into());
+        }
+    }
+    let mut pixels: Vec<Pixel> = Vec::new();
+    pixels.try_reserve_exact(size).map_err(|_| BmpError::DimensionOverflow)?;
+    pixels.resize(size, Pixel::transparent());
+
+    match info.compression {
+        BI_RLE8 | BI_RLE4 => decode_rle(&data, &info, &mut pixels)?,
+        _ => decode_uncompressed(&data, &info, &mut pixels)?
+    }
 
-    let size = height * width;    
-    let mut pixels: Vec<Pixel> = Vec::with_capacity(size);
-    
-    assert!(porder == PixelOrder::Normal, "only normal pixel order is supported");
-    for _ in 0..size { pixels.push(read_color(input)?); }
-    
-    Ok(Raster { pixels, width })
+    Ok(Raster { pixels, width: info.width })
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum PixelOrder { Normal, Strange }
+/// The order in which rows of pixels are stored in a BMP file. The sign of the
+/// height field in the image header decides which is used.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RowOrder {
+    /// The first row in the file is the top row of the image (negative height).
+    TopDown,
+    /// The first row in the file is the bottom row of the image (positive height).
+    /// Nearly every BMP file is stored this way.
+    BottomUp
+}
 
-fn read_height_field<R>(input: &mut R) -> std::io::Result<(PixelOrder, usize)>
-where R: std::io::Read
+/// Bit masks selecting each channel from a 16-bit or 32-bit pixel. An `alpha`
+/// mask of 0 means the image has no alpha channel.
+struct ChannelMasks { red: u32, green: u32, blue: u32, alpha: u32 }
+
+/// Everything from the image header and color table needed to decode the pixel data.
+struct ImageInfo {
+    width: usize,
+    height: usize,
+    order: RowOrder,
+    bpp: u16,
+    compression: u32,
+    masks: ChannelMasks,
+    palette: Vec<Pixel>
+}
+
+fn read_image_header<R>(input: &mut R) -> Result<ImageInfo, AnyError>
+where R: std::io::Read + std::io::Seek
 {
-    let value = read_i32_le(input)?;
-    let order = if value > 0 { PixelOrder::Strange } else { PixelOrder::Normal };
+    let head_size = read_u32_le(input)?;
+    let (width_field, height_field) = match head_size {
+        CORE_HEADER => (i32::from(read_u16_le(input)?), i32::from(read_u16_le(input)?)),
+        INFO_HEADER | V2_HEADER | V3_HEADER | V4_HEADER | V5_HEADER =>
+            (read_i32_le(input)?, read_i32_le(input)?),
+        other => return Err(BmpError::UnsupportedHeader(other).into())
+    };
+    if width_field <= 0 || height_field == 0 {
+        return Err(BmpError::InvalidDimensions { width: width_field, height: height_field }.into());
+    }
+    let width = usize::try_from(width_field).map_err(|_| BmpError::DimensionOverflow)?;
+    let (order, height) = read_height_field(height_field)?;
+    let planes = read_u16_le(input)?;
+    if planes != 1 { return Err(BmpError::UnsupportedPlanes(planes).into()); }
+    let bpp /* bits per pixel */ = read_u16_le(input)?;
+
+    let mut compression = BI_RGB;
+    let mut colors_used = 0;
+    let mut masks = default_masks(bpp);
+    if head_size >= INFO_HEADER {
+        compression = read_u32_le(input)?;
+        let _compressed_size = read_u32_le(input)?;
+        let _ppm_x = read_i32_le(input)?;
+        let _ppm_y = read_i32_le(input)?;
+        colors_used = read_u32_le(input)?;
+        let _colors_important = read_u32_le(input)?;
+    }
+
+    match (compression, bpp) {
+        (BI_RGB, 1 | 4 | 8 | 16 | 24 | 32) => {},
+        (BI_RLE8, 8) | (BI_RLE4, 4) | (BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) => {},
+        (BI_RGB, _) => return Err(BmpError::UnsupportedBitsPerPixel(bpp).into()),
+        _ => return Err(BmpError::UnsupportedCompression(compression).into())
+    }
+
+    // The V2 header and above carry the channel masks inside of the header. The
+    // INFO header instead follows itself with the masks when they are needed.
+    let has_masks = compression == BI_BITFIELDS || compression == BI_ALPHABITFIELDS;
+    if head_size >= V2_HEADER || (head_size == INFO_HEADER && has_masks) {
+        let red = read_u32_le(input)?;
+        let green = read_u32_le(input)?;
+        let blue = read_u32_le(input)?;
+        if has_masks { masks = ChannelMasks { red, green, blue, alpha: 0 }; }
+    }
+    if head_size >= V3_HEADER || (head_size == INFO_HEADER && compression == BI_ALPHABITFIELDS) {
+        let alpha = read_u32_le(input)?;
+        if has_masks { masks.alpha = alpha; }
+    }
+    if head_size > V3_HEADER {
+        // The remainder of the V4 and V5 headers describes color spaces, which are ignored.
+        input.seek_relative(i64::from(head_size - V3_HEADER))?;
+    }
+
+    // The color table follows the headers. Entries are BGR triples for the CORE
+    // header, and BGR plus a reserved byte for all the others.
+    let mut palette: Vec<Pixel> = Vec::new();
+    if bpp <= 8 {
+        let max_colors = 1usize << bpp;
+        let count = match usize::try_from(colors_used) {
+            Ok(0) => max_colors,
+            Ok(n) => n.min(max_colors),
+            Err(_) => max_colors
+        };
+        let entry_len = if head_size == CORE_HEADER { 3 } else { 4 };
+        let mut entry: [u8; 4] = [0; 4];
+        for _ in 0..count {
+            input.read_exact(&mut entry[0..entry_len])?;
+            palette.push(read_color(&entry));
+        }
+    }
+
+    Ok(ImageInfo { width, height, order, bpp, compression, masks, palette })
+}
+
+/// The masks implied for 16-bit and 32-bit images when they are uncompressed.
+/// The high byte of an uncompressed 32-bit pixel is unused, not alpha.
+fn default_masks(bpp: u16) -> ChannelMasks {
+    match bpp {
+        16 => ChannelMasks { red: 0x7C00, green: 0x03E0, blue: 0x001F, alpha: 0 },
+        _ => ChannelMasks { red: 0x00FF0000, green: 0x0000FF00, blue: 0x000000FF, alpha: 0 }
+    }
+}
+
+/// Extracts the channel selected by `mask` from `value` and rescales it to fill 8 bits.
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 { return 0; }
+    let shift = mask.trailing_zeros();
+    let max = u64::from(mask >> shift);
+    let v = u64::from((value & mask) >> shift);
+    ((v * 255 + max / 2) / max) as u8
+}
+
+/// Computes the number of bytes occupied by one row of pixels including padding.
+/// Rows are always padded to a multiple of 4 bytes.
+fn stride(width: usize, bpp: u16) -> usize {
+    (width * usize::from(bpp)).div_ceil(32) * 4
+}
+
+/// Computes the number of bytes of uncompressed pixel data needed for the image described
+/// by `info`. Padding is not required after the last row, see [`decode_uncompressed`].
+fn uncompressed_len(info: &ImageInfo) -> Option<usize> {
+    let row_len = info.width.checked_mul(usize::from(info.bpp))?.div_ceil(8);
+    let row_stride = row_len.div_ceil(4) * 4;
+    row_stride.checked_mul(info.height - 1)?.checked_add(row_len)
+}
+
+/// Maps the `r`th row stored in the file to its row in the image.
+fn image_row(info: &ImageInfo, r: usize) -> usize {
+    match info.order {
+        RowOrder::TopDown => r,
+        RowOrder::BottomUp => info.height - 1 - r
+    }
+}
+
+fn palette_color(info: &ImageInfo, index: u8) -> Result<Pixel, BmpError> {
+    info.palette.get(usize::from(index)).copied().ok_or(BmpError::BadPaletteIndex(index))
+}
+
+fn decode_uncompressed(data: &[u8], info: &ImageInfo, pixels: &mut [Pixel]) -> Result<(), BmpError> {
+    let row_stride = stride(info.width, info.bpp);
+    // Some encoders omit the padding after the last row, so only the meaningful
+    // bytes of each row are required to be present.
+    let row_len = (info.width * usize::from(info.bpp)).div_ceil(8);
+
+    for r in 0..info.height {
+        let begin = r * row_stride;
+        let row = data.get(begin..(begin + row_len)).ok_or(BmpError::Truncated)?;
+        let y = image_row(info, r);
+        let out = &mut pixels[(y * info.width)..((y + 1) * info.width)];
+
+        match info.bpp {
+            1 | 4 | 8 => {
+                let bpp = usize::from(info.bpp);
+                let index_mask = ((1u16 << bpp) - 1) as u8;
+                for (x, px) in out.iter_mut().enumerate() {
+                    // The leftmost pixel occupies the most significant bits of each byte.
+                    let bit = x * bpp;
+                    let index = (row[bit / 8] >> (8 - bpp - bit % 8)) & index_mask;
+                    *px = palette_color(info, index)?;
+                }
+            },
+            16 => for (px, bytes) in out.iter_mut().zip(row.chunks_exact(2)) {
+                let value = u32::from(u16::from_le_bytes([bytes[0], bytes[1]]));
+                *px = masked_color(value, &info.masks);
+            },
+            24 => for (px, bgr) in out.iter_mut().zip(row.chunks_exact(3)) {
+                *px = read_color(bgr);
+            },
+            _ => for (px, bytes) in out.iter_mut().zip(row.chunks_exact(4)) {
+                let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                *px = masked_color(value, &info.masks);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn masked_color(value: u32, masks: &ChannelMasks) -> Pixel {
+    Pixel {
+        red: extract_channel(value, masks.red),
+        green: extract_channel(value, masks.green),
+        blue: extract_channel(value, masks.blue),
+        alpha: if masks.alpha == 0 { u8::MAX } else { extract_channel(value, masks.alpha) }
+    }
+}
+
+/// Decodes RLE8 and RLE4 compressed pixel data. Pixels skipped over by a delta or
+/// end-of-line escape are left as they were in `pixels`, which is transparent.
+/// The format leaves their color undefined.
+fn decode_rle(data: &[u8], info: &ImageInfo, pixels: &mut [Pixel]) -> Result<(), BmpError> {
+    let nibbles = info.compression == BI_RLE4;
+    let mut x: usize = 0;
+    let mut r: usize = 0; // the row within the file, not the image
+    let mut i: usize = 0;
+
+    // Runs which stray outside of the image are clipped rather than rejected.
+    let mut put = |x: usize, r: usize, index: u8| -> Result<(), BmpError> {
+        if x < info.width && r < info.height {
+            let y = image_row(info, r);
+            pixels[y * info.width + x] = palette_color(info, index)?;
+        }
+        Ok(())
+    };
+
+    // Encoders are supposed to finish with an end-of-bitmap escape, but
+    // running out of data is treated the same way.
+    while i + 1 < data.len() {
+        let (count, value) = (usize::from(data[i]), data[i + 1]);
+        i += 2;
+
+        if count > 0 {
+            // Encoded mode: `value` is repeated `count` times. In RLE4 `value`
+            // holds two indices which alternate.
+            for k in 0..count {
+                let index = if !nibbles { value }
+                    else if k % 2 == 0 { value >> 4 }
+                    else { value & 0x0F };
+                put(x, r, index)?;
+                x += 1;
+            }
+            continue;
+        }
+
+        match value {
+            0 /* end of line */ => { x = 0; r += 1; },
+            1 /* end of bitmap */ => break,
+            2 /* delta */ => {
+                let delta = data.get(i..(i + 2)).ok_or(BmpError::Truncated)?;
+                x += usize::from(delta[0]);
+                r += usize::from(delta[1]);
+                i += 2;
+            },
+            n /* absolute mode */ => {
+                let n = usize::from(n);
+                let len = if nibbles { n.div_ceil(2) } else { n };
+                let run = data.get(i..(i + len)).ok_or(BmpError::Truncated)?;
+                for k in 0..n {
+                    let index = if !nibbles { run[k] }
+                        else if k % 2 == 0 { run[k / 2] >> 4 }
+                        else { run[k / 2] & 0x0F };
+                    put(x, r, index)?;
+                    x += 1;
+                }
+                // Absolute runs are padded to a 16-bit boundary.
+                i += len + len % 2;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_height_field(value: i32) -> Result<(RowOrder, usize), BmpError> {
+    let order = if value > 0 { RowOrder::BottomUp } else { RowOrder::TopDown };
     let magnitude = usize::try_from(value.unsigned_abs())
-        .expect("height too large to fit into usize on this platform");
-    return Ok((order, magnitude));
+        .map_err(|_| BmpError::DimensionOverflow)?;
+    Ok((order, magnitude))
 }
 
 
@@ -71,77 +421,114 @@ where R: std::io::Read
 {
     let mut buf: [u8; 2] = [0; 2];
     input.read_exact(&mut buf)?;
-    Ok(u16::from_le_bytes(buf))    
-}
-
-
-fn read_i16_le<R>(input: &mut R) -> std::io::Result<i16>
-where R: std::io::Read
-{
-    let mut buf: [u8; 2] = [0; 2];
-    input.read_exact(&mut buf)?;
-    Ok(i16::from_le_bytes(buf))    
-}
-
-fn read_text<R>(input: &mut R, len: usize) -> Result<String, AnyError>
-where R: std::io::Read
-{
-    let mut s = vec![0u8; len];   
-    input.read_exact(&mut s[0..len])?;
-    Ok(String::from_utf8(s)?)
+    Ok(u16::from_le_bytes(buf))
 }
 
-fn read_color<R>(input: &mut R) -> std::io::Result<Pixel> 
-where R: std::io::Read
-{
-    let mut buf: [u8; 3] = [0; 3];
-    input.read_exact(&mut buf)?;
-    Ok(Pixel { red: buf[0], green: buf[1], blue: buf[2] })
+/// Decodes a pixel from the three bytes `bgr`. Note that BMP stores the
+/// channels in reverse, blue first and red last.
+fn read_color(bgr: &[u8]) -> Pixel {
+    Pixel::rgb(bgr[2], bgr[1], bgr[0])
 }
 
 // Routines for writing BMP files
 // Useful for verifying that the reading routines above
 // work properly.
 
-pub fn write_bmp<W>(output: &mut W, img: &Raster) -> std::io::Result<()>
-where W: std::io::Write 
+/// The pixel formats [`write_bmp_with`] can produce.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BmpDepth {
+    /// 24 bits per pixel, no alpha channel, INFO header.
+    Bgr24,
+    /// 32 bits per pixel with an alpha channel, described by bitfields in a V4 header.
+    Bgra32
+}
+
+/// When `depth` is `None` the depth is chosen based on the image, see [`write_bmp_with`].
+#[derive(Clone, Copy, Debug)]
+pub struct BmpWriteOptions { pub order: RowOrder, pub depth: Option<BmpDepth> }
+
+impl Default for BmpWriteOptions {
+    fn default() -> Self {
+        Self { order: RowOrder::BottomUp, depth: None }
+    }
+}
+
+/// Encodes `img` as an uncompressed BMP file laid out according to `opts`. The default
+/// options store rows bottom-up, which is the layout understood by the widest range of
+/// programs, and pick 24 bits per pixel for opaque images and 32 for any others.
+pub fn write_bmp_with<W>(output: &mut W, img: &Raster, opts: BmpWriteOptions) -> std::io::Result<()>
+where W: std::io::Write
 {
+    let depth = opts.depth.unwrap_or(if img.is_opaque() { BmpDepth::Bgr24 } else { BmpDepth::Bgra32 });
+    let (head_size, bpp, compression) = match depth {
+        BmpDepth::Bgr24 => (INFO_HEADER, 24, BI_RGB),
+        BmpDepth::Bgra32 => (V4_HEADER, 32, BI_BITFIELDS)
+    };
+
     // write file header
     output.write_all(&[
         std::ascii::Char::CapitalB.to_u8(),
         std::ascii::Char::CapitalM.to_u8()
     ])?;
 
-    let pxbcount = u32::try_from(img.pixels.len() * 3)
+    let row_stride = stride(img.width(), bpp);
+    let bytes_pp = usize::from(bpp / 8);
+    let padding = row_stride - img.width() * bytes_pp;
+    let pxbcount = u32::try_from(row_stride * img.height())
         .expect("image size too large to be measured in u32");
-    
-    let fsize: u32 = 14 /* file header */ + 40 /* img header */ 
-        + pxbcount;
-    write_u32_le(output, fsize)?;
+
+    let offset: u32 = 14 /* file header */ + head_size;
+    write_u32_le(output, offset + pxbcount)?;
 
     write_u32_le(output, 0)?;
-    write_u32_le(output, 54)?;
+    write_u32_le(output, offset)?;
 
     // write image header
-    write_u32_le(output, 40)?;
+    write_u32_le(output, head_size)?;
     write_u32_le(output, u32::try_from(img.width())
         .expect("width too large to be measured in u32"))?;
-    write_i32_le(output, -1 * i32::try_from(img.height())
-        .expect("image to tall to be measured in i32"))?;
+    let height = i32::try_from(img.height())
+        .expect("image to tall to be measured in i32");
+    write_i32_le(output, match opts.order {
+        RowOrder::TopDown => -height,
+        RowOrder::BottomUp => height
+    })?;
     write_i16_le(output, 1)?;
-    write_i16_le(output, 24)?;
-    write_u32_le(output, 0)?; // compression type
-    write_u32_le(output, 0)?; // compression size
+    write_i16_le(output, bpp as i16)?;
+    write_u32_le(output, compression)?; // compression type
+    write_u32_le(output, pxbcount)?; // compression size
     write_u32_le(output, 0)?; // prefer pixels per meter x
     write_u32_le(output, 0)?; // prefer pixels per meter y
     write_u32_le(output, 0)?; // color map size
     write_u32_le(output, 0)?; // significant colors
 
-    for color in &img.pixels {
-        write_color(output, *color)?;
+    if head_size == V4_HEADER {
+        write_u32_le(output, 0x00FF0000)?; // red mask
+        write_u32_le(output, 0x0000FF00)?; // green mask
+        write_u32_le(output, 0x000000FF)?; // blue mask
+        write_u32_le(output, 0xFF000000)?; // alpha mask
+        output.write_all(b"BGRs")?; // color space, "sRGB" as a little-endian u32
+        output.write_all(&[0; 36])?; // endpoints, unused for sRGB
+        output.write_all(&[0; 12])?; // gamma, unused for sRGB
     }
-    
-    Ok(())    
+
+    let mut row: Vec<u8> = Vec::with_capacity(row_stride);
+    for r in 0..img.height() {
+        let y = match opts.order {
+            RowOrder::TopDown => r,
+            RowOrder::BottomUp => img.height() - 1 - r
+        };
+        row.clear();
+        for x in 0..img.width() {
+            let px = img.get_pixel(x, y);
+            write_color(&mut row, px);
+            if depth == BmpDepth::Bgra32 { row.push(px.alpha); }
+        }
+        row.resize(row.len() + padding, 0);
+        output.write_all(&row)?;
+    }
+
+    Ok(())
 }
 
 fn write_i32_le<W>(output: &mut W, value: i32) -> std::io::Result<()>
@@ -158,13 +545,6 @@ where W: std::io::Write
     output.write_all(&buf)
 }
 
-fn write_u16_le<W>(output: &mut W, value: u16) -> std::io::Result<()>
-where W: std::io::Write
-{
-    let buf = value.to_le_bytes();
-    output.write_all(&buf)
-}
-
 
 fn write_i16_le<W>(output: &mut W, value: i16) -> std::io::Result<()>
 where W: std::io::Write
@@ -173,9 +553,240 @@ where W: std::io::Write
     output.write_all(&buf)
 }
 
-fn write_color<W>(output: &mut W, value: Pixel) -> std::io::Result<()> 
-where W: std::io::Write
-{
-    let mut buf: [u8; 3] = [value.red, value.green, value.blue];
-    output.write_all(&buf)
+fn write_color(output: &mut Vec<u8>, value: Pixel) {
+    output.extend_from_slice(&[value.blue, value.green, value.red]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Result<Raster, AnyError> {
+        read_bmp(&mut std::io::Cursor::new(bytes))
+    }
+
+    fn encode(img: &Raster, order: RowOrder, depth: BmpDepth) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_bmp_with(&mut bytes, img, BmpWriteOptions { order, depth: Some(depth) }).unwrap();
+        bytes
+    }
+
+    /// An INFO header for an image of the given shape and format.
+    fn info_header(width: i32, height: i32, bpp: u16, compression: u32, colors: u32) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend(INFO_HEADER.to_le_bytes());
+        header.extend(width.to_le_bytes());
+        header.extend(height.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(bpp.to_le_bytes());
+        header.extend(compression.to_le_bytes());
+        header.extend([0; 12]); // compressed size and pixels per meter
+        header.extend(colors.to_le_bytes());
+        header.extend([0; 4]); // important colors
+        header
+    }
+
+    /// Prefixes `header`, `table` and `data` with a file header pointing at `data`.
+    fn file(header: &[u8], table: &[u8], data: &[u8]) -> Vec<u8> {
+        let offset = 14 + header.len() + table.len();
+        let mut bytes = b"BM".to_vec();
+        bytes.extend(((offset + data.len()) as u32).to_le_bytes());
+        bytes.extend([0; 4]);
+        bytes.extend((offset as u32).to_le_bytes());
+        [bytes, header.to_vec(), table.to_vec(), data.to_vec()].concat()
+    }
+
+    /// Four palette entries: black, red, green and blue.
+    const PALETTE: [u8; 16] = [0, 0, 0, 0, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0];
+    const COLORS: [Pixel; 4] = [
+        Pixel { red: 0, green: 0, blue: 0, alpha: 255 },
+        Pixel { red: 255, green: 0, blue: 0, alpha: 255 },
+        Pixel { red: 0, green: 255, blue: 0, alpha: 255 },
+        Pixel { red: 0, green: 0, blue: 255, alpha: 255 }
+    ];
+
+    /// An image 3 pixels wide, so that rows of 24-bit pixels need a byte of padding.
+    fn image(alpha: impl Fn(usize, usize) -> u8) -> Raster {
+        let mut img = Raster::solid(Pixel::transparent(), 3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                img.set_pixel(x, y, Pixel { red: (x * 80) as u8, green: (y * 200) as u8, blue: 7, alpha: alpha(x, y) });
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn round_trips_in_both_row_orders() {
+        let opaque = image(|_, _| 255);
+        let translucent = image(|x, y| (x * 100 + y * 20) as u8);
+        for order in [RowOrder::BottomUp, RowOrder::TopDown] {
+            assert_eq!(decode(&encode(&opaque, order, BmpDepth::Bgr24)).unwrap().pixels, opaque.pixels);
+            assert_eq!(decode(&encode(&translucent, order, BmpDepth::Bgra32)).unwrap().pixels, translucent.pixels);
+        }
+    }
+
+    #[test]
+    fn rows_are_padded_and_channels_stored_in_reverse() {
+        let img = image(|_, _| 255);
+        let bytes = encode(&img, RowOrder::BottomUp, BmpDepth::Bgr24);
+        assert_eq!(bytes.len(), 14 + 40 + 2 * 12);
+        // The bottom row comes first, each pixel blue first, then one byte of padding.
+        assert_eq!(&bytes[54..66], [7, 200, 0, 7, 200, 80, 7, 200, 160, 0, 0, 0]);
+        assert_eq!(&bytes[66..78], [7, 0, 0, 7, 0, 80, 7, 0, 160, 0, 0, 0]);
+
+        let bytes = encode(&img, RowOrder::TopDown, BmpDepth::Bgr24);
+        assert_eq!(i32::from_le_bytes(bytes[22..26].try_into().unwrap()), -2);
+        assert_eq!(&bytes[54..57], [7, 0, 0]);
+    }
+
+    #[test]
+    fn sign_of_height_decides_row_order() {
+        // A row of one red pixel and a row of one blue pixel, each padded to 4 bytes.
+        let data = [0, 0, 255, 0, 255, 0, 0, 0];
+        let bottom_up = decode(&file(&info_header(1, 2, 24, BI_RGB, 0), &[], &data)).unwrap();
+        assert_eq!(bottom_up.pixels, [Pixel::rgb(0, 0, 255), Pixel::rgb(255, 0, 0)]);
+        let top_down = decode(&file(&info_header(1, -2, 24, BI_RGB, 0), &[], &data)).unwrap();
+        assert_eq!(top_down.pixels, [Pixel::rgb(255, 0, 0), Pixel::rgb(0, 0, 255)]);
+    }
+
+    #[test]
+    fn palettes_of_every_depth() {
+        // Five pixels using the colors 1, 2, 3, 0 and 1, packed most significant bits first.
+        let expected = [COLORS[1], COLORS[2], COLORS[3], COLORS[0], COLORS[1]];
+        let cases: [(u16, &[u8]); 3] = [
+            (1, &[0b1010_1000, 0, 0, 0]),
+            (4, &[0x12, 0x30, 0x10, 0]),
+            (8, &[1, 2, 3, 0, 1, 0, 0, 0])
+        ];
+        for (bpp, data) in cases {
+            let img = decode(&file(&info_header(5, 1, bpp, BI_RGB, 4), &PALETTE, data)).unwrap();
+            let expected: Vec<Pixel> = if bpp == 1 {
+                // Only two colors fit, so the bits pick between black and red.
+                [1, 0, 1, 0, 1].map(|i| COLORS[i]).to_vec()
+            } else {
+                expected.to_vec()
+            };
+            assert_eq!(img.pixels, expected, "{bpp} bits per pixel");
+        }
+    }
+
+    #[test]
+    fn core_header_has_three_byte_palette_entries() {
+        let mut header = CORE_HEADER.to_le_bytes().to_vec();
+        header.extend(2u16.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(8u16.to_le_bytes());
+        let mut table: Vec<u8> = (0..256).flat_map(|i| [i as u8, 0, 0]).collect();
+        table[3..6].copy_from_slice(&[0, 0, 255]);
+        let img = decode(&file(&header, &table, &[1, 9, 0, 0])).unwrap();
+        assert_eq!(img.pixels, [Pixel::rgb(255, 0, 0), Pixel::rgb(0, 0, 9)]);
+    }
+
+    #[test]
+    fn sixteen_bit_pixels_use_default_or_given_masks() {
+        // 5-5-5: full red, then half green.
+        let data = [0x00, 0x7C, 0x00, 0x02];
+        let img = decode(&file(&info_header(2, 1, 16, BI_RGB, 0), &[], &data)).unwrap();
+        assert_eq!(img.pixels, [Pixel::rgb(255, 0, 0), Pixel::rgb(0, 132, 0)]);
+
+        // 5-6-5: full green, then full blue.
+        let masks: Vec<u8> = [0xF800u32, 0x07E0, 0x001F].iter().flat_map(|m| m.to_le_bytes()).collect();
+        let data = [0xE0, 0x07, 0x1F, 0x00];
+        let img = decode(&file(&info_header(2, 1, 16, BI_BITFIELDS, 0), &masks, &data)).unwrap();
+        assert_eq!(img.pixels, [Pixel::rgb(0, 255, 0), Pixel::rgb(0, 0, 255)]);
+    }
+
+    #[test]
+    fn thirty_two_bit_alpha_needs_a_mask() {
+        let data = [1, 2, 3, 4];
+        // Without a mask the high byte is unused.
+        let img = decode(&file(&info_header(1, 1, 32, BI_RGB, 0), &[], &data)).unwrap();
+        assert_eq!(img.pixels, [Pixel::rgb(3, 2, 1)]);
+
+        let masks: Vec<u8> = [0xFFu32, 0xFF00, 0xFF0000, 0xFF000000].iter().flat_map(|m| m.to_le_bytes()).collect();
+        let img = decode(&file(&info_header(1, 1, 32, BI_ALPHABITFIELDS, 0), &masks, &data)).unwrap();
+        assert_eq!(img.pixels, [Pixel { red: 1, green: 2, blue: 3, alpha: 4 }]);
+    }
+
+    #[test]
+    fn v4_and_v5_headers_carry_masks() {
+        for size in [V4_HEADER, V5_HEADER] {
+            let mut header = info_header(1, 1, 32, BI_BITFIELDS, 0);
+            header[0..4].copy_from_slice(&size.to_le_bytes());
+            for mask in [0xFF00u32, 0xFF, 0xFF000000, 0xFF0000] { header.extend(mask.to_le_bytes()); }
+            header.resize(size as usize, 0);
+            let img = decode(&file(&header, &[], &[1, 2, 3, 4])).unwrap();
+            assert_eq!(img.pixels, [Pixel { red: 2, green: 1, blue: 4, alpha: 3 }], "{size} byte header");
+        }
+    }
+
+    #[test]
+    fn rle8_runs_and_escapes() {
+        let data = [
+            3, 1,             // three pixels of color 1
+            0, 0,             // end of line
+            0, 3, 2, 3, 1, 0, // an absolute run of three, padded to an even length
+            0, 2, 1, 0,       // move right by one
+            1, 3,             // one pixel of color 3, past the end of the row and clipped
+            0, 1              // end of bitmap
+        ];
+        let img = decode(&file(&info_header(3, -2, 8, BI_RLE8, 4), &PALETTE, &data)).unwrap();
+        assert_eq!(img.pixels, [COLORS[1], COLORS[1], COLORS[1], COLORS[2], COLORS[3], COLORS[1]]);
+
+        // A delta down a row leaves the pixels it skips transparent, and rows count up
+        // from the bottom.
+        let data = [1, 2, 0, 2, 1, 1, 1, 3, 0, 1];
+        let img = decode(&file(&info_header(3, 2, 8, BI_RLE8, 4), &PALETTE, &data)).unwrap();
+        let clear = Pixel::transparent();
+        assert_eq!(img.pixels, [clear, clear, COLORS[3], COLORS[2], clear, clear]);
+    }
+
+    #[test]
+    fn rle4_alternates_nibbles() {
+        let data = [
+            5, 0x12,       // 1, 2, 1, 2, 1
+            0, 0,
+            0, 3, 0x30, 0x20, // an absolute run of 3, 0, 2
+            0, 1
+        ];
+        let img = decode(&file(&info_header(5, -2, 4, BI_RLE4, 4), &PALETTE, &data)).unwrap();
+        let clear = Pixel::transparent();
+        assert_eq!(img.pixels, [
+            COLORS[1], COLORS[2], COLORS[1], COLORS[2], COLORS[1],
+            COLORS[3], COLORS[0], COLORS[2], clear, clear
+        ]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let valid = file(&info_header(1, 1, 24, BI_RGB, 0), &[], &[0; 4]);
+        assert!(decode(&valid).is_ok());
+        let with = |at: usize, bytes: &[u8]| {
+            let mut file = valid.clone();
+            file[at..(at + bytes.len())].copy_from_slice(bytes);
+            decode(&file)
+        };
+        assert!(matches!(with(0, b"MB"), Err(AnyError::Bmp(BmpError::BadSignature(_)))));
+        assert!(matches!(with(14, &[41]), Err(AnyError::Bmp(BmpError::UnsupportedHeader(41)))));
+        assert!(matches!(with(18, &[0]), Err(AnyError::Bmp(BmpError::InvalidDimensions { .. }))));
+        assert!(matches!(with(18, &[0xFF, 0xFF, 0xFF, 0xFF]), Err(AnyError::Bmp(BmpError::InvalidDimensions { .. }))));
+        assert!(matches!(with(26, &[2]), Err(AnyError::Bmp(BmpError::UnsupportedPlanes(2)))));
+        assert!(matches!(with(28, &[7]), Err(AnyError::Bmp(BmpError::UnsupportedBitsPerPixel(7)))));
+        assert!(matches!(with(30, &[BI_RLE8 as u8]), Err(AnyError::Bmp(BmpError::UnsupportedCompression(1)))));
+        assert!(matches!(decode(&valid[..56]), Err(AnyError::Bmp(BmpError::Truncated))));
+        assert!(matches!(decode(&valid[..30]), Err(AnyError::Bmp(BmpError::Truncated))));
+
+        let bad_index = file(&info_header(1, 1, 8, BI_RGB, 4), &PALETTE, &[4, 0, 0, 0]);
+        assert!(matches!(decode(&bad_index), Err(AnyError::Bmp(BmpError::BadPaletteIndex(4)))));
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected_before_allocating() {
+        let tiny = file(&info_header(65535, 65535, 24, BI_RGB, 0), &[], &[0; 6]);
+        assert!(matches!(decode(&tiny), Err(AnyError::Bmp(BmpError::Truncated))));
+        let rle = file(&info_header(65535, 65535, 8, BI_RLE8, 4), &PALETTE, &[0, 1]);
+        assert!(matches!(decode(&rle), Err(AnyError::Bmp(BmpError::TooLarge { .. }))));
+    }
 }
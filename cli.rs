@@ -0,0 +1,783 @@
+use std::time::Duration;
+use crate::matrix::Matrix;
+use crate::transform::Transform2D;
+use crate::color::ColorTransform;
+use crate::color::ColorOverflow;
+use crate::colorspace::ColorSpace;
+use crate::scene::CompositeOp;
+use crate::scene::BlendMode;
+use crate::animation::Easing;
+use crate::animation::Keyframe;
+use crate::quantize::Quantizer;
+use crate::homography::homography_from_quads;
+use crate::estimate::Estimate;
+use crate::estimate::Correspondence;
+use crate::estimate::Model;
+use crate::estimate::RansacOptions;
+use crate::estimate::estimate;
+use crate::estimate::estimate_ransac;
+use crate::raster::Pixel;
+use crate::rasterize::RasterMethod;
+use crate::rasterize::RasterizeOptions;
+use crate::filter::Filter;
+use crate::viewbox::ViewBox;
+use crate::bmp::BmpDepth;
+use crate::bmp::RowOrder;
+use crate::codec::EncoderSettings;
+
+// Command-line argument parsing
+// There are no dependencies in this project, so the argument parser is
+// written by hand. It is intentionally simple: every option takes either
+// zero or one argument, and numeric lists are comma-separated.
+
+/// Finer steps than these make the forward rasterizer crawl without drawing any better.
+const SCAN_PX_RANGE: std::ops::RangeInclusive<f64> = 0.01..=16.0;
+/// Denser output than this quickly grows past what fits in memory.
+const PIXEL_DENSITY_RANGE: std::ops::RangeInclusive<f64> = 0.01..=64.0;
+/// The anti-aliasing presets use when `--antialias` is not given.
+const PRESET_ANTIALIAS: usize = 4;
+
+pub const USAGE: &str = "\
+Usage: laproj [OPTIONS]
+       laproj --preset NAME [--input PATH] [--output-dir DIR]
+
+Applies linear transformations to an image. Transforms are applied in the
+order they are given on the command line. When no arguments are given at
+all, every preset is run (equivalent to `--preset all`).
+
+Input/Output:
+  -i, --input PATH          Image to transform (default: input.bmp). BMP, PNG
+                            and Netpbm files are recognized by their contents.
+  -o, --output PATH         Where to write the transformed image. May be given
+                            more than once. The format is inferred from the
+                            extension unless --format is given.
+  -f, --format FORMAT       Force the output format: bmp, png, gif, pbm, pgm,
+                            ppm, pam, pnm or svg
+      --bmp-depth 24|32     Bits per pixel of BMP output (default: 32 when the
+                            image has transparency, otherwise 24)
+      --bmp-order ORDER     Row order of BMP output: bottom-up or top-down
+                            (default: bottom-up)
+      --pnm-plain           Write the ASCII variant of PBM, PGM and PPM output
+      --pnm-16bit           Write 16-bit samples in PGM, PPM and PAM output
+      --gif-quantizer ALGORITHM
+                            How the palette of each GIF frame is chosen:
+                            median-cut or octree (default: median-cut)
+      --gif-loops N         How many times an animated GIF plays, or 0 to
+                            play it forever (default: 0)
+      --gif-opaque          Keep the color of transparent pixels in GIF
+                            output instead of making them transparent
+
+Transforms:
+      --translate X,Y       Offset every vertex by (X, Y)
+      --scale S | SX,SY     Scale uniformly by S, or by SX and SY
+      --rotate DEGREES      Rotate clockwise on screen
+      --pivot center|X,Y    The point later --scale and --rotate options keep
+                            fixed: the center of the input image, or (X, Y)
+                            (default: 0,0, the top left corner of the image)
+      --reflect x|y         Reflect across the x-axis or the y-axis
+      --shear KX,KY         Shear x by KX*y and y by KY*x
+      --shear-x K           Shear x by K*y, leaving y alone
+      --shear-y K           Shear y by K*x, leaving x alone
+      --matrix A,B,C,D,E,F[,G,H,I]
+                            An arbitrary 3x3 matrix given in row-major order.
+                            When only six values are given the last row is
+                            taken to be 0,0,1. Any other last row gives a
+                            perspective transform.
+      --homography X1,Y1,...,X4,Y4,U1,V1,...,U4,V4
+                            The perspective transform taking each point (Xi,Yi)
+                            to the point (Ui,Vi), for example the corners of a
+                            photographed page to the corners of a rectangle
+      --fit-affine X1,Y1,U1,V1,X2,Y2,U2,V2,...
+                            The affine transform taking each point (Xi,Yi) as
+                            close as possible to (Ui,Vi), by least squares.
+                            Needs at least three pairs.
+      --fit-homography X1,Y1,U1,V1,X2,Y2,U2,V2,...
+                            As --fit-affine but a perspective transform, found
+                            by normalized DLT. Needs at least four pairs.
+      --ransac THRESHOLD    Fit transforms with RANSAC, ignoring any pair whose
+                            point lands farther than THRESHOLD from its target
+      --explain             Print the combined transform matrix along with its
+                            rotation, scale, shear and eigenvalues. --output
+                            may be omitted to only print.
+
+Colors:
+      --color EFFECT        Recolor the input image. May be given more than
+                            once, in which case the effects are applied in
+                            order. EFFECT is one of negative, grayscale, sepia,
+                            swap:ORDER (e.g. swap:bgr), brightness:OFFSET
+                            (added to every channel, 0-255), contrast:FACTOR,
+                            saturation:FACTOR or hue:DEGREES
+      --color-matrix M      An arbitrary color transform given as 16 numbers,
+                            a 4x4 matrix acting on (R,G,B,1), or 25 numbers, a
+                            5x5 matrix acting on (R,G,B,A,1), in row-major order
+      --color-space SPACE   The working space of later --color and
+                            --color-matrix options, converted to from sRGB and
+                            back automatically: srgb, linear, hsv, hsl,
+                            ycbcr601, ycbcr709, xyz or lab (default: srgb).
+                            Blending, brightness and contrast are most faithful
+                            in linear, and in hsv the first row of a color
+                            matrix acts on hue in degrees.
+      --color-overflow clamp|wrap|normalize
+                            What happens to channels pushed outside 0-255:
+                            clamp them, wrap them around, or stretch the range
+                            of the whole image to fit (default: clamp)
+
+Layers:
+      --layer PATH          Draw the image at PATH on top of the input image, or
+                            of the previous layer. Transform, color and the
+                            options below that come after it apply to it alone.
+      --opacity F           Opacity of the layer from 0 to 1 (default: 1)
+      --composite OP        Porter-Duff operator joining the layer to what lies
+                            beneath it: over, in, out, atop or xor
+                            (default: over)
+      --blend MODE          How the layer's colors mix with those beneath it:
+                            normal, multiply, screen, overlay or difference
+                            (default: normal)
+      --z N                 Stacking order of the layer. Higher layers are
+                            drawn later, and equal ones in the order given
+                            (default: 0)
+
+Animation:
+      --frames N            Write an animation of N frames, at least 2, per
+                            keyframe. GIF and SVG outputs hold the whole
+                            animation, while every other output path is
+                            numbered, so that --output frame.png writes
+                            frame-00.png onwards. SVG animations play smoothly
+                            between frames but need affine transforms.
+      --keyframe            Make the transforms given so far a keyframe. The
+                            animation runs through every keyframe and ends at
+                            the whole transform. Without any it runs from the
+                            identity to the whole transform.
+      --easing EASING       How later keyframes are approached: linear,
+                            ease-in, ease-out or ease-in-out (default: linear)
+      --frame-delay MS      How long each frame is shown for in milliseconds
+                            (default: 40)
+
+Viewbox:
+      --viewbox MINX,MINY,WIDTH,HEIGHT
+                            The region of the plane to render. By default the
+                            viewbox is fit to the transformed image.
+
+Rasterizer:
+      --rasterizer METHOD   How raster output is produced: inverse, which maps
+                            each output pixel back onto the image, scanline,
+                            which fills each source pixel as a polygon, or
+                            forward, which scans each source pixel
+                            (default: inverse)
+      --scan-px F           Sampling step of the forward rasterizer in output
+                            pixels, from 0.01 to 16 (default: 1)
+      --filter FILTER       Reconstruction filter of the inverse rasterizer:
+                            nearest, bilinear, bicubic (Catmull-Rom), mitchell
+                            or lanczos3 (default: nearest)
+      --pixel-density F     Output pixels per unit distance, from 0.01 to 64
+                            (default: 1)
+      --antialias N         Take NxN samples per output pixel so that the edges
+                            of the image blend smoothly into the background
+                            (default: 1, meaning no anti-aliasing, or 4 for
+                            presets)
+      --background R,G,B[,A]
+                            Color of uncovered space (default: transparent)
+
+Presets:
+      --preset NAME         Run a built-in textbook part. May be given more
+                            than once. Use `all` to run every preset. The
+                            rasterizer options above apply to its BMP output.
+      --output-dir DIR      Directory presets write into
+                            (default: transformed_images)
+      --list-presets        Print the name of every preset and exit
+
+Performance:
+      --threads N           Threads used to apply the transform (default: the
+                            number of available processors)
+      --benchmark           Time the matrix multiplication kernels against the
+                            naive implementation and exit. Build with
+                            --release for meaningful numbers.
+
+  -h, --help                Print this message and exit
+";
+
+/// What the user asked the program to do.
+pub enum Command {
+    Help,
+    ListPresets,
+    Presets(PresetJob),
+    Transform(TransformJob),
+    /// Run `--benchmark` with the given number of threads.
+    Benchmark(usize)
+}
+
+pub struct PresetJob {
+    pub names: Vec<String>,
+    pub input: String,
+    pub output_dir: String,
+    pub raster: RasterizeOptions
+}
+
+pub struct TransformJob {
+    /// The `--input` image followed by every `--layer`, in the order given.
+    pub layers: Vec<LayerSpec>,
+    pub outputs: Vec<OutputSpec>,
+    /// The outcome of every `--fit-*` option, in order, for reporting residuals.
+    pub fits: Vec<Estimate>,
+    /// Print a description of the combined transform, see `--explain`.
+    pub explain: bool,
+    pub viewbox: Option<ViewBox>,
+    pub raster: RasterizeOptions,
+    pub encoders: EncoderSettings,
+    /// The number of threads to multiply the transform into the quilt with.
+    pub threads: usize,
+    /// The number of frames from one keyframe to the next when animating, see `--frames`.
+    pub frames: Option<usize>,
+    pub frame_delay: Duration
+}
+
+/// An image in the output along with everything that happens to it before it is
+/// composited with the others.
+pub struct LayerSpec {
+    pub input: String,
+    pub transforms: Vec<TransformSpec>,
+    /// The combination of every `--color` and `--color-matrix`, if any were given.
+    pub color: Option<ColorTransform>,
+    pub z: i32,
+    pub opacity: f64,
+    pub operator: CompositeOp,
+    pub blend: BlendMode,
+    /// The number of transforms given before each `--keyframe`, along with the easing in
+    /// effect there.
+    pub keyframes: Vec<(usize, Easing)>,
+    /// The easing in effect after the last transform.
+    pub easing: Easing
+}
+
+impl LayerSpec {
+    fn new(input: String, easing: Easing) -> Self {
+        Self {
+            input,
+            transforms: Vec::new(),
+            color: None,
+            keyframes: Vec::new(),
+            easing,
+            z: 0,
+            opacity: 1.0,
+            operator: CompositeOp::default(),
+            blend: BlendMode::default()
+        }
+    }
+}
+
+/// A `format` of `None` infers the format from the extension of `path`. Format names
+/// are resolved against [`crate::codec::Registry`] when the output is written.
+pub struct OutputSpec { pub path: String, pub format: Option<String> }
+
+#[derive(Clone, Copy, Debug)]
+pub enum Axis { X, Y }
+
+/// The point a scale or rotation leaves fixed, see `--pivot`.
+#[derive(Clone, Copy, Debug)]
+pub enum Pivot {
+    Point(f64, f64),
+    /// The center of the input image, which is only known once it has been loaded.
+    Center
+}
+
+#[derive(Clone, Debug)]
+pub enum TransformSpec {
+    Translate(f64, f64),
+    Scale(f64, f64, Pivot),
+    /// Rotation angle in degrees.
+    Rotate(f64, Pivot),
+    Reflect(Axis),
+    Shear(f64, f64),
+    ShearX(f64),
+    ShearY(f64),
+    Matrix([[f64; 3]; 3])
+}
+
+impl TransformSpec {
+    /// The transform this spec describes, where `center` is the center of the input image.
+    pub fn to_transform(&self, center: (f64, f64)) -> Transform2D {
+        let pivot = |pivot| match pivot {
+            Pivot::Point(x, y) => (x, y),
+            Pivot::Center => center
+        };
+        match *self {
+            TransformSpec::Translate(x, y) => Transform2D::translate(x, y),
+            TransformSpec::Scale(sx, sy, p) => Transform2D::scale_about(pivot(p), sx, sy),
+            TransformSpec::Rotate(degrees, p) => Transform2D::rotate_about(pivot(p), degrees.to_radians()),
+            TransformSpec::Reflect(Axis::X) => Transform2D::reflect_across_line(0.0, 1.0, 0.0)
+                .expect("the x-axis is a line"),
+            TransformSpec::Reflect(Axis::Y) => Transform2D::reflect_across_line(1.0, 0.0, 0.0)
+                .expect("the y-axis is a line"),
+            TransformSpec::Shear(kx, ky) => Transform2D::from_rows([
+                [1.0, kx, 0.0],
+                [ky, 1.0, 0.0],
+                [0.0, 0.0, 1.0]
+            ]),
+            TransformSpec::ShearX(k) => Transform2D::shear_x(k),
+            TransformSpec::ShearY(k) => Transform2D::shear_y(k),
+            TransformSpec::Matrix(rows) => Transform2D::from_rows(rows)
+        }
+    }
+
+    /// Whether the transform depends on the center of the input image.
+    pub fn uses_center(&self) -> bool {
+        matches!(self, TransformSpec::Scale(_, _, Pivot::Center) | TransformSpec::Rotate(_, Pivot::Center))
+    }
+}
+
+/// Chains the transforms of `specs` such that the first spec is applied first. An empty
+/// list composes to the identity. `center` is the center of the input image.
+pub fn compose(specs: &[TransformSpec], center: (f64, f64)) -> Transform2D {
+    specs.iter().fold(Transform2D::identity(), |acc, spec| acc.then(spec.to_transform(center)))
+}
+
+/// The keyframes of `layer`, see `--keyframe`. The whole transform is always the last
+/// keyframe, and the identity the first unless some `--keyframe` says otherwise.
+pub fn keyframes(layer: &LayerSpec, center: (f64, f64)) -> Vec<Keyframe> {
+    let mut marks = layer.keyframes.clone();
+    if marks.is_empty() { marks.push((0, Easing::Linear)); }
+    if marks.last().is_some_and(|&(n, _)| n < layer.transforms.len()) {
+        marks.push((layer.transforms.len(), layer.easing));
+    }
+    marks.into_iter()
+        .map(|(n, easing)| Keyframe { transform: compose(&layer.transforms[..n], center), easing })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+fn usage_err<T>(msg: impl Into<String>) -> Result<T, UsageError> {
+    Err(UsageError(msg.into()))
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, UsageError> {
+    if args.is_empty() {
+        return Ok(Command::Presets(PresetJob {
+            names: vec![String::from("all")],
+            input: String::from("input.bmp"),
+            output_dir: String::from("transformed_images"),
+            raster: RasterizeOptions { antialias: PRESET_ANTIALIAS, ..RasterizeOptions::default() }
+        }));
+    }
+
+    let mut input: Option<String> = None;
+    // The input image is the first layer, whose path is filled in once every option has
+    // been parsed. Transform, color and compositing options apply to the latest layer.
+    let mut easing = Easing::default();
+    let mut layers = vec![LayerSpec::new(String::new(), easing)];
+    let mut output_paths: Vec<String> = Vec::new();
+    let mut format: Option<String> = None;
+    let mut fits: Vec<(usize, usize, Model, Vec<Correspondence>)> = Vec::new();
+    let mut ransac: Option<RansacOptions> = None;
+    let mut explain = false;
+    let mut pivot = Pivot::Point(0.0, 0.0);
+    let mut frames: Option<usize> = None;
+    let mut frame_delay = Duration::from_millis(40);
+    let mut color_overflow = ColorOverflow::default();
+    let mut color_space = ColorSpace::default();
+    let mut viewbox: Option<ViewBox> = None;
+    let mut raster = RasterizeOptions::default();
+    let mut antialias: Option<usize> = None;
+    let mut encoders = EncoderSettings::default();
+    let mut presets: Vec<String> = Vec::new();
+    let mut output_dir: Option<String> = None;
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut benchmark = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // Accept both `--opt value` and `--opt=value`.
+        let (name, inline) = match arg.split_once('=') {
+            Some((n, v)) if arg.starts_with("--") => (n, Some(v.to_string())),
+            _ => (arg.as_str(), None)
+        };
+        let mut value = || -> Result<String, UsageError> {
+            if let Some(v) = inline.clone() { return Ok(v); }
+            match args.next() {
+                Some(v) => Ok(v.clone()),
+                None => usage_err(format!("option '{}' requires an argument", name))
+            }
+        };
+        let layer_index = layers.len() - 1;
+        let layer = &mut layers[layer_index];
+
+        match name {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--list-presets" => return Ok(Command::ListPresets),
+            "-i" | "--input" => input = Some(value()?),
+            "-o" | "--output" => output_paths.push(value()?),
+            "-f" | "--format" => format = Some(value()?),
+            "--bmp-depth" => encoders.bmp.depth = match value()?.as_str() {
+                "24" => Some(BmpDepth::Bgr24),
+                "32" => Some(BmpDepth::Bgra32),
+                other => return usage_err(format!("'{}' expects 24 or 32, got '{}'", name, other))
+            },
+            "--pnm-plain" => encoders.pnm_plain = true,
+            "--pnm-16bit" => encoders.pnm_wide = true,
+            "--gif-quantizer" => encoders.gif.quantizer = match value()?.as_str() {
+                "median-cut" => Quantizer::MedianCut,
+                "octree" => Quantizer::Octree,
+                other => return usage_err(format!("'{}' expects median-cut or octree, got '{}'", name, other))
+            },
+            "--gif-loops" => {
+                let v = value()?;
+                encoders.gif.loops = v.parse()
+                    .map_err(|_| UsageError(format!("'{}' expects an integer from 0 to 65535, got '{}'", name, v)))?;
+            },
+            "--gif-opaque" => encoders.gif.transparency = false,
+            "--bmp-order" => encoders.bmp.order = match value()?.as_str() {
+                "bottom-up" => RowOrder::BottomUp,
+                "top-down" => RowOrder::TopDown,
+                other => return usage_err(format!(
+                    "'{}' expects bottom-up or top-down, got '{}'", name, other))
+            },
+            "--translate" => {
+                let [x, y] = parse_floats::<2>(name, &value()?)?;
+                layer.transforms.push(TransformSpec::Translate(x, y));
+            },
+            "--scale" => {
+                let v = value()?;
+                let spec = match parse_float_list(name, &v)?.as_slice() {
+                    [s] => TransformSpec::Scale(*s, *s, pivot),
+                    [sx, sy] => TransformSpec::Scale(*sx, *sy, pivot),
+                    _ => return usage_err(format!("'{}' expects S or SX,SY", name))
+                };
+                layer.transforms.push(spec);
+            },
+            "--rotate" => {
+                let [degrees] = parse_floats::<1>(name, &value()?)?;
+                layer.transforms.push(TransformSpec::Rotate(degrees, pivot));
+            },
+            "--pivot" => pivot = match value()?.as_str() {
+                "center" => Pivot::Center,
+                v => {
+                    let [x, y] = parse_floats::<2>(name, v)?;
+                    Pivot::Point(x, y)
+                }
+            },
+            "--reflect" => {
+                let axis = match value()?.as_str() {
+                    "x" | "X" => Axis::X,
+                    "y" | "Y" => Axis::Y,
+                    other => return usage_err(format!("'{}' expects x or y, got '{}'", name, other))
+                };
+                layer.transforms.push(TransformSpec::Reflect(axis));
+            },
+            "--shear" => {
+                let [kx, ky] = parse_floats::<2>(name, &value()?)?;
+                layer.transforms.push(TransformSpec::Shear(kx, ky));
+            },
+            "--shear-x" | "--shear-y" => {
+                let [k] = parse_floats::<1>(name, &value()?)?;
+                layer.transforms.push(if name == "--shear-x" { TransformSpec::ShearX(k) } else { TransformSpec::ShearY(k) });
+            },
+            "--matrix" => {
+                let v = value()?;
+                let rows = match parse_float_list(name, &v)?.as_slice() {
+                    [a, b, c, d, e, f] => [[*a, *b, *c], [*d, *e, *f], [0.0, 0.0, 1.0]],
+                    [a, b, c, d, e, f, g, h, i] => [[*a, *b, *c], [*d, *e, *f], [*g, *h, *i]],
+                    _ => return usage_err(format!("'{}' expects 6 or 9 comma-separated numbers", name))
+                };
+                layer.transforms.push(TransformSpec::Matrix(rows));
+            },
+            "--homography" => {
+                let v = parse_floats::<16>(name, &value()?)?;
+                let src: [(f64, f64); 4] = std::array::from_fn(|i| (v[2 * i], v[2 * i + 1]));
+                let dst: [(f64, f64); 4] = std::array::from_fn(|i| (v[8 + 2 * i], v[8 + 2 * i + 1]));
+                let Some(m) = homography_from_quads(&src, &dst) else {
+                    return usage_err(format!("'{}' was given three collinear points", name));
+                };
+                layer.transforms.push(TransformSpec::Matrix(matrix_rows(&m)));
+            },
+            "--fit-affine" | "--fit-homography" => {
+                let model = if name == "--fit-affine" { Model::Affine } else { Model::Homography };
+                let v = parse_float_list(name, &value()?)?;
+                if v.len() % 4 != 0 {
+                    return usage_err(format!("'{}' expects groups of four numbers X,Y,U,V", name));
+                }
+                let pairs = v.chunks_exact(4).map(|c| ((c[0], c[1]), (c[2], c[3]))).collect();
+                // The fit waits until every option is parsed, since --ransac may come later.
+                fits.push((layer_index, layer.transforms.len(), model, pairs));
+                layer.transforms.push(TransformSpec::Translate(0.0, 0.0));
+            },
+            "--ransac" => {
+                let [threshold] = parse_floats::<1>(name, &value()?)?;
+                if threshold <= 0.0 { return usage_err(format!("'{}' must be positive", name)); }
+                ransac = Some(RansacOptions { threshold, ..RansacOptions::default() });
+            },
+            "--explain" => explain = true,
+            "--frames" => {
+                let v = value()?;
+                frames = match v.parse::<usize>() {
+                    Ok(n) if n >= 2 => Some(n),
+                    _ => return usage_err(format!("'{}' expects an integer of at least 2, got '{}'", name, v))
+                };
+            },
+            "--frame-delay" => {
+                let [ms] = parse_floats::<1>(name, &value()?)?;
+                if ms <= 0.0 { return usage_err(format!("'{}' must be positive", name)); }
+                frame_delay = Duration::from_secs_f64(ms / 1000.0);
+            },
+            "--keyframe" => layer.keyframes.push((layer.transforms.len(), easing)),
+            "--easing" => {
+                easing = match value()?.as_str() {
+                    "linear" => Easing::Linear,
+                    "ease-in" => Easing::EaseIn,
+                    "ease-out" => Easing::EaseOut,
+                    "ease-in-out" => Easing::EaseInOut,
+                    other => return usage_err(format!(
+                        "'{}' expects linear, ease-in, ease-out or ease-in-out, got '{}'", name, other))
+                };
+                layer.easing = easing;
+            },
+            "--color" | "--color-matrix" => {
+                let next = if name == "--color" {
+                    parse_color_effect(name, &value()?)?
+                } else {
+                    let v = parse_float_list(name, &value()?)?;
+                    let v: Vec<f32> = v.into_iter().map(|x| x as f32).collect();
+                    match v.len() {
+                        16 => ColorTransform::from_rgb_rows(std::array::from_fn(|r| std::array::from_fn(|c| v[4 * r + c]))),
+                        25 => ColorTransform::from_rows(std::array::from_fn(|r| std::array::from_fn(|c| v[5 * r + c]))),
+                        _ => return usage_err(format!("'{}' expects 16 or 25 comma-separated numbers", name))
+                    }
+                };
+                let next = next.in_space(color_space);
+                layer.color = Some(match layer.color.take() {
+                    Some(acc) => acc.then(next),
+                    None => next
+                });
+            },
+            "--layer" => layers.push(LayerSpec::new(value()?, easing)),
+            "--z" => {
+                let v = value()?;
+                layer.z = v.parse().map_err(|_| UsageError(format!("'{}' expects an integer, got '{}'", name, v)))?;
+            },
+            "--opacity" => {
+                let [opacity] = parse_floats::<1>(name, &value()?)?;
+                if !(0.0..=1.0).contains(&opacity) { return usage_err(format!("'{}' must be from 0 to 1", name)); }
+                layer.opacity = opacity;
+            },
+            "--composite" => layer.operator = match value()?.as_str() {
+                "over" => CompositeOp::Over,
+                "in" => CompositeOp::In,
+                "out" => CompositeOp::Out,
+                "atop" => CompositeOp::Atop,
+                "xor" => CompositeOp::Xor,
+                other => return usage_err(format!("'{}' expects over, in, out, atop or xor, got '{}'", name, other))
+            },
+            "--blend" => layer.blend = match value()?.as_str() {
+                "normal" => BlendMode::Normal,
+                "multiply" => BlendMode::Multiply,
+                "screen" => BlendMode::Screen,
+                "overlay" => BlendMode::Overlay,
+                "difference" => BlendMode::Difference,
+                other => return usage_err(format!(
+                    "'{}' expects normal, multiply, screen, overlay or difference, got '{}'", name, other))
+            },
+            "--color-space" => color_space = match value()?.as_str() {
+                "srgb" => ColorSpace::Srgb,
+                "linear" => ColorSpace::LinearRgb,
+                "hsv" => ColorSpace::Hsv,
+                "hsl" => ColorSpace::Hsl,
+                "ycbcr601" | "ycbcr" => ColorSpace::YCbCr601,
+                "ycbcr709" => ColorSpace::YCbCr709,
+                "xyz" => ColorSpace::Xyz,
+                "lab" => ColorSpace::Lab,
+                other => return usage_err(format!(
+                    "'{}' expects srgb, linear, hsv, hsl, ycbcr601, ycbcr709, xyz or lab, got '{}'", name, other))
+            },
+            "--color-overflow" => color_overflow = match value()?.as_str() {
+                "clamp" => ColorOverflow::Clamp,
+                "wrap" => ColorOverflow::Wrap,
+                "normalize" => ColorOverflow::Normalize,
+                other => return usage_err(format!("'{}' expects clamp, wrap or normalize, got '{}'", name, other))
+            },
+            "--viewbox" => {
+                let [min_x, min_y, width, height] = parse_floats::<4>(name, &value()?)?;
+                if width <= 0.0 || height <= 0.0 {
+                    return usage_err("viewbox width and height must be positive");
+                }
+                viewbox = Some(ViewBox { min_x, min_y, width, height });
+            },
+            "--rasterizer" => raster.method = match value()?.as_str() {
+                "inverse" => RasterMethod::Inverse,
+                "forward" => RasterMethod::Forward,
+                "scanline" => RasterMethod::Scanline,
+                other => return usage_err(format!(
+                    "'{}' expects inverse, scanline or forward, got '{}'", name, other))
+            },
+            "--filter" => raster.filter = match value()?.as_str() {
+                "nearest" => Filter::Nearest,
+                "bilinear" => Filter::Bilinear,
+                "bicubic" | "catmull-rom" => Filter::CatmullRom,
+                "mitchell" => Filter::Mitchell,
+                "lanczos3" | "lanczos" => Filter::Lanczos3,
+                other => return usage_err(format!(
+                    "'{}' expects nearest, bilinear, bicubic, mitchell or lanczos3, got '{}'", name, other))
+            },
+            "--scan-px" => raster.scan_px = parse_bounded(name, &value()?, SCAN_PX_RANGE)?,
+            "--pixel-density" => raster.pixel_density = parse_bounded(name, &value()?, PIXEL_DENSITY_RANGE)?,
+            "--antialias" => {
+                let v = value()?;
+                antialias = match v.parse::<usize>() {
+                    Ok(n) if (1..=16).contains(&n) => Some(n),
+                    _ => return usage_err(format!("'{}' expects an integer from 1 to 16, got '{}'", name, v))
+                };
+            },
+            "--background" => raster.bg_color = parse_color(name, &value()?)?,
+            "--preset" => presets.push(value()?),
+            "--output-dir" => output_dir = Some(value()?),
+            "--threads" => {
+                let v = value()?;
+                threads = match v.parse::<usize>() {
+                    Ok(n) if n >= 1 => n,
+                    _ => return usage_err(format!("'{}' expects a positive integer, got '{}'", name, v))
+                };
+            },
+            "--benchmark" => benchmark = true,
+            other if other.starts_with('-') => return usage_err(format!("unrecognized option '{}'", other)),
+            other => return usage_err(format!("unexpected argument '{}'", other))
+        }
+    }
+
+    if benchmark { return Ok(Command::Benchmark(threads)); }
+
+    let input = input.unwrap_or_else(|| String::from("input.bmp"));
+
+    if !presets.is_empty() {
+        let base = &layers[0];
+        if layers.len() > 1 || !base.transforms.is_empty() || base.color.is_some() || !output_paths.is_empty()
+            || viewbox.is_some() || explain || frames.is_some()
+        {
+            return usage_err(
+                "--preset cannot be combined with transforms, colors, layers, --output, --viewbox, --explain or --frames");
+        }
+        let output_dir = output_dir.unwrap_or_else(|| String::from("transformed_images"));
+        // Presets are anti-aliased unless asked otherwise, so that rotated and sheared
+        // parts have smooth borders.
+        let raster = RasterizeOptions { antialias: antialias.unwrap_or(PRESET_ANTIALIAS), ..raster };
+        return Ok(Command::Presets(PresetJob { names: presets, input, output_dir, raster }));
+    }
+
+    if output_dir.is_some() {
+        return usage_err("--output-dir is only meaningful with --preset");
+    }
+    raster.antialias = antialias.unwrap_or(raster.antialias);
+    if output_paths.is_empty() && !explain {
+        return usage_err("no output given, use --output PATH");
+    }
+
+    if frames.is_none() && layers.iter().any(|layer| !layer.keyframes.is_empty()) {
+        return usage_err("--keyframe is only meaningful with --frames");
+    }
+
+    if ransac.is_some() && fits.is_empty() {
+        return usage_err("--ransac is only meaningful with --fit-affine or --fit-homography");
+    }
+    let mut estimates = Vec::with_capacity(fits.len());
+    for (l, i, model, pairs) in fits {
+        let result = match &ransac {
+            Some(opts) => estimate_ransac(model, &pairs, opts),
+            None => estimate(model, &pairs)
+        };
+        let fit = result.map_err(|e| UsageError(format!("cannot fit a transform to the given points: {}", e)))?;
+        layers[l].transforms[i] = TransformSpec::Matrix(matrix_rows(&fit.matrix));
+        estimates.push(fit);
+    }
+
+    let outputs = output_paths.into_iter()
+        .map(|path| OutputSpec { path, format: format.clone() })
+        .collect();
+
+    layers[0].input = input;
+    for layer in &mut layers {
+        layer.color = layer.color.take().map(|c| c.with_overflow(color_overflow));
+    }
+
+    Ok(Command::Transform(TransformJob { layers, outputs, fits: estimates, explain, viewbox, raster, encoders, threads,
+        frames, frame_delay }))
+}
+
+/// Parses the EFFECT of `--color`, which is a name optionally followed by a colon and an
+/// amount.
+fn parse_color_effect(opt: &str, s: &str) -> Result<ColorTransform, UsageError> {
+    let (effect, amount) = match s.split_once(':') {
+        Some((effect, amount)) => (effect, Some(amount)),
+        None => (s, None)
+    };
+    let number = || -> Result<f32, UsageError> {
+        match amount {
+            Some(a) => parse_floats::<1>(opt, a).map(|[x]| x as f32),
+            None => usage_err(format!("'{}' effect '{}' needs an amount, as in {}:AMOUNT", opt, effect, effect))
+        }
+    };
+    let transform = match effect {
+        "negative" => ColorTransform::negative(),
+        "grayscale" => ColorTransform::grayscale(),
+        "sepia" => ColorTransform::sepia(),
+        "swap" => {
+            // Each letter names the input channel that lands in that position.
+            let order = amount.unwrap_or_default().to_ascii_lowercase();
+            let channels: Option<Vec<usize>> = order.chars().map(|c| "rgb".find(c)).collect();
+            let Some([r, g, b]) = channels.and_then(|c| <[usize; 3]>::try_from(c).ok()) else {
+                return usage_err(format!("'{}' expects swap:ORDER with ORDER three of r, g and b, got '{}'", opt, s));
+            };
+            ColorTransform::swap_channels([r, g, b])
+        },
+        "brightness" => ColorTransform::brightness(number()?),
+        "contrast" => ColorTransform::contrast(number()?),
+        "saturation" => ColorTransform::saturation(number()?),
+        "hue" => ColorTransform::hue_rotate(number()?.to_radians()),
+        other => return usage_err(format!(
+            "'{}' expects negative, grayscale, sepia, swap, brightness, contrast, saturation or hue, got '{}'", opt, other))
+    };
+    Ok(transform)
+}
+
+/// The entries of the 3x3 matrix `m` in row-major order.
+fn matrix_rows(m: &Matrix<f64>) -> [[f64; 3]; 3] {
+    std::array::from_fn(|r| std::array::from_fn(|c| m[(r, c)]))
+}
+
+fn parse_float_list(opt: &str, s: &str) -> Result<Vec<f64>, UsageError> {
+    s.split(',')
+        .map(|part| match part.trim().parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            _ => Err(UsageError(format!("'{}' expects finite numbers, got '{}'", opt, part)))
+        })
+        .collect()
+}
+
+/// Parses a single number which must lie within `range`.
+fn parse_bounded(opt: &str, s: &str, range: std::ops::RangeInclusive<f64>) -> Result<f64, UsageError> {
+    let [v] = parse_floats::<1>(opt, s)?;
+    if !range.contains(&v) {
+        return usage_err(format!("'{}' must be between {} and {}", opt, range.start(), range.end()));
+    }
+    Ok(v)
+}
+
+fn parse_floats<const N: usize>(opt: &str, s: &str) -> Result<[f64; N], UsageError> {
+    let list = parse_float_list(opt, s)?;
+    <[f64; N]>::try_from(list.as_slice())
+        .map_err(|_| UsageError(format!("'{}' expects {} comma-separated number(s)", opt, N)))
+}
+
+fn parse_color(opt: &str, s: &str) -> Result<Pixel, UsageError> {
+    let parts: Result<Vec<u8>, _> = s.split(',').map(|part| part.trim().parse::<u8>()).collect();
+    match parts.as_deref() {
+        Ok([red, green, blue]) => Ok(Pixel::rgb(*red, *green, *blue)),
+        Ok([red, green, blue, alpha]) => Ok(Pixel { red: *red, green: *green, blue: *blue, alpha: *alpha }),
+        _ => usage_err(format!("'{}' expects R,G,B or R,G,B,A with each component in 0..=255", opt))
+    }
+}
@@ -0,0 +1,314 @@
+use crate::error::AnyError;
+use crate::raster::Raster;
+use crate::quilt::Quilt;
+use crate::scene::Scene;
+use crate::viewbox::ViewBox;
+use crate::rasterize::rasterize;
+use crate::rasterize::RasterizeOptions;
+use crate::bmp::BmpWriteOptions;
+use crate::pnm::PnmFormat;
+use crate::pnm::PnmWriteOptions;
+use crate::gif::GifWriter;
+use crate::gif::GifWriteOptions;
+use crate::transform::Transform2D;
+use std::time::Duration;
+
+// Format registry
+// Every image format is exposed through the `ImageDecoder` and `ImageEncoder`
+// traits and registered with a `Registry`. Input files are identified by their
+// leading bytes rather than their names, and output files by their extension.
+// Adding a format means implementing the traits and adding it to `Registry::new`.
+
+pub trait ReadSeek: std::io::Read + std::io::Seek {}
+impl<T> ReadSeek for T where T: std::io::Read + std::io::Seek {}
+
+/// A transformed quilt, or a scene of several, on its way to being encoded. Raster
+/// formats call [`Rendering::rasterize`], while vector formats draw the content directly.
+pub struct Rendering<'a> { pub content: Content<'a>, pub vb: &'a ViewBox, pub options: &'a RasterizeOptions }
+
+#[derive(Clone, Copy)]
+pub enum Content<'a> {
+    Quilt(&'a Quilt),
+    Scene(&'a Scene)
+}
+
+impl<'a> Rendering<'a> {
+    /// Fails when the viewbox has no area, such as after scaling by zero, as there is no
+    /// such thing as a raster image without any pixels.
+    pub fn rasterize(&self) -> Result<Raster, AnyError> {
+        if !(self.vb.width > 0.0 && self.vb.height > 0.0) {
+            return Err(AnyError::EmptyImage(format!("the {}x{} viewbox has no area to draw into",
+                self.vb.width, self.vb.height)));
+        }
+        Ok(match self.content {
+            Content::Quilt(quilt) => rasterize(quilt, self.vb, self.options),
+            Content::Scene(scene) => scene.rasterize(self.vb, self.options)
+        })
+    }
+}
+
+/// A frame of an animation. Besides the frame itself, it carries the scene it was made
+/// from, before any of its layers were transformed, and the transform of each layer, so
+/// that formats which animate transforms themselves can draw every layer only once.
+pub struct Frame<'a> {
+    pub rendering: Rendering<'a>,
+    pub source: &'a Scene,
+    /// The transform of each layer of `source`, in the order the layers were pushed.
+    pub transforms: &'a [Transform2D]
+}
+
+pub trait ImageDecoder {
+    /// A short human readable name for the format, used in error messages.
+    fn name(&self) -> &'static str;
+    /// Determines whether a file beginning with `magic` is in this format. `magic`
+    /// holds the first 16 bytes of the file, or fewer if the file is shorter.
+    fn sniff(&self, magic: &[u8]) -> bool;
+    fn decode(&self, input: &mut dyn ReadSeek) -> Result<Raster, AnyError>;
+}
+
+pub trait ImageEncoder {
+    /// The name given to `--format` to select this encoder.
+    fn name(&self) -> &'static str;
+    /// File extensions, in lowercase and without the dot, which select this encoder.
+    fn extensions(&self) -> &'static [&'static str];
+    fn encode(&self, output: &mut dyn std::io::Write, rendering: &Rendering) -> Result<(), AnyError>;
+    /// Starts an animation which is written into a single file, for formats which can
+    /// hold one. The frames of an animation in any other format are encoded one file each.
+    fn begin_animation(&self) -> Option<Box<dyn AnimationEncoder>> { None }
+}
+
+/// Writes the frames of an animation into a single file as each one is rendered.
+pub trait AnimationEncoder {
+    /// Appends a frame shown for `delay`. Every frame of an animation has the same size.
+    fn add_frame(&mut self, output: &mut dyn std::io::Write, frame: &Frame, delay: Duration) -> Result<(), AnyError>;
+    /// Checks, before anything is rendered, that the format can show each layer moving
+    /// through `frames`, which holds the transform of every layer in each frame.
+    fn check(&self, _frames: &[Vec<Transform2D>]) -> Result<(), AnyError> { Ok(()) }
+    /// Completes the file after the last frame.
+    fn finish(&mut self, output: &mut dyn std::io::Write) -> Result<(), AnyError>;
+}
+
+/// Options for the encoders which have them, gathered from the command line.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EncoderSettings { pub bmp: BmpWriteOptions, pub pnm_plain: bool, pub pnm_wide: bool, pub gif: GifWriteOptions }
+
+pub struct BmpCodec { pub opts: BmpWriteOptions }
+
+impl ImageDecoder for BmpCodec {
+    fn name(&self) -> &'static str { "BMP" }
+    fn sniff(&self, magic: &[u8]) -> bool { magic.starts_with(b"BM") }
+    fn decode(&self, mut input: &mut dyn ReadSeek) -> Result<Raster, AnyError> {
+        crate::bmp::read_bmp(&mut input)
+    }
+}
+
+impl ImageEncoder for BmpCodec {
+    fn name(&self) -> &'static str { "bmp" }
+    fn extensions(&self) -> &'static [&'static str] { &["bmp", "dib"] }
+    fn encode(&self, mut output: &mut dyn std::io::Write, rendering: &Rendering) -> Result<(), AnyError> {
+        Ok(crate::bmp::write_bmp_with(&mut output, &rendering.rasterize()?, self.opts)?)
+    }
+}
+
+pub struct PngCodec;
+
+impl ImageDecoder for PngCodec {
+    fn name(&self) -> &'static str { "PNG" }
+    fn sniff(&self, magic: &[u8]) -> bool { magic.starts_with(b"\x89PNG\r\n\x1a\n") }
+    fn decode(&self, mut input: &mut dyn ReadSeek) -> Result<Raster, AnyError> {
+        crate::png::read_png(&mut input)
+    }
+}
+
+impl ImageEncoder for PngCodec {
+    fn name(&self) -> &'static str { "png" }
+    fn extensions(&self) -> &'static [&'static str] { &["png"] }
+    fn encode(&self, mut output: &mut dyn std::io::Write, rendering: &Rendering) -> Result<(), AnyError> {
+        Ok(crate::png::write_png(&mut output, &rendering.rasterize()?)?)
+    }
+}
+
+/// A `format` of `None` chooses between PPM and PAM based on the image.
+pub struct PnmCodec { pub format: Option<PnmFormat>, pub plain: bool, pub wide: bool }
+
+impl ImageDecoder for PnmCodec {
+    fn name(&self) -> &'static str { "Netpbm" }
+    fn sniff(&self, magic: &[u8]) -> bool {
+        matches!(magic, [b'P', b'1'..=b'7', ws, ..] if ws.is_ascii_whitespace())
+    }
+    fn decode(&self, mut input: &mut dyn ReadSeek) -> Result<Raster, AnyError> {
+        crate::pnm::read_pnm(&mut input)
+    }
+}
+
+impl ImageEncoder for PnmCodec {
+    fn name(&self) -> &'static str { self.extensions()[0] }
+    fn extensions(&self) -> &'static [&'static str] {
+        match self.format {
+            Some(PnmFormat::Bitmap) => &["pbm"],
+            Some(PnmFormat::Graymap) => &["pgm"],
+            Some(PnmFormat::Pixmap) => &["ppm"],
+            Some(PnmFormat::Pam) => &["pam"],
+            None => &["pnm"]
+        }
+    }
+    fn encode(&self, mut output: &mut dyn std::io::Write, rendering: &Rendering) -> Result<(), AnyError> {
+        let raster = rendering.rasterize()?;
+        match self.format {
+            Some(format) => {
+                let opts = PnmWriteOptions { format, plain: self.plain, wide: self.wide };
+                crate::pnm::write_pnm_with(&mut output, &raster, opts)?
+            },
+            None => crate::pnm::write_pnm(&mut output, &raster)?
+        }
+        Ok(())
+    }
+}
+
+pub struct GifEncoder { pub opts: GifWriteOptions }
+
+impl ImageEncoder for GifEncoder {
+    fn name(&self) -> &'static str { "gif" }
+    fn extensions(&self) -> &'static [&'static str] { &["gif"] }
+    fn encode(&self, mut output: &mut dyn std::io::Write, rendering: &Rendering) -> Result<(), AnyError> {
+        Ok(crate::gif::write_gif(&mut output, &rendering.rasterize()?, self.opts)?)
+    }
+    fn begin_animation(&self) -> Option<Box<dyn AnimationEncoder>> {
+        Some(Box::new(GifWriter::new(self.opts)))
+    }
+}
+
+impl AnimationEncoder for GifWriter {
+    fn add_frame(&mut self, mut output: &mut dyn std::io::Write, frame: &Frame, delay: Duration) -> Result<(), AnyError> {
+        Ok(self.write_frame(&mut output, &frame.rendering.rasterize()?, delay)?)
+    }
+    fn finish(&mut self, mut output: &mut dyn std::io::Write) -> Result<(), AnyError> {
+        Ok(GifWriter::finish(self, &mut output)?)
+    }
+}
+
+pub struct SvgEncoder;
+
+impl ImageEncoder for SvgEncoder {
+    fn name(&self) -> &'static str { "svg" }
+    fn extensions(&self) -> &'static [&'static str] { &["svg"] }
+    fn encode(&self, mut output: &mut dyn std::io::Write, rendering: &Rendering) -> Result<(), AnyError> {
+        match rendering.content {
+            Content::Quilt(quilt) => crate::svg::render_svg(&mut output, quilt, rendering.vb)?,
+            Content::Scene(scene) => crate::svg::render_scene_svg(&mut output, scene, rendering.vb)?
+        }
+        Ok(())
+    }
+    fn begin_animation(&self) -> Option<Box<dyn AnimationEncoder>> {
+        Some(Box::new(SvgAnimation { source: None, vb: None, frames: Vec::new(), delays: Vec::new() }))
+    }
+}
+
+/// Gathers the transforms of every frame, and writes the whole animation once it has
+/// them all, see [`crate::svg::render_animated_svg`].
+struct SvgAnimation { source: Option<Scene>, vb: Option<ViewBox>, frames: Vec<Vec<Transform2D>>, delays: Vec<Duration> }
+
+impl AnimationEncoder for SvgAnimation {
+    fn check(&self, frames: &[Vec<Transform2D>]) -> Result<(), AnyError> {
+        if frames.iter().flatten().all(Transform2D::is_affine) { return Ok(()); }
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            "SVG cannot animate perspective transforms, write the animation as GIF instead").into())
+    }
+    fn add_frame(&mut self, _output: &mut dyn std::io::Write, frame: &Frame, delay: Duration) -> Result<(), AnyError> {
+        if self.source.is_none() {
+            self.source = Some(frame.source.clone());
+            self.vb = Some(frame.rendering.vb.clone());
+        }
+        self.frames.push(frame.transforms.to_vec());
+        self.delays.push(delay);
+        Ok(())
+    }
+    fn finish(&mut self, mut output: &mut dyn std::io::Write) -> Result<(), AnyError> {
+        let (Some(scene), Some(vb)) = (&self.source, &self.vb) else { return Ok(()); };
+        let motion = crate::svg::Motion { frames: &self.frames, delays: &self.delays };
+        Ok(crate::svg::render_animated_svg(&mut output, scene, &motion, vb)?)
+    }
+}
+
+pub struct Registry { decoders: Vec<Box<dyn ImageDecoder>>, encoders: Vec<Box<dyn ImageEncoder>> }
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new(&EncoderSettings::default())
+    }
+}
+
+impl Registry {
+    /// Creates a registry holding every built-in format.
+    pub fn new(settings: &EncoderSettings) -> Self {
+        let mut registry = Registry { decoders: Vec::new(), encoders: Vec::new() };
+        registry.register_decoder(Box::new(BmpCodec { opts: settings.bmp }));
+        registry.register_decoder(Box::new(PngCodec));
+        registry.register_decoder(Box::new(PnmCodec { format: None, plain: false, wide: false }));
+
+        registry.register_encoder(Box::new(BmpCodec { opts: settings.bmp }));
+        registry.register_encoder(Box::new(PngCodec));
+        for format in [None, Some(PnmFormat::Bitmap), Some(PnmFormat::Graymap),
+            Some(PnmFormat::Pixmap), Some(PnmFormat::Pam)]
+        {
+            let (plain, wide) = (settings.pnm_plain, settings.pnm_wide);
+            registry.register_encoder(Box::new(PnmCodec { format, plain, wide }));
+        }
+        registry.register_encoder(Box::new(GifEncoder { opts: settings.gif }));
+        registry.register_encoder(Box::new(SvgEncoder));
+        registry
+    }
+
+    pub fn register_decoder(&mut self, decoder: Box<dyn ImageDecoder>) {
+        self.decoders.push(decoder);
+    }
+
+    pub fn register_encoder(&mut self, encoder: Box<dyn ImageEncoder>) {
+        self.encoders.push(encoder);
+    }
+
+    /// Identifies the format of `input` from its first few bytes and decodes it.
+    pub fn decode(&self, input: &mut dyn ReadSeek) -> Result<Raster, AnyError> {
+        let mut magic: Vec<u8> = Vec::with_capacity(16);
+        std::io::Read::read_to_end(&mut std::io::Read::take(&mut *input, 16), &mut magic)?;
+        input.seek(std::io::SeekFrom::Start(0))?;
+
+        match self.decoders.iter().find(|d| d.sniff(&magic)) {
+            Some(decoder) => decoder.decode(input),
+            None => {
+                let names: Vec<&str> = self.decoders.iter().map(|d| d.name()).collect();
+                Err(AnyError::UnknownFormat(format!(
+                    "unrecognized image format, expected one of {}", names.join(", "))))
+            }
+        }
+    }
+
+    pub fn encoder_named(&self, name: &str) -> Result<&dyn ImageEncoder, AnyError> {
+        let name = name.to_ascii_lowercase();
+        match self.encoders.iter().find(|e| e.name() == name) {
+            Some(encoder) => Ok(encoder.as_ref()),
+            None => Err(AnyError::UnknownFormat(format!(
+                "unknown output format '{}', expected one of {}", name, self.encoder_names())))
+        }
+    }
+
+    /// Chooses an encoder based on the extension of `path`.
+    pub fn encoder_for_path(&self, path: &str) -> Result<&dyn ImageEncoder, AnyError> {
+        let ext = std::path::Path::new(path).extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let found = ext.as_deref().and_then(|ext|
+            self.encoders.iter().find(|e| e.extensions().contains(&ext)));
+        match found {
+            Some(encoder) => Ok(encoder.as_ref()),
+            None => Err(AnyError::UnknownFormat(format!(
+                "cannot infer the output format of '{}' from its extension, use --format with one of {}",
+                path, self.encoder_names())))
+        }
+    }
+
+    fn encoder_names(&self) -> String {
+        let names: Vec<&str> = self.encoders.iter().map(|e| e.name()).collect();
+        names.join(", ")
+    }
+}
@@ -0,0 +1,311 @@
+use crate::matrix::Matrix;
+use crate::matrix::matmul_replace;
+use crate::quilt::Quilt;
+use crate::raster::Raster;
+use crate::raster::Pixel;
+use crate::colorspace::ColorSpace;
+
+// Color transforms
+// A color transform is a 5x5 matrix acting on the column `(r, g, b, a, 1)`, in the same
+// layout as a quilt's `colmat`, with each channel running from 0 to 255. The trailing 1
+// lets the last column hold an offset, so brightening by 50 adds 50 to every channel.
+//
+// A transform may be performed in another working space than sRGB, see `in_space`,
+// in which case every color is converted into that space before the matrix is applied
+// and back into sRGB afterwards. The matrix then sees the channels of that space, so in
+// `ColorSpace::Hsv` adding 120 to the first channel turns every hue by 120 degrees.
+//
+// The arithmetic is done in `f32`, and since most interesting transforms push some
+// results outside 0..=255, `ColorOverflow` decides how they are brought back into a
+// byte. The presets below follow the filter effects of CSS and SVG, which weight the
+// channels by their contribution to perceived brightness (Rec. 709 luma).
+
+/// What happens to a color channel whose transformed value falls outside 0..=255.
+/// Alpha is always clamped, since wrapping or stretching opacity is never wanted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorOverflow {
+    /// Values below 0 become 0 and values above 255 become 255.
+    #[default]
+    Clamp,
+    /// Values wrap around modulo 256, as unsigned byte arithmetic does.
+    Wrap,
+    /// The lowest and highest values over the whole image are stretched to 0 and 255,
+    /// keeping the detail that clamping would flatten.
+    Normalize
+}
+
+/// A sequence of color matrices, each applied in its own working space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorTransform { stages: Vec<Stage>, pub overflow: ColorOverflow }
+
+#[derive(Clone, Debug, PartialEq)]
+struct Stage { space: ColorSpace, matrix: Matrix<f32> }
+
+/// The weights of red, green and blue in the perceived brightness of a color.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+impl ColorTransform {
+    pub fn identity() -> Self {
+        Self { stages: Vec::new(), overflow: ColorOverflow::default() }
+    }
+
+    /// The transform whose matrix is `rows`, acting on `(r, g, b, a, 1)` in sRGB.
+    pub fn from_rows(rows: [[f32; 5]; 5]) -> Self {
+        let stage = Stage { space: ColorSpace::Srgb, matrix: Matrix::literal(rows) };
+        Self { stages: vec![stage], overflow: ColorOverflow::default() }
+    }
+
+    /// The transform acting on `(r, g, b, 1)` by `rows`, leaving alpha untouched.
+    pub fn from_rgb_rows(rows: [[f32; 4]; 4]) -> Self {
+        let [r, g, b, _] = rows;
+        Self::from_rows([
+            [r[0], r[1], r[2], 0.0, r[3]],
+            [g[0], g[1], g[2], 0.0, g[3]],
+            [b[0], b[1], b[2], 0.0, b[3]],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// The transform acting on `(r, g, b)` by the 3x3 matrix `m`.
+    fn linear(m: [[f32; 3]; 3]) -> Self {
+        Self::from_rgb_rows(std::array::from_fn(|i| match m.get(i) {
+            Some(row) => [row[0], row[1], row[2], 0.0],
+            None => [0.0, 0.0, 0.0, 1.0]
+        }))
+    }
+
+    pub fn with_overflow(self, overflow: ColorOverflow) -> Self {
+        Self { overflow, ..self }
+    }
+
+    /// This transform performed in `space` rather than sRGB. Every matrix of a chained
+    /// transform moves to `space`, so choose the space of each part before chaining.
+    pub fn in_space(mut self, space: ColorSpace) -> Self {
+        for stage in &mut self.stages { stage.space = space; }
+        self
+    }
+
+    /// The transform which applies `self` and then `next`, using the overflow of `next`.
+    /// Results are only brought back into range once, at the very end.
+    pub fn then(mut self, next: ColorTransform) -> Self {
+        for stage in next.stages {
+            // Consecutive matrices in the same space multiply into one.
+            match self.stages.last_mut() {
+                Some(last) if last.space == stage.space => last.matrix = &stage.matrix * &last.matrix,
+                _ => self.stages.push(stage)
+            }
+        }
+        Self { stages: self.stages, overflow: next.overflow }
+    }
+
+    /// Replaces every channel `v` with `255 - v`.
+    pub fn negative() -> Self {
+        Self::from_rgb_rows([
+            [-1.0, 0.0, 0.0, 255.0],
+            [0.0, -1.0, 0.0, 255.0],
+            [0.0, 0.0, -1.0, 255.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ])
+    }
+
+    pub fn grayscale() -> Self {
+        Self::saturation(0.0)
+    }
+
+    pub fn sepia() -> Self {
+        Self::linear([
+            [0.393, 0.769, 0.189],
+            [0.349, 0.686, 0.168],
+            [0.272, 0.534, 0.131]
+        ])
+    }
+
+    /// Rearranges the color channels so that output channel `i` is input channel
+    /// `order[i]`, where 0, 1 and 2 are red, green and blue. `[2, 1, 0]` swaps red and
+    /// blue. Panics if an index is greater than 2.
+    pub fn swap_channels(order: [usize; 3]) -> Self {
+        assert!(order.iter().all(|&c| c < 3), "channel index out of range");
+        Self::linear(order.map(|c| std::array::from_fn(|i| if i == c { 1.0 } else { 0.0 })))
+    }
+
+    /// Adds `offset` to every color channel.
+    pub fn brightness(offset: f32) -> Self {
+        Self::from_rgb_rows([
+            [1.0, 0.0, 0.0, offset],
+            [0.0, 1.0, 0.0, offset],
+            [0.0, 0.0, 1.0, offset],
+            [0.0, 0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// Scales the distance of every channel from mid-gray by `factor`, so that 1 leaves
+    /// the image unchanged and 0 turns it entirely gray.
+    pub fn contrast(factor: f32) -> Self {
+        let offset = 127.5 * (1.0 - factor);
+        Self::from_rgb_rows([
+            [factor, 0.0, 0.0, offset],
+            [0.0, factor, 0.0, offset],
+            [0.0, 0.0, factor, offset],
+            [0.0, 0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// Moves every color toward the gray of the same brightness, so that 0 is grayscale,
+    /// 1 leaves the image unchanged and values above 1 oversaturate.
+    pub fn saturation(amount: f32) -> Self {
+        Self::linear(std::array::from_fn(|row| std::array::from_fn(|col| {
+            let identity = if row == col { 1.0 } else { 0.0 };
+            LUMA[col] + amount * (identity - LUMA[col])
+        })))
+    }
+
+    /// Turns every hue around the color wheel by `theta` radians while keeping its
+    /// brightness, so red moves toward yellow and green for small positive angles.
+    pub fn hue_rotate(theta: f32) -> Self {
+        let (s, c) = theta.sin_cos();
+        let [lr, lg, lb] = LUMA;
+        Self::linear([
+            [lr + c * (1.0 - lr) - s * lr, lg - c * lg - s * lg, lb - c * lb + s * (1.0 - lb)],
+            [lr - c * lr + s * 0.143, lg + c * (1.0 - lg) + s * 0.140, lb - c * lb - s * 0.283],
+            [lr - c * lr - s * (1.0 - lr), lg - c * lg + s * lg, lb + c * (1.0 - lb) + s * lb]
+        ])
+    }
+
+    /// Recolors every tile of `quilt`.
+    pub fn apply_to_quilt(&self, quilt: &mut Quilt) {
+        let colors = self.transform(quilt.colmat.map(|&c| f32::from(c)));
+        for (col, color) in colors.into_iter().enumerate() {
+            quilt.colmat.get_col_mut(col)[..4].copy_from_slice(&color);
+        }
+    }
+
+    /// Recolors every pixel of `raster`.
+    pub fn apply_to_raster(&self, raster: &mut Raster) {
+        let mut m: Matrix<f32> = Matrix::new(5, raster.pixels.len());
+        for (col, px) in raster.pixels.iter().enumerate() {
+            m.get_col_mut(col).copy_from_slice(&[px.red, px.green, px.blue, px.alpha, 1].map(f32::from));
+        }
+        for (px, [red, green, blue, alpha]) in raster.pixels.iter_mut().zip(self.transform(m)) {
+            *px = Pixel { red, green, blue, alpha };
+        }
+    }
+
+    /// Transforms every column `(r, g, b, a, 1)` of `colors` and brings the results back
+    /// into bytes according to `overflow`.
+    fn transform(&self, mut colors: Matrix<f32>) -> Vec<[u8; 4]> {
+        for stage in &self.stages {
+            // The colors are already in sRGB, so there is nothing to convert.
+            if stage.space == ColorSpace::Srgb {
+                matmul_replace(&stage.matrix, &mut colors);
+                continue;
+            }
+            convert(&mut colors, |c| stage.space.convert_from_srgb(c));
+            matmul_replace(&stage.matrix, &mut colors);
+            convert(&mut colors, |c| stage.space.convert_to_srgb(c));
+        }
+
+        // Normalizing maps the range of the color channels over the whole image onto
+        // 0..=255, which is then clamped only to absorb rounding. An image of a single
+        // flat color has no range to stretch and is just clamped.
+        let (mut offset, mut scale) = (0.0, 1.0);
+        if self.overflow == ColorOverflow::Normalize {
+            let (mut low, mut high) = (f32::INFINITY, f32::NEG_INFINITY);
+            for col in 0..colors.colc() {
+                for &v in &colors.get_col(col)[..3] {
+                    (low, high) = (low.min(v), high.max(v));
+                }
+            }
+            if high > low { (offset, scale) = (low, 255.0 / (high - low)); }
+        }
+
+        let to_byte = |v: f32| -> u8 {
+            match self.overflow {
+                ColorOverflow::Wrap => v.round().rem_euclid(256.0) as u8,
+                _ => ((v - offset) * scale).round().clamp(0.0, 255.0) as u8
+            }
+        };
+        (0..colors.colc())
+            .map(|col| {
+                let c = colors.get_col(col);
+                [to_byte(c[0]), to_byte(c[1]), to_byte(c[2]), c[3].round().clamp(0.0, 255.0) as u8]
+            })
+            .collect()
+    }
+}
+
+/// Replaces the first three channels of every column of `colors` with `f` of them.
+fn convert(colors: &mut Matrix<f32>, f: impl Fn([f32; 3]) -> [f32; 3]) {
+    for col in 0..colors.colc() {
+        let c = &mut colors.get_col_mut(col)[..3];
+        let converted = f([c[0], c[1], c[2]]);
+        c.copy_from_slice(&converted);
+    }
+}
+
+impl Default for ColorTransform {
+    fn default() -> Self { Self::identity() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(t: &ColorTransform, colors: &[[u8; 4]]) -> Vec<[u8; 4]> {
+        let mut raster = Raster {
+            pixels: colors.iter().map(|&[red, green, blue, alpha]| Pixel { red, green, blue, alpha }).collect(),
+            width: colors.len()
+        };
+        t.apply_to_raster(&mut raster);
+        raster.pixels.iter().map(|p| [p.red, p.green, p.blue, p.alpha]).collect()
+    }
+
+    #[test]
+    fn presets_on_known_pixels() {
+        let colors = [[10, 200, 255, 128], [255, 0, 0, 255], [255, 255, 255, 255]];
+        assert_eq!(apply(&ColorTransform::negative(), &colors), [[245, 55, 0, 128], [0, 255, 255, 255], [0, 0, 0, 255]]);
+        assert_eq!(apply(&ColorTransform::grayscale(), &colors[1..]), [[54, 54, 54, 255], [255, 255, 255, 255]]);
+        assert_eq!(apply(&ColorTransform::sepia(), &colors[2..]), [[255, 255, 239, 255]]);
+        assert_eq!(apply(&ColorTransform::swap_channels([2, 1, 0]), &colors[..1]), [[255, 200, 10, 128]]);
+        assert_eq!(apply(&ColorTransform::contrast(0.0), &colors[..1]), [[128, 128, 128, 128]]);
+        assert_eq!(apply(&ColorTransform::saturation(1.0), &colors), colors);
+        assert_eq!(apply(&ColorTransform::hue_rotate(0.0), &colors), colors);
+    }
+
+    #[test]
+    fn overflow_decides_out_of_range_channels() {
+        let colors = [[10, 220, 255, 255]];
+        let brighter = ColorTransform::brightness(50.0);
+        assert_eq!(apply(&brighter, &colors), [[60, 255, 255, 255]]);
+        assert_eq!(apply(&brighter.clone().with_overflow(ColorOverflow::Wrap), &colors), [[60, 14, 49, 255]]);
+        // 60..=305 is stretched onto 0..=255.
+        assert_eq!(apply(&brighter.with_overflow(ColorOverflow::Normalize), &colors), [[0, 219, 255, 255]]);
+
+        let mut opaque = [[0.0; 5]; 5];
+        for (i, row) in opaque.iter_mut().enumerate() { row[i] = 1.0; }
+        opaque[3][4] = 100.0;
+        let opaque = ColorTransform::from_rows(opaque).with_overflow(ColorOverflow::Wrap);
+        assert_eq!(apply(&opaque, &[[1, 2, 3, 200]]), [[1, 2, 3, 255]]);
+    }
+
+    #[test]
+    fn chaining_matches_applying_in_sequence() {
+        let stages = [
+            ColorTransform::saturation(1.2).in_space(ColorSpace::LinearRgb),
+            ColorTransform::brightness(10.0),
+            ColorTransform::contrast(0.8),
+            ColorTransform::hue_rotate(0.5)
+        ];
+        let chained = stages.iter().cloned().reduce(ColorTransform::then).unwrap();
+        assert_eq!(chained.stages.len(), 2);
+
+        // Muted colors, so that no stage leaves the gamut and clamps in between.
+        let colors: Vec<[u8; 4]> = (0..64u8).map(|i| [90 + i, 140 - i / 2, 110 + i % 7 * 4, 255]).collect();
+        let mut sequential = colors.clone();
+        for stage in &stages { sequential = apply(stage, &sequential); }
+        // Applying the stages one by one rounds to bytes after each of them.
+        for (a, b) in apply(&chained, &colors).iter().zip(&sequential) {
+            assert!(a.iter().zip(b).all(|(x, y)| x.abs_diff(*y) <= 1), "{a:?} != {b:?}");
+        }
+    }
+}
@@ -0,0 +1,243 @@
+// Color spaces
+// Decoded images hold gamma-encoded sRGB, in which a channel value of 128 is nowhere
+// near half as bright as 255. Linear operations such as averaging or scaling brightness
+// are only physically meaningful on linear light, and operations on hue or saturation
+// are simplest in a space which has those as coordinates. `ColorSpace` converts colors
+// out of sRGB into such a working space and back again.
+//
+// Every space is given in the units a color matrix sees, which keep the familiar 0 to
+// 255 range wherever the space has no established units of its own:
+//
+//     Srgb, LinearRgb    red, green and blue from 0 to 255
+//     Hsv, Hsl           hue in degrees from 0 to 360, then saturation and value or
+//                        lightness from 0 to 255
+//     YCbCr601, 709      full-range luma from 0 to 255 and chroma centered on 128, as
+//                        used by JPEG
+//     Xyz                CIE 1931 XYZ relative to the D65 white point, scaled so that
+//                        white has a Y of 255
+//     Lab                CIELAB relative to D65, with L from 0 to 100 and a and b
+//                        roughly from -128 to 127
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    LinearRgb,
+    Hsv,
+    Hsl,
+    /// Y'CbCr with the luma weights of ITU-R BT.601, used for standard definition video.
+    YCbCr601,
+    /// Y'CbCr with the luma weights of ITU-R BT.709, used for high definition video.
+    YCbCr709,
+    Xyz,
+    Lab
+}
+
+impl ColorSpace {
+    /// Converts `rgb`, a gamma-encoded sRGB color with channels from 0 to 255, into
+    /// this space.
+    pub fn convert_from_srgb(self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            ColorSpace::Srgb => rgb,
+            ColorSpace::LinearRgb => rgb.map(|v| 255.0 * srgb_to_linear(v / 255.0)),
+            ColorSpace::Hsv => rgb_to_hsv(rgb),
+            ColorSpace::Hsl => rgb_to_hsl(rgb),
+            ColorSpace::YCbCr601 => rgb_to_ycbcr(rgb, BT601),
+            ColorSpace::YCbCr709 => rgb_to_ycbcr(rgb, BT709),
+            ColorSpace::Xyz => linear_to_xyz(rgb.map(|v| srgb_to_linear(v / 255.0))).map(|v| 255.0 * v),
+            ColorSpace::Lab => xyz_to_lab(linear_to_xyz(rgb.map(|v| srgb_to_linear(v / 255.0))))
+        }
+    }
+
+    /// Converts `c`, a color in this space, back into gamma-encoded sRGB with channels
+    /// from 0 to 255. Colors outside the sRGB gamut come out below 0 or above 255.
+    pub fn convert_to_srgb(self, c: [f32; 3]) -> [f32; 3] {
+        match self {
+            ColorSpace::Srgb => c,
+            ColorSpace::LinearRgb => c.map(|v| 255.0 * linear_to_srgb(v / 255.0)),
+            ColorSpace::Hsv => hsv_to_rgb(c),
+            ColorSpace::Hsl => hsl_to_rgb(c),
+            ColorSpace::YCbCr601 => ycbcr_to_rgb(c, BT601),
+            ColorSpace::YCbCr709 => ycbcr_to_rgb(c, BT709),
+            ColorSpace::Xyz => xyz_to_linear(c.map(|v| v / 255.0)).map(|v| 255.0 * linear_to_srgb(v)),
+            ColorSpace::Lab => xyz_to_linear(lab_to_xyz(c)).map(|v| 255.0 * linear_to_srgb(v))
+        }
+    }
+}
+
+/// Removes the sRGB gamma from a channel between 0 and 1. Values outside that range are
+/// extended symmetrically about 0, so that out-of-gamut colors survive a round trip.
+pub fn srgb_to_linear(v: f32) -> f32 {
+    let a = v.abs();
+    let linear = if a <= 0.04045 { a / 12.92 } else { ((a + 0.055) / 1.055).powf(2.4) };
+    linear.copysign(v)
+}
+
+/// Applies the sRGB gamma to a linear channel between 0 and 1, undoing `srgb_to_linear`.
+pub fn linear_to_srgb(v: f32) -> f32 {
+    let a = v.abs();
+    let encoded = if a <= 0.0031308 { a * 12.92 } else { 1.055 * a.powf(1.0 / 2.4) - 0.055 };
+    encoded.copysign(v)
+}
+
+pub fn rgb_to_hsv(rgb: [f32; 3]) -> [f32; 3] {
+    let (max, min) = (rgb.into_iter().fold(f32::MIN, f32::max), rgb.into_iter().fold(f32::MAX, f32::min));
+    let saturation = if max > 0.0 { 255.0 * (max - min) / max } else { 0.0 };
+    [hue(rgb, max, min), saturation, max]
+}
+
+pub fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let chroma = v * s / 255.0;
+    from_hue(h, chroma, v - chroma)
+}
+
+pub fn rgb_to_hsl(rgb: [f32; 3]) -> [f32; 3] {
+    let (max, min) = (rgb.into_iter().fold(f32::MIN, f32::max), rgb.into_iter().fold(f32::MAX, f32::min));
+    let lightness = (max + min) / 2.0;
+    let denominator = 255.0 - (max + min - 255.0).abs();
+    let saturation = if denominator > 0.0 { 255.0 * (max - min) / denominator } else { 0.0 };
+    [hue(rgb, max, min), saturation, lightness]
+}
+
+pub fn hsl_to_rgb([h, s, l]: [f32; 3]) -> [f32; 3] {
+    let chroma = (255.0 - (2.0 * l - 255.0).abs()) * s / 255.0;
+    from_hue(h, chroma, l - chroma / 2.0)
+}
+
+/// The hue in degrees of `rgb`, whose largest and smallest channels are `max` and `min`.
+/// Grays have no hue and are given 0.
+fn hue([r, g, b]: [f32; 3], max: f32, min: f32) -> f32 {
+    let chroma = max - min;
+    if chroma <= 0.0 { return 0.0; }
+    let sector = if max == r {
+        (g - b) / chroma
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    (60.0 * sector).rem_euclid(360.0)
+}
+
+/// The color of hue `h` degrees with the given chroma, lifted by `m` in every channel.
+fn from_hue(h: f32, chroma: f32, m: f32) -> [f32; 3] {
+    let sector = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x]
+    };
+    [r + m, g + m, b + m]
+}
+
+/// The weights of red and blue in luma. Green takes up the rest.
+#[derive(Clone, Copy)]
+struct LumaWeights { kr: f32, kb: f32 }
+
+const BT601: LumaWeights = LumaWeights { kr: 0.299, kb: 0.114 };
+const BT709: LumaWeights = LumaWeights { kr: 0.2126, kb: 0.0722 };
+
+fn rgb_to_ycbcr([r, g, b]: [f32; 3], LumaWeights { kr, kb }: LumaWeights) -> [f32; 3] {
+    let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+    [y, 128.0 + (b - y) / (2.0 * (1.0 - kb)), 128.0 + (r - y) / (2.0 * (1.0 - kr))]
+}
+
+fn ycbcr_to_rgb([y, cb, cr]: [f32; 3], LumaWeights { kr, kb }: LumaWeights) -> [f32; 3] {
+    let r = y + 2.0 * (1.0 - kr) * (cr - 128.0);
+    let b = y + 2.0 * (1.0 - kb) * (cb - 128.0);
+    let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+    [r, g, b]
+}
+
+/// The linear sRGB primaries in CIE XYZ, for a D65 white of Y = 1.
+const RGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.119192, 0.9503041]
+];
+
+const XYZ_TO_RGB: [[f32; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.969266, 1.8760108, 0.041556],
+    [0.0556434, -0.2040259, 1.0572252]
+];
+
+/// The D65 white point in CIE XYZ.
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+/// Converts linear RGB with channels from 0 to 1 into XYZ.
+pub fn linear_to_xyz(rgb: [f32; 3]) -> [f32; 3] {
+    RGB_TO_XYZ.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2])
+}
+
+pub fn xyz_to_linear(xyz: [f32; 3]) -> [f32; 3] {
+    XYZ_TO_RGB.map(|row| row[0] * xyz[0] + row[1] * xyz[1] + row[2] * xyz[2])
+}
+
+pub fn xyz_to_lab(xyz: [f32; 3]) -> [f32; 3] {
+    let [fx, fy, fz] = std::array::from_fn(|i| lab_f(xyz[i] / WHITE[i]));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+pub fn lab_to_xyz([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let fy = (l + 16.0) / 116.0;
+    let f = [fy + a / 500.0, fy, fy - b / 200.0];
+    std::array::from_fn(|i| WHITE[i] * lab_f_inverse(f[i]))
+}
+
+/// The cube root at the heart of CIELAB, with a linear segment near black so that its
+/// slope stays finite.
+fn lab_f(t: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA { t.cbrt() } else { t / (3.0 * DELTA * DELTA) + 4.0 / 29.0 }
+}
+
+fn lab_f_inverse(f: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if f > DELTA { f * f * f } else { 3.0 * DELTA * DELTA * (f - 4.0 / 29.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 8] = [
+        ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Hsv, ColorSpace::Hsl,
+        ColorSpace::YCbCr601, ColorSpace::YCbCr709, ColorSpace::Xyz, ColorSpace::Lab
+    ];
+
+    fn assert_close(a: [f32; 3], b: [f32; 3], tolerance: f32) {
+        assert!(a.iter().zip(&b).all(|(x, y)| (x - y).abs() <= tolerance), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn every_space_round_trips() {
+        let mut colors = vec![[0.0; 3], [1.0; 3], [128.0; 3], [255.0; 3], [255.0, 0.0, 0.0], [0.0, 255.0, 0.0], [0.0, 0.0, 255.0]];
+        colors.extend((0..50).map(|i: u8| [i * 5, 250 - i * 3, i.wrapping_mul(37)].map(f32::from)));
+        for space in SPACES {
+            for &rgb in &colors {
+                assert_close(space.convert_to_srgb(space.convert_from_srgb(rgb)), rgb, 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn known_coordinates() {
+        let white = [255.0; 3];
+        assert_close(ColorSpace::Hsv.convert_from_srgb([255.0, 0.0, 0.0]), [0.0, 255.0, 255.0], 1e-3);
+        assert_close(ColorSpace::Hsl.convert_from_srgb([0.0, 0.0, 255.0]), [240.0, 255.0, 127.5], 1e-3);
+        // Grays and black have neither hue nor saturation.
+        assert_close(ColorSpace::Hsv.convert_from_srgb([90.0; 3]), [0.0, 0.0, 90.0], 1e-3);
+        assert_close(ColorSpace::Hsl.convert_from_srgb([0.0; 3]), [0.0; 3], 1e-3);
+        assert_close(ColorSpace::Hsl.convert_from_srgb(white), [0.0, 0.0, 255.0], 1e-3);
+        assert_close(ColorSpace::YCbCr601.convert_from_srgb([90.0; 3]), [90.0, 128.0, 128.0], 1e-3);
+        assert_close(ColorSpace::LinearRgb.convert_from_srgb([0.0, 255.0, 187.5]), [0.0, 255.0, 127.5], 0.1);
+        assert_close(ColorSpace::Xyz.convert_from_srgb(white), WHITE.map(|v| 255.0 * v), 0.05);
+        assert_close(ColorSpace::Lab.convert_from_srgb(white), [100.0, 0.0, 0.0], 0.01);
+        assert_close(ColorSpace::Lab.convert_from_srgb([0.0; 3]), [0.0; 3], 1e-3);
+    }
+}
@@ -1,7 +1,19 @@
+use crate::bmp::BmpError;
+use crate::png::PngError;
+use crate::pnm::PnmError;
+
 #[derive(Debug)]
 pub enum AnyError {
     IO(std::io::Error),
-    Text(std::string::FromUtf8Error)
+    Text(std::string::FromUtf8Error),
+    Bmp(BmpError),
+    Png(PngError),
+    Pnm(PnmError),
+    UnknownPreset(String),
+    /// No registered codec handles an input file or output path. Holds the full message.
+    UnknownFormat(String),
+    /// There is nothing to draw into an output. Holds the full message.
+    EmptyImage(String)
 }
 
 impl From<std::io::Error> for AnyError {
@@ -16,3 +28,50 @@ impl From<std::string::FromUtf8Error> for AnyError {
     }
 }
 
+impl From<BmpError> for AnyError {
+    fn from(value: BmpError) -> Self {
+        Self::Bmp(value)
+    }
+}
+
+impl From<PngError> for AnyError {
+    fn from(value: PngError) -> Self {
+        Self::Png(value)
+    }
+}
+
+impl From<PnmError> for AnyError {
+    fn from(value: PnmError) -> Self {
+        Self::Pnm(value)
+    }
+}
+
+impl std::fmt::Display for AnyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnyError::IO(e) => write!(f, "{}", e),
+            AnyError::Text(e) => write!(f, "{}", e),
+            AnyError::Bmp(e) => write!(f, "malformed BMP: {}", e),
+            AnyError::Png(e) => write!(f, "malformed PNG: {}", e),
+            AnyError::Pnm(e) => write!(f, "malformed Netpbm file: {}", e),
+            AnyError::UnknownPreset(name) => write!(f, "unknown preset '{}'", name),
+            AnyError::UnknownFormat(msg) => write!(f, "{}", msg),
+            AnyError::EmptyImage(msg) => write!(f, "{}", msg)
+        }
+    }
+}
+
+impl std::error::Error for AnyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AnyError::IO(e) => Some(e),
+            AnyError::Text(e) => Some(e),
+            AnyError::Bmp(e) => Some(e),
+            AnyError::Png(e) => Some(e),
+            AnyError::Pnm(e) => Some(e),
+            AnyError::UnknownPreset(_) => None,
+            AnyError::UnknownFormat(_) => None,
+            AnyError::EmptyImage(_) => None
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use crate::matrix::Matrix;
+use crate::homography::apply_3x3;
+use crate::homography::invert_3x3;
+use crate::matrix::determinant;
+use crate::matrix::solve;
+use crate::matrix::symmetric_eigen;
+use crate::matrix::SymmetricEigen;
+
+// Transform estimation
+// Often it is known where a few points of an image should land, rather than the
+// matrix which puts them there. The routines here find the affine transform or
+// homography which best explains a set of point correspondences. With more pairs
+// than strictly necessary the fit is least-squares, and RANSAC can be used to
+// ignore pairs which are simply wrong, such as a mismatched feature.
+
+/// A source point paired with the destination it should be transformed to.
+pub type Correspondence = ((f64, f64), (f64, f64));
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Model {
+    /// Six degrees of freedom, needing at least three pairs. Parallel lines stay parallel.
+    Affine,
+    /// Eight degrees of freedom, needing at least four pairs.
+    Homography
+}
+
+impl Model {
+    /// The fewest correspondences which determine a transform of this kind.
+    pub fn min_pairs(self) -> usize {
+        match self {
+            Model::Affine => 3,
+            Model::Homography => 4
+        }
+    }
+}
+
+/// A fitted transform along with how well it fits.
+pub struct Estimate {
+    /// A 3x3 matrix suitable for [`crate::matrix::matmul_replace`] on a quilt's `locmat`.
+    pub matrix: Matrix<f64>,
+    /// The distance between where each source point is transformed to and its destination.
+    pub residuals: Vec<f64>,
+    /// Whether each pair was used in the fit. Only RANSAC rejects any.
+    pub inliers: Vec<bool>
+}
+
+impl Estimate {
+    pub fn inlier_count(&self) -> usize {
+        self.inliers.iter().filter(|&&inlier| inlier).count()
+    }
+
+    /// The root mean square of the residuals of the inliers.
+    pub fn rms_residual(&self) -> f64 {
+        let inlier_residuals = self.residuals.iter().zip(&self.inliers).filter(|(_, &inlier)| inlier);
+        let sum: f64 = inlier_residuals.map(|(r, _)| r * r).sum();
+        (sum / self.inlier_count().max(1) as f64).sqrt()
+    }
+
+    /// The largest residual of the inliers.
+    pub fn max_residual(&self) -> f64 {
+        self.residuals.iter().zip(&self.inliers).filter(|(_, &inlier)| inlier)
+            .map(|(r, _)| *r).fold(0.0, f64::max)
+    }
+}
+
+#[derive(Debug)]
+pub enum EstimateError {
+    TooFewPairs { needed: usize, given: usize },
+    /// The points do not determine a unique transform, for instance because too many
+    /// of them are collinear.
+    Degenerate
+}
+
+impl std::fmt::Display for EstimateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EstimateError::TooFewPairs { needed, given } =>
+                write!(f, "at least {} point pairs are needed but {} were given", needed, given),
+            EstimateError::Degenerate => write!(f, "the points do not determine a unique transform")
+        }
+    }
+}
+
+impl std::error::Error for EstimateError {}
+
+/// Fits a transform of kind `model` to every pair in `pairs` by least squares.
+pub fn estimate(model: Model, pairs: &[Correspondence]) -> Result<Estimate, EstimateError> {
+    let m = fit(model, pairs)?;
+    let residuals = pairs.iter().map(|&pair| residual(&m, pair)).collect();
+    Ok(Estimate { matrix: to_matrix(&m), residuals, inliers: vec![true; pairs.len()] })
+}
+
+pub struct RansacOptions {
+    /// Pairs whose residual exceeds this distance are outliers.
+    pub threshold: f64,
+    pub iterations: usize,
+    /// Seeds the choice of samples, so that a given set of options always gives the same fit.
+    pub seed: u64
+}
+
+impl Default for RansacOptions {
+    fn default() -> Self {
+        Self { threshold: 1.0, iterations: 1000, seed: 0x9E3779B97F4A7C15 }
+    }
+}
+
+/// Fits a transform of kind `model` to `pairs` while ignoring outliers. Each iteration fits
+/// the minimum number of randomly chosen pairs exactly and counts how many others agree
+/// with it. The transform with the most agreement is then refit by least squares to every
+/// pair which agrees with it.
+pub fn estimate_ransac(model: Model, pairs: &[Correspondence], opts: &RansacOptions)
+    -> Result<Estimate, EstimateError>
+{
+    let k = model.min_pairs();
+    if pairs.len() < k {
+        return Err(EstimateError::TooFewPairs { needed: k, given: pairs.len() });
+    }
+
+    let mut rng = XorShift(opts.seed.max(1));
+    let mut best: Option<(usize, f64, Vec<bool>)> = None;
+    let mut sample: Vec<Correspondence> = Vec::with_capacity(k);
+    for _ in 0..opts.iterations {
+        sample.clear();
+        for i in rng.choose(pairs.len(), k) { sample.push(pairs[i]); }
+        let Ok(m) = fit(model, &sample) else { continue; };
+
+        let residuals: Vec<f64> = pairs.iter().map(|&pair| residual(&m, pair)).collect();
+        let inliers: Vec<bool> = residuals.iter().map(|&r| r <= opts.threshold).collect();
+        let count = inliers.iter().filter(|&&inlier| inlier).count();
+        let error: f64 = residuals.iter().filter(|&&r| r <= opts.threshold).sum();
+        let better = match &best {
+            Some((best_count, best_error, _)) => count > *best_count || (count == *best_count && error < *best_error),
+            None => true
+        };
+        if better { best = Some((count, error, inliers)); }
+    }
+
+    let Some((_, _, inliers)) = best else { return Err(EstimateError::Degenerate); };
+    let consensus: Vec<Correspondence> = pairs.iter().zip(&inliers)
+        .filter(|(_, &inlier)| inlier).map(|(&pair, _)| pair).collect();
+    let m = fit(model, &consensus)?;
+    let residuals: Vec<f64> = pairs.iter().map(|&pair| residual(&m, pair)).collect();
+    let inliers = residuals.iter().map(|&r| r <= opts.threshold).collect();
+    Ok(Estimate { matrix: to_matrix(&m), residuals, inliers })
+}
+
+fn fit(model: Model, pairs: &[Correspondence]) -> Result<[[f64; 3]; 3], EstimateError> {
+    if pairs.len() < model.min_pairs() {
+        return Err(EstimateError::TooFewPairs { needed: model.min_pairs(), given: pairs.len() });
+    }
+    match model {
+        Model::Affine => fit_affine(pairs),
+        Model::Homography => fit_homography(pairs)
+    }
+}
+
+/// Solves the normal equations of the least-squares affine fit. The x and y coordinates
+/// of the destination are independent, so each row of the matrix is solved for separately.
+fn fit_affine(pairs: &[Correspondence]) -> Result<[[f64; 3]; 3], EstimateError> {
+    let (t_src, t_dst) = (normalizer(pairs.iter().map(|p| p.0)), normalizer(pairs.iter().map(|p| p.1)));
+    let mut rows = [[0f64; 3]; 2];
+    for (axis, row) in rows.iter_mut().enumerate() {
+        let mut normal: Matrix<f64> = Matrix::new(3, 3);
+        let mut rhs: Matrix<f64> = Matrix::new(3, 1);
+        for &(src, dst) in pairs {
+            let [x, y, _] = apply_3x3(&t_src, src);
+            let target = apply_3x3(&t_dst, dst)[axis];
+            let v = [x, y, 1.0];
+            for j in 0..3 {
+                let col = normal.get_col_mut(j);
+                for i in 0..3 { col[i] += v[i] * v[j]; }
+                rhs.get_col_mut(0)[j] += v[j] * target;
+            }
+        }
+        let solution = solve(&normal, &rhs).map_err(|_| EstimateError::Degenerate)?;
+        row.copy_from_slice(solution.get_col(0));
+    }
+    let normalized = [rows[0], rows[1], [0.0, 0.0, 1.0]];
+    denormalize(&normalized, &t_src, &t_dst)
+}
+
+/// The normalized direct linear transform. Both point sets are first moved and scaled so
+/// that they are centered on the origin with an average distance of √2 from it, which keeps
+/// the system well conditioned. The homography is then the unit vector `h` minimizing |Ah|,
+/// which is the eigenvector of AᵀA with the smallest eigenvalue.
+fn fit_homography(pairs: &[Correspondence]) -> Result<[[f64; 3]; 3], EstimateError> {
+    let (t_src, t_dst) = (normalizer(pairs.iter().map(|p| p.0)), normalizer(pairs.iter().map(|p| p.1)));
+    let mut ata: Matrix<f64> = Matrix::new(9, 9);
+    for &(src, dst) in pairs {
+        let [x, y, _] = apply_3x3(&t_src, src);
+        let [u, v, _] = apply_3x3(&t_dst, dst);
+        let rows = [
+            [0.0, 0.0, 0.0, -x, -y, -1.0, v * x, v * y, v],
+            [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, -u]
+        ];
+        for row in &rows {
+            for j in 0..9 {
+                let col = ata.get_col_mut(j);
+                for i in 0..9 { col[i] += row[i] * row[j]; }
+            }
+        }
+    }
+
+    let SymmetricEigen { values, vectors } = symmetric_eigen(&ata);
+    // A second vanishing eigenvalue means a whole family of homographies fits equally well.
+    let largest = values[8];
+    if largest <= 0.0 || values[1] <= largest * 1e-12 {
+        return Err(EstimateError::Degenerate);
+    }
+    let h = vectors.get_col(0);
+    let normalized = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], h[8]]];
+    denormalize(&normalized, &t_src, &t_dst)
+}
+
+/// A similarity transform which centers `points` on the origin and scales them to an
+/// average distance of √2 from it.
+fn normalizer(points: impl Iterator<Item = (f64, f64)> + Clone) -> [[f64; 3]; 3] {
+    let n = points.clone().count().max(1) as f64;
+    let (cx, cy) = points.clone().fold((0.0, 0.0), |(ax, ay), (x, y)| (ax + x / n, ay + y / n));
+    let mean_dist = points.map(|(x, y)| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt()).sum::<f64>() / n;
+    let s = if mean_dist > 0.0 { std::f64::consts::SQRT_2 / mean_dist } else { 1.0 };
+    [[s, 0.0, -s * cx], [0.0, s, -s * cy], [0.0, 0.0, 1.0]]
+}
+
+/// Undoes the normalization of both point sets, giving `t_dst⁻¹ · m · t_src`, and scales
+/// the result so that its bottom right entry is 1.
+fn denormalize(m: &[[f64; 3]; 3], t_src: &[[f64; 3]; 3], t_dst: &[[f64; 3]; 3])
+    -> Result<[[f64; 3]; 3], EstimateError>
+{
+    let t_dst_inv = invert_3x3(t_dst).ok_or(EstimateError::Degenerate)?;
+    let product = mul_3x3(&t_dst_inv, &mul_3x3(m, t_src));
+    if determinant(&to_matrix(&product)) == 0.0 { return Err(EstimateError::Degenerate); }
+    let scale = product[2][2];
+    if scale.abs() < f64::EPSILON { return Ok(product); }
+    Ok(product.map(|row| row.map(|e| e / scale)))
+}
+
+fn mul_3x3(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    std::array::from_fn(|r| std::array::from_fn(|c| (0..3).map(|k| a[r][k] * b[k][c]).sum()))
+}
+
+fn to_matrix(m: &[[f64; 3]; 3]) -> Matrix<f64> {
+    Matrix::literal(*m)
+}
+
+fn residual(m: &[[f64; 3]; 3], (src, dst): Correspondence) -> f64 {
+    let [x, y, w] = apply_3x3(m, src);
+    if w == 0.0 { return f64::INFINITY; }
+    ((x / w - dst.0).powi(2) + (y / w - dst.1).powi(2)).sqrt()
+}
+
+/// A xorshift generator, which is plenty random enough to draw RANSAC samples.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Chooses `k` distinct indices less than `n`.
+    fn choose(&mut self, n: usize, k: usize) -> Vec<usize> {
+        let mut chosen: Vec<usize> = Vec::with_capacity(k);
+        while chosen.len() < k {
+            let i = (self.next() % n as u64) as usize;
+            if !chosen.contains(&i) { chosen.push(i); }
+        }
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFFINE: [[f64; 3]; 3] = [[2.0, 0.5, 3.0], [-1.0, 1.5, 7.0], [0.0, 0.0, 1.0]];
+    const HOMOGRAPHY: [[f64; 3]; 3] = [[1.2, 0.1, 5.0], [0.05, 0.9, -3.0], [0.001, 0.002, 1.0]];
+
+    fn pairs(m: &[[f64; 3]; 3], sources: &[(f64, f64)]) -> Vec<Correspondence> {
+        sources.iter().map(|&src| {
+            let [x, y, w] = apply_3x3(m, src);
+            (src, (x / w, y / w))
+        }).collect()
+    }
+
+    /// Homographies come from an eigenvector of a system with squared entries, so they are
+    /// only accurate to around the square root of the precision of `f64`.
+    fn assert_recovers(fit: &Estimate, expected: &[[f64; 3]; 3]) {
+        for (r, row) in expected.iter().enumerate() {
+            for (c, e) in row.iter().enumerate() {
+                let got = fit.matrix.get_col(c)[r];
+                assert!((got - e).abs() < 1e-6 * e.abs().max(1.0), "expected {e} at ({r}, {c}), got {got}");
+            }
+        }
+        assert!(fit.max_residual() < 1e-4, "residual {}", fit.max_residual());
+    }
+
+    #[test]
+    fn exact_fits_recover_the_transform() {
+        let fit = estimate(Model::Affine, &pairs(&AFFINE, &[(0.0, 0.0), (4.0, 1.0), (1.0, 5.0)])).unwrap();
+        assert_recovers(&fit, &AFFINE);
+        let square = [(0.0, 0.0), (100.0, 0.0), (100.0, 80.0), (0.0, 80.0)];
+        let fit = estimate(Model::Homography, &pairs(&HOMOGRAPHY, &square)).unwrap();
+        assert_recovers(&fit, &HOMOGRAPHY);
+    }
+
+    #[test]
+    fn collinear_sources_are_degenerate() {
+        let line = [(0.0, 0.0), (1.0, 2.0), (2.0, 4.0), (3.0, 6.0)];
+        assert!(matches!(estimate(Model::Affine, &pairs(&AFFINE, &line[..3])), Err(EstimateError::Degenerate)));
+        assert!(matches!(estimate(Model::Homography, &pairs(&HOMOGRAPHY, &line)), Err(EstimateError::Degenerate)));
+        assert!(matches!(estimate(Model::Homography, &pairs(&HOMOGRAPHY, &line[..3])),
+            Err(EstimateError::TooFewPairs { needed: 4, given: 3 })));
+    }
+
+    #[test]
+    fn ransac_rejects_the_outlier() {
+        let sources: Vec<(f64, f64)> = (0..12).map(|i| (f64::from(i % 4) * 30.0, f64::from(i / 4) * 25.0 + f64::from(i))).collect();
+        for (model, m) in [(Model::Affine, AFFINE), (Model::Homography, HOMOGRAPHY)] {
+            let mut pairs = pairs(&m, &sources);
+            pairs[5].1.0 += 40.0;
+            let fit = estimate_ransac(model, &pairs, &RansacOptions::default()).unwrap();
+            let outliers: Vec<usize> = (0..pairs.len()).filter(|&i| !fit.inliers[i]).collect();
+            assert_eq!(outliers, [5], "{model:?}");
+            assert_recovers(&fit, &m);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// Reconstruction filters
+// When the inverse rasterizer maps an output pixel back onto the quilt it lands
+// between tile centers. A filter decides how the colors of nearby tiles are
+// weighted to produce the color at that point. Every filter here is separable,
+// so the weight of a tile is `weight(dx) * weight(dy)` where `dx` and `dy` are
+// measured in tiles from the sample point to the tile's center.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    /// The color of the tile containing the sample point, unweighted.
+    Nearest,
+    /// Linear interpolation between the four nearest tiles.
+    Bilinear,
+    /// The cubic spline with B = 0 and C = 1/2. Sharp, with slight ringing at edges.
+    CatmullRom,
+    /// The cubic spline with B = C = 1/3, as recommended by Mitchell and Netravali.
+    /// Softer than Catmull-Rom with less ringing.
+    Mitchell,
+    /// A sinc windowed by a wider sinc, spanning three tiles in each direction.
+    Lanczos3
+}
+
+impl Filter {
+    /// The distance from the sample point, in tiles, beyond which `weight` is zero.
+    pub fn support(self) -> f64 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::CatmullRom | Filter::Mitchell => 2.0,
+            Filter::Lanczos3 => 3.0
+        }
+    }
+
+    pub fn weight(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest => if x < 0.5 { 1.0 } else { 0.0 },
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            Filter::CatmullRom => bicubic(x, 0.0, 0.5),
+            Filter::Mitchell => bicubic(x, 1.0 / 3.0, 1.0 / 3.0),
+            Filter::Lanczos3 => if x < 3.0 { sinc(x) * sinc(x / 3.0) } else { 0.0 }
+        }
+    }
+}
+
+/// The Mitchell-Netravali family of cubic splines, evaluated at `x >= 0`.
+fn bicubic(x: f64, b: f64, c: f64) -> f64 {
+    let (x2, x3) = (x * x, x * x * x);
+    let w = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b)
+    } else if x < 2.0 {
+        (-b - 6.0 * c) * x3 + (6.0 * b + 30.0 * c) * x2 + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    w / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 { return 1.0; }
+    let px = std::f64::consts::PI * x;
+    px.sin() / px
+}
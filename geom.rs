@@ -20,29 +20,19 @@ pub fn vec_dif_2d(v1: impl PointLike2D<T = f64>, v2: impl PointLike2D<T = f64>)
 {
     let (v1x, v1y) = v1.to_tuple();
     let (v2x, v2y) = v2.to_tuple();
-    return (v1x - v2x, v1y - v2y);
+    (v1x - v2x, v1y - v2y)
 }
 
 pub fn vec_sum_2d(v1: impl PointLike2D<T = f64>, v2: impl PointLike2D<T = f64>) -> (f64, f64)
 {
     let (v1x, v1y) = v1.to_tuple();
     let (v2x, v2y) = v2.to_tuple();
-    return (v1x + v2x, v1y + v2y);
+    (v1x + v2x, v1y + v2y)
 }
 
 pub fn vec_scale_2d(v1: impl PointLike2D<T = f64>, scalar: f64) -> (f64, f64)
 {
     let (v1x, v1y) = v1.to_tuple();
-    return (v1x * scalar, v1y * scalar);
+    (v1x * scalar, v1y * scalar)
     
 }
-
-pub fn unit_vec(v1: impl PointLike2D<T = f64>) -> (f64, f64)
-{
-    let distance = euclidean_distance_2d((0f64, 0f64), v1.to_tuple());
-    let (v1x, v1y) = v1.to_tuple();
-    return (
-        v1x / distance,
-        v1y / distance
-    )
-}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use crate::raster::Raster;
+use crate::quantize::Quantizer;
+use crate::quantize::histogram;
+use crate::quantize::nearest;
+use crate::zlib::BitWriter;
+
+// Routines for writing GIF files
+// A GIF is a header describing the size of the logical screen followed by any number
+// of images drawn onto it, each optionally preceded by a graphic control extension
+// giving how long it is shown for and which palette index is transparent. An
+// application extension named NETSCAPE2.0 makes viewers loop the sequence.
+//
+// Every frame gets a local palette of its own, chosen by quantizing the frame, see
+// `quantize.rs`, and its pixels are palette indices compressed by the variable-width
+// LZW of the GIF89a specification, which is implemented in `lzw_compress` below.
+
+/// The most codes the LZW dictionary of a GIF can hold, as codes are at most 12 bits.
+const MAX_CODES: usize = 4096;
+
+/// Pixels with an alpha below this are drawn transparent, and the rest opaque.
+const ALPHA_THRESHOLD: u8 = 128;
+
+#[derive(Clone, Copy, Debug)]
+pub struct GifWriteOptions {
+    pub quantizer: Quantizer,
+    /// The number of times an animation plays, where 0 plays it forever.
+    pub loops: u16,
+    /// Whether mostly transparent pixels are written as transparent. Otherwise every
+    /// pixel keeps its color and the alpha channel is dropped.
+    pub transparency: bool
+}
+
+impl Default for GifWriteOptions {
+    fn default() -> Self {
+        Self { quantizer: Quantizer::default(), loops: 0, transparency: true }
+    }
+}
+
+/// Writes the frames of an animated GIF one at a time. The header goes out along with
+/// the first frame, whose size becomes the size of the whole animation.
+pub struct GifWriter { opts: GifWriteOptions, screen: Option<(u16, u16)> }
+
+impl GifWriter {
+    pub fn new(opts: GifWriteOptions) -> Self {
+        Self { opts, screen: None }
+    }
+
+    /// Appends `img` as a frame shown for `delay`, which is rounded to the hundredths of
+    /// a second GIF counts in.
+    pub fn write_frame<W>(&mut self, output: &mut W, img: &Raster, delay: std::time::Duration) -> std::io::Result<()>
+    where W: std::io::Write
+    {
+        let too_large = || std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("a {}x{} image is too large for GIF, which allows at most 65535x65535", img.width(), img.height()));
+        let width = u16::try_from(img.width()).map_err(|_| too_large())?;
+        let height = u16::try_from(img.height()).map_err(|_| too_large())?;
+        let mut bytes = Vec::new();
+        match self.screen {
+            None => {
+                self.write_header(&mut bytes, width, height);
+                self.screen = Some((width, height));
+            },
+            Some(screen) if screen != (width, height) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("a {}x{} frame does not fit a {}x{} animation, every frame of a GIF must have the same size",
+                    width, height, screen.0, screen.1))),
+            Some(_) => {}
+        }
+
+        let transparent = |alpha: u8| self.opts.transparency && alpha < ALPHA_THRESHOLD;
+        let has_transparency = img.pixels.iter().any(|px| transparent(px.alpha));
+        // One palette entry is set aside for transparent pixels, wherever there are any.
+        let colors = if has_transparency { 255 } else { 256 };
+        let hist = histogram(img.pixels.iter()
+            .filter(|px| !transparent(px.alpha))
+            .map(|px| [px.red, px.green, px.blue]));
+        let mut palette = self.opts.quantizer.palette(&hist, colors);
+        // The transparent entry follows the colors, out of reach of opaque pixels.
+        let transparent_index = has_transparency.then_some(palette.len());
+
+        let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+        let indices: Vec<u8> = img.pixels.iter()
+            .map(|px| match transparent_index {
+                Some(index) if transparent(px.alpha) => index as u8,
+                _ => {
+                    let color = [px.red, px.green, px.blue];
+                    *cache.entry(color).or_insert_with(|| nearest(&palette, color) as u8)
+                }
+            })
+            .collect();
+        if has_transparency { palette.push([0, 0, 0]); }
+
+        // The palette is padded up to a power of two with at least two entries.
+        let bits = (usize::BITS - (palette.len().max(2) - 1).leading_zeros()) as u8;
+        palette.resize(1 << bits, [0, 0, 0]);
+
+        // Graphic control extension. Each frame covers the whole screen, so a frame with
+        // transparency has the screen cleared beneath it rather than drawing over the
+        // frame before it.
+        let delay = u16::try_from((delay.as_millis() + 5) / 10).unwrap_or(u16::MAX);
+        let disposal: u8 = if self.opts.transparency { 2 } else { 1 };
+        bytes.extend([0x21, 0xF9, 4, disposal << 2 | u8::from(has_transparency)]);
+        bytes.extend(delay.to_le_bytes());
+        bytes.extend([transparent_index.unwrap_or(0) as u8, 0]);
+
+        // Image descriptor, with a local color table of 2^bits entries.
+        bytes.push(0x2C);
+        for v in [0, 0, width, height] { bytes.extend(v.to_le_bytes()); }
+        bytes.push(0x80 | (bits - 1));
+        bytes.extend(palette.iter().flatten());
+
+        let min_code_size = bits.max(2);
+        bytes.push(min_code_size);
+        for block in lzw_compress(&indices, min_code_size).chunks(255) {
+            bytes.push(block.len() as u8);
+            bytes.extend(block);
+        }
+        bytes.push(0);
+
+        output.write_all(&bytes)
+    }
+
+    /// Writes the trailer which ends the file.
+    pub fn finish<W>(&mut self, output: &mut W) -> std::io::Result<()>
+    where W: std::io::Write
+    {
+        output.write_all(&[0x3B])
+    }
+
+    fn write_header(&self, bytes: &mut Vec<u8>, width: u16, height: u16) {
+        bytes.extend(b"GIF89a");
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        // No global color table, 8 bits per primary, background index 0, square pixels.
+        bytes.extend([0x70, 0, 0]);
+
+        // The NETSCAPE2.0 extension counts repetitions after the first play, where 0
+        // repeats forever. Without it a GIF plays once.
+        if self.opts.loops != 1 {
+            bytes.extend([0x21, 0xFF, 11]);
+            bytes.extend(b"NETSCAPE2.0");
+            bytes.extend([3, 1]);
+            bytes.extend(self.opts.loops.saturating_sub(1).to_le_bytes());
+            bytes.push(0);
+        }
+    }
+}
+
+/// Encodes `img` as a GIF file holding a single image.
+pub fn write_gif<W>(output: &mut W, img: &Raster, opts: GifWriteOptions) -> std::io::Result<()>
+where W: std::io::Write
+{
+    let mut writer = GifWriter::new(GifWriteOptions { loops: 1, ..opts });
+    writer.write_frame(output, img, std::time::Duration::ZERO)?;
+    writer.finish(output)
+}
+
+/// Compresses the palette indices `data`, each less than `2^min_code_size`, into the
+/// packed codes of a GIF image. Codes start one bit wider than `min_code_size` and
+/// widen as the dictionary grows, up to 12 bits, after which the dictionary is cleared.
+pub fn lzw_compress(data: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut output = BitWriter::new();
+    // Maps a code and the index which follows it to the code for the longer string.
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = u32::from(min_code_size) + 1;
+
+    output.bits(u32::from(clear), width);
+    let Some((&first, rest)) = data.split_first() else {
+        output.bits(u32::from(end), width);
+        output.align();
+        return output.output;
+    };
+    let mut prefix = u16::from(first);
+    for &index in rest {
+        if let Some(&code) = dictionary.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        output.bits(u32::from(prefix), width);
+        dictionary.insert((prefix, index), next);
+        next += 1;
+        // The decoder learns each code one step later than the encoder, so the width
+        // grows once the code after the largest the current width can hold is in use.
+        if usize::from(next) > 1 << width && width < 12 {
+            width += 1;
+        }
+        if usize::from(next) == MAX_CODES {
+            output.bits(u32::from(clear), width);
+            dictionary.clear();
+            next = end + 1;
+            width = u32::from(min_code_size) + 1;
+        }
+        prefix = u16::from(index);
+    }
+    output.bits(u32::from(prefix), width);
+    output.bits(u32::from(end), width);
+    output.align();
+    output.output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::Pixel;
+
+    /// Decodes the packed codes of a GIF image, returning the palette indices and the
+    /// number of clear codes along the way.
+    fn lzw_decompress(data: &[u8], min_code_size: u8) -> (Vec<u8>, usize) {
+        let clear = 1usize << min_code_size;
+        let initial: Vec<Vec<u8>> = (0..(clear + 2)).map(|i| vec![i as u8]).collect();
+        let mut dictionary = initial.clone();
+        let mut width = u32::from(min_code_size) + 1;
+        let (mut output, mut clears) = (Vec::new(), 0);
+        let mut prev: Option<Vec<u8>> = None;
+        let mut bit = 0;
+        loop {
+            let code = (0..width).fold(0, |code, i| {
+                let b = bit + i as usize;
+                code | usize::from((data[b / 8] >> (b % 8)) & 1) << i
+            });
+            bit += width as usize;
+            if code == clear {
+                dictionary = initial.clone();
+                width = u32::from(min_code_size) + 1;
+                prev = None;
+                clears += 1;
+                continue;
+            }
+            if code == clear + 1 { break; }
+            let entry = match (dictionary.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) if code == dictionary.len() => [prev.as_slice(), &prev[..1]].concat(),
+                _ => panic!("code {code} is not in the dictionary")
+            };
+            output.extend(&entry);
+            if let Some(prev) = prev.filter(|_| dictionary.len() < MAX_CODES) {
+                dictionary.push([prev.as_slice(), &entry[..1]].concat());
+            }
+            if dictionary.len() == 1 << width && width < 12 { width += 1; }
+            prev = Some(entry);
+        }
+        (output, clears)
+    }
+
+    fn noise(len: usize, modulus: u64) -> Vec<u8> {
+        let mut state = 7u64;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) % modulus) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn lzw_round_trips_across_dictionary_resets() {
+        for (data, min_code_size) in [(noise(30000, 256), 8), (noise(50000, 4), 2), (noise(100000, 2), 2)] {
+            let (decoded, clears) = lzw_decompress(&lzw_compress(&data, min_code_size), min_code_size);
+            assert_eq!(decoded, data);
+            assert!(clears > 1, "the dictionary was never cleared");
+        }
+        for data in [vec![], vec![1], vec![1, 1, 1, 0]] {
+            assert_eq!(lzw_decompress(&lzw_compress(&data, 2), 2), (data, 1));
+        }
+    }
+
+    /// Decodes a single image GIF as written by [`write_gif`].
+    fn decode(bytes: &[u8]) -> Raster {
+        assert_eq!(&bytes[..6], b"GIF89a");
+        let width = usize::from(u16::from_le_bytes([bytes[6], bytes[7]]));
+        let control = &bytes[13..21];
+        assert_eq!(control[..3], [0x21, 0xF9, 4]);
+        let transparent = (control[3] & 1 == 1).then_some(control[6]);
+        let descriptor = &bytes[21..31];
+        let entries = 1 << ((descriptor[9] & 7) + 1);
+        let palette = &bytes[31..(31 + 3 * entries)];
+        let mut at = 31 + 3 * entries;
+        let min_code_size = bytes[at];
+        at += 1;
+        let mut data = Vec::new();
+        while bytes[at] != 0 {
+            let len = usize::from(bytes[at]);
+            data.extend(&bytes[(at + 1)..(at + 1 + len)]);
+            at += 1 + len;
+        }
+        assert_eq!(&bytes[(at + 1)..], [0x3B]);
+
+        let pixels = lzw_decompress(&data, min_code_size).0.iter().map(|&i| match transparent {
+            Some(t) if t == i => Pixel::transparent(),
+            _ => {
+                let c = &palette[(3 * usize::from(i))..];
+                Pixel::rgb(c[0], c[1], c[2])
+            }
+        }).collect();
+        Raster { pixels, width }
+    }
+
+    fn encode(img: &Raster) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_gif(&mut bytes, img, GifWriteOptions::default()).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn few_colors_are_kept_exactly() {
+        // 256 distinct colors, the most a palette holds.
+        let mut img = Raster::solid(Pixel::transparent(), 32, 8);
+        for (i, px) in img.pixels.iter_mut().enumerate() {
+            *px = Pixel::rgb(i as u8, (i * 7) as u8, 255 - i as u8);
+        }
+        assert_eq!(decode(&encode(&img)).pixels, img.pixels);
+
+        // 255 colors and transparency.
+        img.pixels[0] = Pixel { alpha: 10, ..img.pixels[0] };
+        let decoded = decode(&encode(&img));
+        assert_eq!(decoded.pixels[0], Pixel::transparent());
+        assert_eq!(decoded.pixels[1..], img.pixels[1..]);
+    }
+
+    #[test]
+    fn frames_must_match_the_first() {
+        let mut writer = GifWriter::new(GifWriteOptions::default());
+        let mut bytes = Vec::new();
+        let delay = std::time::Duration::from_millis(100);
+        writer.write_frame(&mut bytes, &Raster::solid(Pixel::rgb(1, 2, 3), 4, 3), delay).unwrap();
+        let err = writer.write_frame(&mut bytes, &Raster::solid(Pixel::rgb(1, 2, 3), 3, 4), delay).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::matrix::Matrix;
+use crate::matrix::inverse;
+use crate::matrix::solve;
+
+// Projective transforms
+// A homography is a 3x3 matrix acting on homogeneous coordinates `(x, y, w)`. The
+// point it represents on the plane is `(x / w, y / w)`, so unlike an affine transform
+// a homography can make parallel lines converge, as they do in a photograph taken
+// at an angle. Scaling a homography by any non-zero factor leaves it unchanged.
+
+/// Finds the homography taking each of the four points `src` to the corresponding point
+/// of `dst`. Returns `None` when three points of either set are collinear, in which case
+/// no unique homography exists.
+///
+/// For example, mapping the four corners of a photographed sheet of paper onto the corners
+/// of a rectangle undoes the keystone distortion of the photograph.
+pub fn homography_from_quads(src: &[(f64, f64); 4], dst: &[(f64, f64); 4]) -> Option<Matrix<f64>> {
+    // Fixing the bottom right entry at 1 leaves eight unknowns, and every pair of points
+    // contributes two linear equations in them:
+    //     h11 x + h12 y + h13 - h31 x X - h32 y X = X
+    //     h21 x + h22 y + h23 - h31 x Y - h32 y Y = Y
+    let mut a: Matrix<f64> = Matrix::new(8, 8);
+    let mut b: Matrix<f64> = Matrix::new(8, 1);
+    for (i, (&(x, y), &(tx, ty))) in src.iter().zip(dst.iter()).enumerate() {
+        let rows = [
+            ([x, y, 1.0, 0.0, 0.0, 0.0, -x * tx, -y * tx], tx),
+            ([0.0, 0.0, 0.0, x, y, 1.0, -x * ty, -y * ty], ty)
+        ];
+        for (k, (coefficients, rhs)) in rows.into_iter().enumerate() {
+            let row = 2 * i + k;
+            for (col, c) in coefficients.into_iter().enumerate() { a.get_col_mut(col)[row] = c; }
+            b.get_col_mut(0)[row] = rhs;
+        }
+    }
+    let h = solve(&a, &b).ok()?;
+    let h = h.get_col(0);
+    Some(Matrix::literal([
+        [h[0], h[1], h[2]],
+        [h[3], h[4], h[5]],
+        [h[6], h[7], 1.0]
+    ]))
+}
+
+/// Inverts the 3x3 matrix `m`. Returns `None` when `m` is singular.
+pub fn invert_3x3(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let inv = inverse(&Matrix::literal(*m)).ok()?;
+    Some(std::array::from_fn(|r| std::array::from_fn(|c| inv[(r, c)])))
+}
+
+/// Applies the homography `m` to the point `(x, y)`, returning the resulting homogeneous
+/// coordinate without dividing through by `w`.
+pub fn apply_3x3(m: &[[f64; 3]; 3], (x, y): (f64, f64)) -> [f64; 3] {
+    m.map(|row| row[0] * x + row[1] * y + row[2])
+}
@@ -1,5 +1,4 @@
 #![feature(trait_alias)]
-#![feature(seek_seek_relative)]
 #![feature(ascii_char)]
 #![feature(ascii_char_variants)]
 
@@ -12,197 +11,325 @@ mod viewbox;
 mod raster;
 mod geom;
 mod rasterize;
-
-use bmp::write_bmp;
-use matrix::Matrix;
-use matrix::matmul_replace;
+mod filter;
+mod homography;
+mod estimate;
+mod png;
+mod pnm;
+mod zlib;
+mod cli;
+mod presets;
+mod codec;
+mod transform;
+mod color;
+mod colorspace;
+mod bench;
+mod scene;
+mod animation;
+mod quantize;
+mod gif;
+
+use std::process::ExitCode;
+use matrix::determinant;
+use matrix::decompose_affine;
+use matrix::eigenvalues;
 use quilt::Quilt;
+use raster::Raster;
+use transform::Transform2D;
 use error::AnyError;
+use viewbox::fit_vb;
 use viewbox::ViewBox;
-use rasterize::rasterize_autofit_autoconfig;
-use rasterize::rasterize_autoconfig;
-use svg::render_svg_autofit;
-use svg::render_svg;
+use codec::Registry;
+use codec::Rendering;
+use codec::Content;
+use codec::Frame;
+use cli::Command;
+use cli::PresetJob;
+use cli::TransformJob;
+use presets::PresetContext;
+use scene::Layer;
+use scene::Scene;
+use animation::Animation;
 
 // Entrypoint
-// The textbook problem 4.15 in Coding the Matrix by Philip N. Klein
-// has 6 parts. For each part, a different linear transformation must be
-// applied to the input image. 
-//
-// I have written a procedure for each part and placed them all in this file. 
-// To execute one of these procedures/parts, simply uncomment it in the main procedure
-// below. I reccomend only uncommenting one procedure at a time, as all together they
-// might take a while to complete on slower computers. Then on the command line,
-// cd into this directory and type "cargo run". Cargo is the Rust buildtool,
-// you can get it by installing the Rust toolchain. 
-
-fn main() -> Result<(), AnyError> {
-    part1_identity()?;       // outputs to part1_identity.svg/bmp
-    part2_translating()?;    // outputs to part2_translating.svg/bmp
-    part3_scaling()?;        // outputs to part3_scaling.svg/bmp
-    part4_rotating()?;       // outputs to part4_rotating.svg/bmp
-    part5_reflecting()?;     // outputs to part5_reflecting.svg/bmp
-    part6_colortransform()?; // outputs to part6_colortransform.svg/bmp
-    part7_stretching()?;     // outputs to part7_stretching.svg/bmp
-    Ok(())
-}
-
-
+// See `cli::USAGE` for the command-line interface, or run "cargo run -- --help".
+// The procedures for each part of the textbook problem live in `presets.rs`.
 
-// Procedures/Parts
-// The following procedures correspond to parts 1-6 of Lab 4.15 in Coding the Matrix
-// by Philip N. Klein. To run one of these procedures simply uncomment its invocation in
-// the main procedure above ^^^
-
-fn part1_identity() -> Result<(), AnyError> {
-    let t = Matrix::<f64>::literal([
-        [1.0, 0.0, 0.0],
-        [0.0, 1.0, 0.0],
-        [0.0, 0.0, 1.0]
-    ]);
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    
+    let command = match cli::parse_args(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("laproj: {}", e);
+            eprintln!("Try 'laproj --help' for more information.");
+            return ExitCode::from(2);
+        }
+    };
 
-    let mut q = load_input_img()?;
-    matmul_replace(&t, &mut q.locmat);
+    let result = match command {
+        Command::Help => { print!("{}", cli::USAGE); Ok(()) },
+        Command::ListPresets => {
+            for (name, _) in presets::PRESETS { println!("{}", name); }
+            Ok(())
+        },
+        Command::Presets(job) => run_presets(&job),
+        Command::Transform(job) => run_transform(&job),
+        Command::Benchmark(threads) => { bench::run_benchmarks(threads); Ok(()) }
+    };
 
-    write_output_img(&q, "part1_identity")?;
-    Ok(())
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(AnyError::UnknownPreset(name)) => {
+            eprintln!("laproj: unknown preset '{}', see 'laproj --list-presets'", name);
+            ExitCode::from(2)
+        },
+        Err(e @ AnyError::UnknownFormat(_)) => {
+            eprintln!("laproj: {}", e);
+            ExitCode::from(2)
+        },
+        Err(e) => {
+            eprintln!("laproj: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn part2_translating() -> Result<(), AnyError> {
-    let xoffset: f64 = 250.0; // The horizontal offset to apply to each vertex
-    let yoffset: f64 = 100.0; // The vertical offset to apply to each vertex
-    
-    let t = Matrix::<f64>::literal([
-        [1.0, 0.0, xoffset],
-        [0.0, 1.0, yoffset],
-        [0.0, 0.0, 1.0]
-    ]);
-
-    let mut q = load_input_img()?;
-    matmul_replace(&t, &mut q.locmat);
-
-    // Fix the point (0.0, 0.0) as the physical origin so that the translation is apparent. 
-    // This, as opposed to autofitting the viewbox, which would reverse the translation
-    // by changing the coordinate of the physical origin to be equal to the coordinate of
-    // the topleft-most vertex of the quilt.
-    let vb = ViewBox { 
-        min_x: 0.0, min_y: 0.0, 
-        width: (q.pwidth + 1) as f64 + xoffset,
-        height: (q.pheight + 1) as f64 + yoffset
-    };
-    svg::render_svg(&mut std::fs::File::create("transformed_images/part2_translating.svg")?, &q, &vb)?;
-    write_bmp(&mut std::fs::File::create("transformed_images/part2_translating.bmp")?, 
-        &rasterize_autoconfig(&q, &vb))?;
+fn run_presets(job: &PresetJob) -> Result<(), AnyError> {
+    // Resolve every name before running anything so that a typo in the last
+    // preset does not surface only after the first few have finished.
+    let mut selected = Vec::new();
+    for name in &job.names {
+        if name == "all" {
+            selected.extend(presets::PRESETS.iter().map(|(_, p)| *p));
+            continue;
+        }
+        match presets::find_preset(name) {
+            Some(preset) => selected.push(preset),
+            None => return Err(AnyError::UnknownPreset(name.clone()))
+        }
+    }
 
+    std::fs::create_dir_all(&job.output_dir)?;
+    let ctx = PresetContext { input: &job.input, output_dir: &job.output_dir, raster: &job.raster };
+    for preset in selected { preset(&ctx)?; }
     Ok(())
 }
 
-fn part3_scaling() -> Result<(), AnyError> {
-    let xscale: f64 = 3.0;
-    let yscale: f64 = 1.0;
+fn run_transform(job: &TransformJob) -> Result<(), AnyError> {
+    // Resolve every encoder before doing any work so that a bad format or
+    // extension is reported before the image is loaded and rasterized.
+    let registry = Registry::new(&job.encoders);
+    let mut outputs = Vec::with_capacity(job.outputs.len());
+    for output in &job.outputs {
+        let encoder = match &output.format {
+            Some(name) => registry.encoder_named(name)?,
+            None => registry.encoder_for_path(&output.path)?
+        };
+        outputs.push((&output.path, encoder));
+    }
+
+    for fit in &job.fits {
+        eprintln!("laproj: fit {} of {} point pairs, rms residual {:.4}, max residual {:.4}",
+            fit.inlier_count(), fit.residuals.len(), fit.rms_residual(), fit.max_residual());
+    }
 
-    let t = Matrix::<f64>::literal([
-        [xscale, 0.0, 0.0],
-        [0.0, yscale, 0.0],
-        [0.0, 0.0, 1.0]
-    ]);
+    let mut layers = Vec::with_capacity(job.layers.len());
+    for (i, spec) in job.layers.iter().enumerate() {
+        // Only `--explain` without `--output` can do without the images, and then only
+        // for layers with no transform about their center.
+        let needs_image = !outputs.is_empty() || spec.transforms.iter().any(|t| t.uses_center());
+        let quilt = if needs_image {
+            let mut image = load_input_raster(&spec.input)?;
+            if let Some(color) = &spec.color { color.apply_to_raster(&mut image); }
+            Some(quilt::knit(image))
+        } else {
+            None
+        };
+        let center = quilt.as_ref().map_or((0.0, 0.0), Quilt::center);
+
+        let transform = cli::compose(&spec.transforms, center);
+        if job.explain {
+            if job.layers.len() > 1 { println!("layer {} ({}):", i, spec.input); }
+            explain_transform(&transform);
+        }
+        let Some(q) = quilt.filter(|_| !outputs.is_empty()) else { continue; };
+        let animation = match job.frames {
+            Some(frames) => Animation::new(cli::keyframes(spec, center), center, frames),
+            None => Animation::still(transform)
+        };
+        layers.push((spec, q, animation));
+    }
+    if outputs.is_empty() { return Ok(()); }
 
-    let mut q = load_input_img()?;
-    matmul_replace(&t, &mut q.locmat);
-    write_output_img(&q, "part3_scaling")?;
-    Ok(())    
-}
+    // Layers with fewer keyframes than others hold their last one until the end.
+    let frame_count = layers.iter().map(|(_, _, animation)| animation.frame_count()).max().unwrap_or(1);
+    let mut source = Scene::new();
+    for (spec, quilt, _) in &layers {
+        source.push(Layer { quilt: quilt.clone(), z: spec.z, opacity: spec.opacity, operator: spec.operator, blend: spec.blend });
+    }
+    // The transform of every layer in each frame.
+    let frames: Vec<Vec<Transform2D>> = (0..frame_count)
+        .map(|frame| layers.iter().map(|(_, _, animation)| animation.frame(frame)).collect())
+        .collect();
+
+    // Formats which can hold an animation get every frame in one file, and the others
+    // a numbered file per frame.
+    let mut animations = Vec::new();
+    let mut stills = Vec::new();
+    for &(path, encoder) in &outputs {
+        match encoder.begin_animation().filter(|_| frame_count > 1) {
+            Some(animation) => {
+                // Refused before anything is rendered or any file is created.
+                animation.check(&frames)?;
+                animations.push((path, animation));
+            },
+            None => stills.push((path, encoder))
+        }
+    }
 
-fn part4_rotating() -> Result<(), AnyError> {
-    let theta: f64 = 0.25 * std::f64::consts::PI;
+    let scene_at = |transforms: &[Transform2D]| {
+        let mut scene = Scene::new();
+        for ((spec, quilt, _), transform) in layers.iter().zip(transforms) {
+            let mut q = quilt.clone();
+            transform.apply_threaded(&mut q, job.threads);
+            scene.push(Layer { quilt: q, z: spec.z, opacity: spec.opacity, operator: spec.operator, blend: spec.blend });
+        }
+        scene
+    };
 
-    let t = Matrix::<f64>::literal([
-       [theta.cos(), -1.0 * theta.sin(), 0.0],
-       [theta.sin(), theta.cos(), 0.0],
-       [0.0, 0.0, 1.0]  
-    ]);
+    // Every frame of an animation shares the viewbox fitting all of them, so that the
+    // picture holds still while the quilts move within it.
+    let shared_vb = match (&job.viewbox, frame_count) {
+        (Some(vb), _) => Some(vb.clone()),
+        (None, 1) => None,
+        (None, _) => frames.iter().map(|transforms| scene_at(transforms).fit_vb())
+            .reduce(|a, b| Ok(a?.union(&b?))).transpose()?
+    };
 
-    let mut q = load_input_img()?;
-    matmul_replace(&t, &mut q.locmat);
-    write_output_img(&q, "part4_rotating")?;
+    // Animations are also encoded in memory and only written once they are complete.
+    let mut animations: Vec<(&String, Vec<u8>, _)> = animations.into_iter()
+        .map(|(path, animation)| (path, Vec::new(), animation))
+        .collect();
+
+    for (frame, transforms) in frames.iter().enumerate() {
+        let scene = scene_at(transforms);
+        let vb = match &shared_vb {
+            Some(vb) => vb.clone(),
+            None => scene.fit_vb()?
+        };
+
+        // A lone image drawn plainly is rendered as it always was rather than as a scene.
+        let content = match scene.single_quilt() {
+            Some(q) => Content::Quilt(q),
+            None => Content::Scene(&scene)
+        };
+        let rendering = Rendering { content, vb: &vb, options: &job.raster };
+        for &(path, encoder) in &stills {
+            let path = if frame_count > 1 { numbered_path(path, frame, frame_count) } else { path.clone() };
+            // Encode before creating the file, so that a failure leaves no broken file behind.
+            let mut bytes = Vec::new();
+            encoder.encode(&mut bytes, &rendering)?;
+            std::fs::write(path, bytes)?;
+        }
+        let frame = Frame { rendering, source: &source, transforms };
+        for (_, bytes, animation) in &mut animations {
+            animation.add_frame(bytes, &frame, job.frame_delay)?;
+        }
+    }
+    for (path, mut bytes, mut animation) in animations {
+        animation.finish(&mut bytes)?;
+        std::fs::write(path, bytes)?;
+    }
     Ok(())
 }
 
-fn part5_reflecting() -> Result<(), AnyError> {
-    // // reflects across the y-axis (negates x value)
-    // let reflect_y = Matrix::<f64>::literal([
-    //     [-1.0, 0.0, 0.0],
-    //     [0.0, 1.0, 0.0],
-    //     [0.0, 0.0, 1.0]
-    // ]);
-
-    // reflects across x-axis (negates y value)
-    let reflect_x = Matrix::<f64>::literal([
-        [1.0, 0.0, 0.0],
-        [0.0, -1.0, 0.0],
-        [0.0, 0.0, 1.0] 
-    ]);
-
-    let mut q = load_input_img()?;
-    matmul_replace(&reflect_x, &mut q.locmat);
-    write_output_img(&q, "part5_reflecting")?;
-    Ok(())
+/// `path` with the number of frame `frame` of `count` inserted before its extension, as
+/// in `frame-07.png`. Numbers are padded to the same width so that the files sort in order.
+fn numbered_path(path: &str, frame: usize, count: usize) -> String {
+    let width = (count - 1).to_string().len();
+    let p = std::path::Path::new(path);
+    let stem = p.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let name = match p.extension() {
+        Some(ext) => format!("{}-{:0width$}.{}", stem, frame, ext.to_string_lossy()),
+        None => format!("{}-{:0width$}", stem, frame)
+    };
+    p.with_file_name(name).to_string_lossy().into_owned()
 }
 
-fn part6_colortransform() -> Result<(), AnyError> {
-    let mut q = load_input_img()?;
-
-    // Colors are 8-bit unsigned integers but we'll need signed
-    // integers to do the computation. 
-    let mut im_mat: Matrix<i16> = q.colmat.map(|byte| i16::from(*byte));
-    
-    let negative_coloring = Matrix::<i16>::literal([
-        [-1, 0, 0, 255],
-        [0, -1, 0, 255],
-        [0, 0, -1, 255],
-        [0, 0, 0, 1]
-    ]);
-
-    matmul_replace(&negative_coloring, &mut im_mat);
-    q.colmat = im_mat.map(|i| u8::try_from(*i).unwrap());
-    write_output_img(&q, "part6_colortransform")?;
-    Ok(())
-}
+/// Prints the matrix of `transform` along with what it does to the plane.
+fn explain_transform(transform: &Transform2D) {
+    let m = transform.matrix();
+    println!("matrix:");
+    for line in format!("{m:.4}").lines() { println!("  {line}"); }
+
+    let det = determinant(m);
+    let orientation = if det < 0.0 { "reverses orientation" } else { "preserves orientation" };
+    println!("determinant: {:.4} ({})", det, orientation);
+
+    match decompose_affine(m) {
+        Some(parts) => {
+            println!("translation: {:.4}, {:.4}", parts.translation.0, parts.translation.1);
+            println!("rotation: {:.4} degrees", parts.rotation.to_degrees());
+            println!("scale: {:.4}, {:.4}", parts.scale.0, parts.scale.1);
+            println!("shear: {:.4}", parts.shear);
+            println!("reflection: {}", if parts.reflection { "yes" } else { "no" });
+            println!("principal scales: {:.4}, {:.4}", parts.principal_scales.0, parts.principal_scales.1);
+        },
+        None => println!("perspective: the bottom row is not 0, 0, 1, so the transform is not affine")
+    }
 
-fn part7_stretching() -> Result<(), AnyError> {
-    let mut q = load_input_img()?;
+    match eigenvalues(m) {
+        Some(values) => {
+            let values: Vec<String> = values.into_iter()
+                .map(|(re, im)| match im {
+                    0.0 => format!("{:.4}", re),
+                    _ => format!("{:.4}{:+.4}i", re, im)
+                })
+                .collect();
+            println!("eigenvalues: {}", values.join(", "));
+        },
+        None => println!("eigenvalues: unknown, the QR algorithm did not converge")
+    }
 
-    let t: Matrix<f64> = Matrix::literal([
-        [1.0, 0.0, 0.0],
-        [-1.0, 1.0, 0.0],
-        [0.0, 0.0, 1.0]
-    ]);
+    match transform.inverse() {
+        Ok(inverse) => {
+            println!("inverse:");
+            for line in format!("{:.4}", inverse.matrix()).lines() { println!("  {line}"); }
+        },
+        Err(_) => println!("inverse: none, the transform collapses the plane onto a line or point")
+    }
+}
 
-    matmul_replace(&t, &mut q.locmat);
-    write_output_img(&q, "part7_stretching")?;
-    Ok(())
+fn load_input_img(path: &str) -> Result<Quilt, AnyError> {
+    let q = quilt::knit(load_input_raster(path)?);
+    Ok(q)
 }
 
+fn load_input_raster(path: &str) -> Result<Raster, AnyError> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    Registry::default().decode(&mut file).map_err(|e| match e {
+        AnyError::UnknownFormat(msg) => AnyError::UnknownFormat(format!("{}: {}", path, msg)),
+        e => e
+    })
+}
 
-fn load_input_img() -> Result<Quilt, AnyError> {
-    let mut file = std::fs::File::open("input.bmp")?;
-    let image = bmp::read_bmp(&mut file)?;
-    let q = quilt::knit(image);
-    Ok(q)
+fn write_output_img(quilt: &Quilt, ctx: &PresetContext, name: &str) -> Result<(), AnyError> {
+    write_output_img_vb(quilt, &fit_vb(quilt)?, ctx, name)
 }
 
-fn write_output_img(quilt: &Quilt, name: &str) -> Result<(), std::io::Error> {
-    // SVG
-    {
-        let file_name = format!("transformed_images/{}.svg", name);
-        let mut file = std::fs::File::create(file_name)?;
-        render_svg_autofit(&mut file, &quilt)?;
-    }  
-    // BMP
-    {
-        let file_name = format!("transformed_images/{}.bmp", name);
-        let mut file = std::fs::File::create(file_name)?;
-        write_bmp(&mut file, &rasterize_autofit_autoconfig(&quilt))?;
+/// Writes `{name}.svg` and `{name}.bmp` into the output directory of `ctx`, both showing
+/// the region `vb`.
+fn write_output_img_vb(quilt: &Quilt, vb: &ViewBox, ctx: &PresetContext, name: &str) -> Result<(), AnyError> {
+    let registry = Registry::default();
+    let rendering = Rendering { content: Content::Quilt(quilt), vb, options: ctx.raster };
+    for ext in ["svg", "bmp"] {
+        let path = std::path::Path::new(ctx.output_dir).join(format!("{}.{}", name, ext));
+        let encoder = registry.encoder_for_path(&path.to_string_lossy())?;
+        // Encode before creating the file, so that a failure leaves no broken file behind.
+        let mut bytes = Vec::new();
+        encoder.encode(&mut bytes, &rendering)?;
+        std::fs::write(path, bytes)?;
     }
     Ok(())
-} 
+}
@@ -14,8 +14,8 @@ impl<T> Matrix<T> where T: Clone {
     pub fn literal<const M: usize, const N: usize>(v: [[T; N]; M]) -> Self {
         let mut content: Vec<T> = Vec::with_capacity(M * N);
         for n in 0..N {
-            for m in 0..M {
-                content.push(v[m][n].clone());
+            for row in &v {
+                content.push(row[n].clone());
             }
         }
         Self { content, rowc: M }
@@ -37,7 +37,7 @@ impl<T> Matrix<T> {
     /// and discarding this matrix.
     pub fn transpose(mut self) -> Self {
         let (src_rowc, src_colc) = (self.rowc(), self.colc());
-        let (dst_rowc, dst_colc) = (src_colc, src_rowc);
+        let dst_rowc = src_colc;
 
         let size = self.content.len();
         let mut content = Vec::with_capacity(size);
@@ -98,16 +98,66 @@ pub fn dot<T>(a: &[T], b: &[T]) -> T where T: DotProduct {
     for i in 0..a.len() {
         dp = dp + a[i] * b[i]
     }
-    return dp;
+    dp
 }
 
 
+// Matrix multiplication
+// `matmul` picks between two kernels. Transforms are usually a small matrix applied to
+// a very wide one, such as a 3x3 applied to a quilt's `locmat` with a column for every
+// vertex, so that case gets a kernel which keeps the rows of `left` on hand and streams
+// through `right` once. Every other product is computed a column of the output at a
+// time as a sum of the columns of `left`, in blocks small enough to stay in cache.
+// Both kernels add the terms of each entry in the same order as `dot`, so they agree
+// exactly with `matmul_naive`.
+
+/// The largest `left`, in rows and columns, multiplied by the small-left kernel. Large
+/// enough for the 5x5 color transforms applied to a quilt's `colmat`.
+const SMALL_LEFT: usize = 5;
+
+/// The number of rows and of inner terms per block of the blocked kernel. A block of
+/// `left` then occupies 128 KiB of `f64`s.
+const BLOCK: usize = 128;
+
+/// Computes `left * right` into `output`, which must already have the shape of the
+/// product.
 pub fn matmul<T>(left: &Matrix<T>, right: &Matrix<T>, output: &mut Matrix<T>)
 where T: DotProduct
 {
-    assert!(left.colc() == right.rowc());
-    assert!(output.rowc() == left.rowc());
-    assert!(output.colc() == right.colc());  
+    assert_product_shape(left, right, output);
+    matmul_cols(left, &right.content, &mut output.content);
+}
+
+/// As `matmul` but splits the columns of `right` and `output` into `threads` chunks and
+/// multiplies each chunk on its own thread. A `threads` of 0 or 1 multiplies on the
+/// calling thread.
+pub fn matmul_threaded<T>(left: &Matrix<T>, right: &Matrix<T>, output: &mut Matrix<T>, threads: usize)
+where T: DotProduct + Send + Sync
+{
+    assert_product_shape(left, right, output);
+    let colc = right.colc();
+    if threads <= 1 || colc < 2 || output.rowc() == 0 {
+        return matmul_cols(left, &right.content, &mut output.content);
+    }
+
+    let chunk_colc = colc.div_ceil(threads.min(colc));
+    let (right_chunk, output_chunk) = (chunk_colc * right.rowc(), chunk_colc * output.rowc());
+    let right_chunks = right.content.chunks(right_chunk);
+    let output_chunks = output.content.chunks_mut(output_chunk);
+    std::thread::scope(|scope| {
+        for (right_cols, output_cols) in right_chunks.zip(output_chunks) {
+            scope.spawn(move || matmul_cols(left, right_cols, output_cols));
+        }
+    });
+}
+
+/// The straightforward implementation of `matmul`, which transposes `left` and takes the
+/// dot product of every row of `left` with every column of `right`. Kept as a baseline
+/// for `--benchmark`.
+pub fn matmul_naive<T>(left: &Matrix<T>, right: &Matrix<T>, output: &mut Matrix<T>)
+where T: DotProduct
+{
+    assert_product_shape(left, right, output);
 
     let left_t = left.clone().transpose(); 
     
@@ -127,3 +177,1010 @@ where T: DotProduct + Default
     matmul(left, right, &mut intermediate);
     *right = intermediate;
 }
+
+/// As `matmul_replace` but multiplies on `threads` threads, see `matmul_threaded`.
+pub fn matmul_replace_threaded<T>(left: &Matrix<T>, right: &mut Matrix<T>, threads: usize)
+where T: DotProduct + Send + Sync
+{
+    assert!(left.rowc() == right.rowc());
+    let mut intermediate: Matrix<T> = Matrix::new(left.rowc(), right.colc());
+    matmul_threaded(left, right, &mut intermediate, threads);
+    *right = intermediate;
+}
+
+/// The small-left kernel for a `left` of `R` rows and `K` columns, given as the
+/// row-major `rows`.
+fn matmul_small<T, const R: usize, const K: usize>(rows: &[T], right_cols: &[T], output_cols: &mut [T])
+where T: DotProduct
+{
+    let rows: [[T; K]; R] = std::array::from_fn(|i| std::array::from_fn(|k| rows[i * K + k]));
+    for (right_col, output_col) in right_cols.chunks_exact(K).zip(output_cols.chunks_exact_mut(R)) {
+        let right_col: &[T; K] = right_col.try_into().unwrap();
+        for (row, out) in rows.iter().zip(output_col.iter_mut()) {
+            *out = dot(row, right_col);
+        }
+    }
+}
+
+fn assert_product_shape<T>(left: &Matrix<T>, right: &Matrix<T>, output: &Matrix<T>) {
+    assert!(left.colc() == right.rowc());
+    assert!(output.rowc() == left.rowc());
+    assert!(output.colc() == right.colc());
+}
+
+/// Multiplies `left` by the run of whole columns `right_cols`, writing the product into
+/// the corresponding columns `output_cols`.
+fn matmul_cols<T>(left: &Matrix<T>, right_cols: &[T], output_cols: &mut [T])
+where T: DotProduct
+{
+    let (rowc, inner) = (left.rowc(), left.colc());
+    if rowc == 0 { return; }
+    if inner == 0 {
+        output_cols.fill(T::default());
+        return;
+    }
+
+    if rowc <= SMALL_LEFT && inner <= SMALL_LEFT {
+        let rows: Vec<T> = left.clone().transpose().content;
+        // Fixing the lengths of the common shapes at compile time lets every dot
+        // product be unrolled.
+        return match (rowc, inner) {
+            (3, 3) => matmul_small::<T, 3, 3>(&rows, right_cols, output_cols),
+            (5, 5) => matmul_small::<T, 5, 5>(&rows, right_cols, output_cols),
+            _ => {
+                for (right_col, output_col) in right_cols.chunks_exact(inner).zip(output_cols.chunks_exact_mut(rowc)) {
+                    for (row, out) in rows.chunks_exact(inner).zip(output_col.iter_mut()) {
+                        *out = dot(row, right_col);
+                    }
+                }
+            }
+        };
+    }
+
+    output_cols.fill(T::default());
+    for row_start in (0..rowc).step_by(BLOCK) {
+        let row_end = (row_start + BLOCK).min(rowc);
+        for k_start in (0..inner).step_by(BLOCK) {
+            let k_end = (k_start + BLOCK).min(inner);
+            for (right_col, output_col) in right_cols.chunks_exact(inner).zip(output_cols.chunks_exact_mut(rowc)) {
+                let output_block = &mut output_col[row_start..row_end];
+                for (k, &scale) in right_col.iter().enumerate().take(k_end).skip(k_start) {
+                    let left_block = &left.get_col(k)[row_start..row_end];
+                    for (out, &l) in output_block.iter_mut().zip(left_block) {
+                        *out = *out + l * scale;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Arithmetic
+// Operator overloads for composing transforms. The operators panic when the shapes
+// of their operands disagree, just as `matmul` does; the `checked_` methods return
+// the mismatch as an error instead.
+
+/// The shapes of two matrices were incompatible with the operation applied to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DimensionMismatch {
+    pub operation: &'static str,
+    pub left: (usize, usize),
+    pub right: (usize, usize)
+}
+
+impl std::fmt::Display for DimensionMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot {} a {}x{} matrix and a {}x{} matrix", self.operation,
+            self.left.0, self.left.1, self.right.0, self.right.1)
+    }
+}
+
+impl std::error::Error for DimensionMismatch {}
+
+impl<T> Matrix<T> {
+    pub fn shape(&self) -> (usize, usize) { (self.rowc(), self.colc()) }
+
+    /// Iterates over the entries of row `i` from left to right.
+    pub fn get_row(&self, i: usize) -> impl Iterator<Item = &T> {
+        assert!(i < self.rowc());
+        self.content.iter().skip(i).step_by(self.rowc)
+    }
+
+    fn mismatch(&self, operation: &'static str, other: &Matrix<T>) -> DimensionMismatch {
+        DimensionMismatch { operation, left: self.shape(), right: other.shape() }
+    }
+}
+
+/// The rows or columns a matrix was to be built from do not make up a rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    /// There are no rows or columns, or the first of them is empty. `line` is `"row"` or
+    /// `"column"`.
+    Empty { line: &'static str },
+    /// The `index`th row or column has `found` entries, where the first has `expected`.
+    Ragged { line: &'static str, index: usize, expected: usize, found: usize }
+}
+
+impl std::fmt::Display for StackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackError::Empty { line } =>
+                write!(f, "cannot build a matrix from no {}s, or from empty ones", line),
+            StackError::Ragged { line, index, expected, found } =>
+                write!(f, "{} {} has {} entries, but {} 0 has {}", line, index, found, line, expected)
+        }
+    }
+}
+
+impl std::error::Error for StackError {}
+
+impl<T> Matrix<T> where T: Clone {
+    /// Builds a matrix from a slice of equal-length rows. Fails when the rows are ragged
+    /// or there are none.
+    pub fn from_rows(rows: &[Vec<T>]) -> Result<Self, StackError> {
+        Ok(Self::stack(rows, "row")?.transpose())
+    }
+
+    /// Builds a matrix from a slice of equal-length columns. Fails when the columns are
+    /// ragged or there are none.
+    pub fn from_cols(cols: &[Vec<T>]) -> Result<Self, StackError> {
+        Self::stack(cols, "column")
+    }
+
+    /// Builds a matrix whose columns are `lines`, reporting errors in terms of `line`.
+    fn stack(lines: &[Vec<T>], line: &'static str) -> Result<Self, StackError> {
+        // A matrix with no rows or no columns has no shape to speak of, as its number of
+        // columns is worked out from its number of rows.
+        let expected = lines.first().map_or(0, Vec::len);
+        if expected == 0 { return Err(StackError::Empty { line }); }
+        if let Some((index, ragged)) = lines.iter().enumerate().find(|(_, l)| l.len() != expected) {
+            return Err(StackError::Ragged { line, index, expected, found: ragged.len() });
+        }
+        Ok(Matrix { content: lines.concat(), rowc: expected })
+    }
+}
+
+impl<T> Matrix<T> where T: Clone + Default + From<u8> {
+    /// Builds the `n` by `n` identity matrix. Panics if `n` is zero, as a matrix without
+    /// rows has no shape to speak of.
+    pub fn identity(n: usize) -> Self {
+        assert!(n > 0, "the identity matrix must have at least one row");
+        let mut m: Matrix<T> = Matrix::new(n, n);
+        for i in 0..n { m[(i, i)] = T::from(1); }
+        m
+    }
+}
+
+impl<T> Matrix<T> where T: DotProduct {
+    /// Computes the product `self * rhs`, or reports that the number of columns of
+    /// `self` differs from the number of rows of `rhs`.
+    pub fn checked_mul(&self, rhs: &Matrix<T>) -> Result<Matrix<T>, DimensionMismatch> {
+        if self.colc() != rhs.rowc() { return Err(self.mismatch("multiply", rhs)); }
+        let mut output = Matrix::new(self.rowc(), rhs.colc());
+        matmul(self, rhs, &mut output);
+        Ok(output)
+    }
+
+    pub fn checked_add(&self, rhs: &Matrix<T>) -> Result<Matrix<T>, DimensionMismatch> {
+        self.zip_with("add", rhs, |a, b| a + b)
+    }
+
+    pub fn checked_sub(&self, rhs: &Matrix<T>) -> Result<Matrix<T>, DimensionMismatch>
+    where T: std::ops::Sub<Output = T>
+    {
+        self.zip_with("subtract", rhs, |a, b| a - b)
+    }
+
+    fn zip_with(&self, operation: &'static str, rhs: &Matrix<T>, f: impl Fn(T, T) -> T)
+        -> Result<Matrix<T>, DimensionMismatch>
+    {
+        if self.shape() != rhs.shape() { return Err(self.mismatch(operation, rhs)); }
+        let content = self.content.iter().zip(&rhs.content).map(|(&a, &b)| f(a, b)).collect();
+        Ok(Matrix { content, rowc: self.rowc })
+    }
+}
+
+impl<T> std::ops::Index<(usize, usize)> for Matrix<T> {
+    type Output = T;
+    fn index(&self, (row, col): (usize, usize)) -> &T {
+        assert!(row < self.rowc());
+        &self.get_col(col)[row]
+    }
+}
+
+impl<T> std::ops::IndexMut<(usize, usize)> for Matrix<T> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut T {
+        assert!(row < self.rowc());
+        &mut self.get_col_mut(col)[row]
+    }
+}
+
+/// Implements a binary operator for every combination of owned and borrowed operands
+/// by forwarding to the given `checked_` method.
+macro_rules! impl_matrix_op {
+    ($trait:ident, $method:ident, $checked:ident $(, $bound:path)?) => {
+        impl<T> std::ops::$trait<&Matrix<T>> for &Matrix<T> where T: DotProduct $(+ $bound)? {
+            type Output = Matrix<T>;
+            fn $method(self, rhs: &Matrix<T>) -> Matrix<T> {
+                self.$checked(rhs).unwrap_or_else(|e| panic!("{e}"))
+            }
+        }
+
+        impl<T> std::ops::$trait<Matrix<T>> for Matrix<T> where T: DotProduct $(+ $bound)? {
+            type Output = Matrix<T>;
+            fn $method(self, rhs: Matrix<T>) -> Matrix<T> { (&self).$method(&rhs) }
+        }
+
+        impl<T> std::ops::$trait<&Matrix<T>> for Matrix<T> where T: DotProduct $(+ $bound)? {
+            type Output = Matrix<T>;
+            fn $method(self, rhs: &Matrix<T>) -> Matrix<T> { (&self).$method(rhs) }
+        }
+
+        impl<T> std::ops::$trait<Matrix<T>> for &Matrix<T> where T: DotProduct $(+ $bound)? {
+            type Output = Matrix<T>;
+            fn $method(self, rhs: Matrix<T>) -> Matrix<T> { self.$method(&rhs) }
+        }
+    };
+}
+
+impl_matrix_op!(Mul, mul, checked_mul);
+impl_matrix_op!(Add, add, checked_add);
+impl_matrix_op!(Sub, sub, checked_sub, std::ops::Sub<Output = T>);
+
+impl<T> std::ops::Neg for &Matrix<T> where T: std::ops::Neg<Output = T> + Copy {
+    type Output = Matrix<T>;
+    fn neg(self) -> Matrix<T> { self.map(|&x| -x) }
+}
+
+impl<T> std::ops::Neg for Matrix<T> where T: std::ops::Neg<Output = T> + Copy {
+    type Output = Matrix<T>;
+    fn neg(self) -> Matrix<T> { -&self }
+}
+
+impl std::ops::Mul<f64> for &Matrix<f64> {
+    type Output = Matrix<f64>;
+    fn mul(self, scalar: f64) -> Matrix<f64> { self.map(|&x| x * scalar) }
+}
+
+impl std::ops::Mul<f64> for Matrix<f64> {
+    type Output = Matrix<f64>;
+    fn mul(self, scalar: f64) -> Matrix<f64> { &self * scalar }
+}
+
+impl std::ops::Mul<&Matrix<f64>> for f64 {
+    type Output = Matrix<f64>;
+    fn mul(self, m: &Matrix<f64>) -> Matrix<f64> { m * self }
+}
+
+impl std::ops::Mul<Matrix<f64>> for f64 {
+    type Output = Matrix<f64>;
+    fn mul(self, m: Matrix<f64>) -> Matrix<f64> { &m * self }
+}
+
+impl<T> PartialEq for Matrix<T> where T: PartialEq {
+    fn eq(&self, other: &Self) -> bool {
+        self.rowc == other.rowc && self.content == other.content
+    }
+}
+
+/// Prints one row per line with the columns right-aligned, honouring the precision of
+/// the format string, so `{:.3}` rounds every entry to three decimal places.
+impl<T> std::fmt::Display for Matrix<T> where T: std::fmt::Display {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cells = self.map(|x| match f.precision() {
+            Some(p) => format!("{x:.p$}"),
+            None => format!("{x}")
+        });
+        let widths: Vec<usize> = (0..cells.colc())
+            .map(|c| cells.get_col(c).iter().map(String::len).max().unwrap_or(0))
+            .collect();
+        for r in 0..cells.rowc() {
+            if r > 0 { writeln!(f)?; }
+            write!(f, "[")?;
+            for (cell, width) in cells.get_row(r).zip(&widths) {
+                write!(f, " {cell:>width$}")?;
+            }
+            write!(f, " ]")?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> std::fmt::Debug for Matrix<T> where T: std::fmt::Debug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows: Vec<Vec<&T>> = (0..self.rowc()).map(|r| self.get_row(r).collect()).collect();
+        f.debug_struct("Matrix")
+            .field("rowc", &self.rowc())
+            .field("colc", &self.colc())
+            .field("rows", &rows)
+            .finish()
+    }
+}
+
+// Linear algebra
+// Routines for square systems of equations, all built on the LU decomposition with
+// partial pivoting. These are only defined for `Matrix<f64>`.
+
+#[derive(Debug)]
+pub struct SingularMatrix;
+
+impl std::fmt::Display for SingularMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "matrix is singular")
+    }
+}
+
+impl std::error::Error for SingularMatrix {}
+
+/// The factorization `PA = LU` of a square matrix `A`, where `P` is a permutation, `L` is
+/// lower triangular with a unit diagonal and `U` is upper triangular. `L` and `U` are stored
+/// together in `lu`, leaving the unit diagonal of `L` implicit.
+pub struct Lu {
+    lu: Matrix<f64>,
+    /// Row `i` of `PA` is row `perm[i]` of `A`.
+    perm: Vec<usize>,
+    /// `-1` if `P` is an odd permutation, and `1` otherwise.
+    parity: f64,
+    singular: bool
+}
+
+/// Computes the LU decomposition of the square matrix `a`. At each step the row with
+/// the largest entry in the pivot column is swapped into place, which keeps the
+/// factorization stable.
+pub fn lu(a: &Matrix<f64>) -> Lu {
+    let n = a.rowc();
+    assert!(a.colc() == n);
+    let mut lu = a.clone();
+    let mut perm: Vec<usize> = (0..n).collect();
+    let mut parity = 1.0;
+    let mut singular = false;
+
+    // Pivots no larger than this are indistinguishable from rounding error.
+    let max_abs = lu.content.iter().fold(0f64, |acc, e| acc.max(e.abs()));
+    let tolerance = max_abs * n as f64 * f64::EPSILON;
+    let at = |row: usize, col: usize| col * n + row;
+
+    for k in 0..n {
+        let p = (k..n).max_by(|&i, &j| lu.content[at(i, k)].abs().total_cmp(&lu.content[at(j, k)].abs()))
+            .unwrap();
+        if lu.content[at(p, k)].abs() <= tolerance || !lu.content[at(p, k)].is_finite() {
+            singular = true;
+            continue;
+        }
+        if p != k {
+            for col in 0..n { lu.content.swap(at(k, col), at(p, col)); }
+            perm.swap(k, p);
+            parity = -parity;
+        }
+        let pivot = lu.content[at(k, k)];
+        for i in (k + 1)..n {
+            let factor = lu.content[at(i, k)] / pivot;
+            lu.content[at(i, k)] = factor;
+            for j in (k + 1)..n {
+                lu.content[at(i, j)] -= factor * lu.content[at(k, j)];
+            }
+        }
+    }
+
+    Lu { lu, perm, parity, singular }
+}
+
+impl Lu {
+    pub fn determinant(&self) -> f64 {
+        if self.singular { return 0.0; }
+        let n = self.lu.rowc();
+        (0..n).map(|i| self.lu.content[i * n + i]).product::<f64>() * self.parity
+    }
+
+    /// Solves `AX = B` for `X`, where every column of `b` is a right-hand side.
+    pub fn solve(&self, b: &Matrix<f64>) -> Result<Matrix<f64>, SingularMatrix> {
+        let n = self.lu.rowc();
+        assert!(b.rowc() == n);
+        if self.singular { return Err(SingularMatrix); }
+        let at = |row: usize, col: usize| col * n + row;
+        let lu = &self.lu.content;
+
+        let mut x: Matrix<f64> = Matrix::new(n, b.colc());
+        for c in 0..b.colc() {
+            let (rhs, out) = (b.get_col(c), x.get_col_mut(c));
+            // Forward substitution through L, applying P on the way in.
+            for i in 0..n {
+                let sum: f64 = (0..i).map(|k| lu[at(i, k)] * out[k]).sum();
+                out[i] = rhs[self.perm[i]] - sum;
+            }
+            // Back substitution through U.
+            for i in (0..n).rev() {
+                let sum: f64 = ((i + 1)..n).map(|k| lu[at(i, k)] * out[k]).sum();
+                out[i] = (out[i] - sum) / lu[at(i, i)];
+            }
+        }
+        Ok(x)
+    }
+
+    pub fn inverse(&self) -> Result<Matrix<f64>, SingularMatrix> {
+        self.solve(&Matrix::identity(self.lu.rowc()))
+    }
+}
+
+/// Computes the determinant of the square matrix `a`. For a transform of the plane its
+/// sign tells whether the transform preserves orientation: it is negative exactly when
+/// the transform involves a reflection.
+pub fn determinant(a: &Matrix<f64>) -> f64 {
+    lu(a).determinant()
+}
+
+/// Computes the inverse of the square matrix `a`, failing if `a` is singular.
+pub fn inverse(a: &Matrix<f64>) -> Result<Matrix<f64>, SingularMatrix> {
+    lu(a).inverse()
+}
+
+/// Solves `AX = B` for `X`, where every column of `b` is a right-hand side, failing if
+/// `a` is singular.
+pub fn solve(a: &Matrix<f64>, b: &Matrix<f64>) -> Result<Matrix<f64>, SingularMatrix> {
+    lu(a).solve(b)
+}
+
+// Decompositions
+// Factorizations which explain what a matrix does: QR separates a rotation from a
+// triangular remainder, the SVD separates two rotations from a pure stretch, and the
+// eigendecomposition finds the directions a matrix only scales. They are written for
+// the small dense matrices of this project, favouring clarity over speed.
+
+/// The factorization `A = QR` of an `m` by `n` matrix, where `Q` is an `m` by `m`
+/// orthogonal matrix and `R` is `m` by `n` and upper triangular.
+pub struct Qr { pub q: Matrix<f64>, pub r: Matrix<f64> }
+
+/// Computes the QR decomposition of `a` by Householder reflections. Each reflection zeroes
+/// the entries below the diagonal in one column of `R`, and `Q` accumulates the reflections.
+pub fn qr(a: &Matrix<f64>) -> Qr {
+    let (m, n) = (a.rowc(), a.colc());
+    let mut r = a.clone();
+    let mut q = Matrix::identity(m);
+
+    for k in 0..n.min(m.saturating_sub(1)) {
+        // The reflection across the hyperplane orthogonal to `v` maps `x` onto a multiple
+        // of the k-th basis vector. The sign of `alpha` avoids cancellation in `v[0]`.
+        let x = &r.get_col(k)[k..];
+        let norm = x.iter().map(|e| e * e).sum::<f64>().sqrt();
+        if norm == 0.0 { continue; }
+        let alpha = if x[0] > 0.0 { -norm } else { norm };
+        let mut v: Vec<f64> = x.to_vec();
+        v[0] -= alpha;
+        let v_norm = v.iter().map(|e| e * e).sum::<f64>().sqrt();
+        if v_norm == 0.0 { continue; }
+        v.iter_mut().for_each(|e| *e /= v_norm);
+
+        for j in k..n {
+            let col = &mut r.get_col_mut(j)[k..];
+            let d = 2.0 * dot(&v, col);
+            col.iter_mut().zip(&v).for_each(|(e, vi)| *e -= d * vi);
+        }
+        for row in 0..m {
+            let d = 2.0 * (0..v.len()).map(|i| q[(row, k + i)] * v[i]).sum::<f64>();
+            for (i, vi) in v.iter().enumerate() {
+                q[(row, k + i)] -= d * vi;
+            }
+        }
+        for i in (k + 1)..m { r[(i, k)] = 0.0; }
+    }
+
+    Qr { q, r }
+}
+
+/// The eigendecomposition `A = V diag(values) Vᵀ` of a symmetric matrix, with the
+/// eigenvalues in ascending order and the corresponding unit eigenvectors as the
+/// columns of `vectors`.
+pub struct SymmetricEigen { pub values: Vec<f64>, pub vectors: Matrix<f64> }
+
+/// Diagonalizes the symmetric matrix `a` by cyclic Jacobi rotations. Each rotation zeroes
+/// one off-diagonal entry, and a sweep over all of them shrinks the rest, so that after a
+/// handful of sweeps only the eigenvalues remain on the diagonal.
+pub fn symmetric_eigen(a: &Matrix<f64>) -> SymmetricEigen {
+    let n = a.rowc();
+    assert!(a.colc() == n);
+    let mut a = a.clone();
+    let mut v = Matrix::identity(n);
+
+    for _ in 0..100 {
+        let total: f64 = a.content.iter().map(|e| e * e).sum();
+        let diagonal: f64 = (0..n).map(|i| a[(i, i)].powi(2)).sum();
+        if total - diagonal <= total * 1e-30 { break; }
+
+        for p in 0..n {
+            for q in (p + 1)..n {
+                let apq = a[(p, q)];
+                if apq == 0.0 { continue; }
+                // The rotation by θ in the (p, q) plane which zeroes a[p][q].
+                let theta = (a[(q, q)] - a[(p, p)]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                rotate_cols(&mut a, p, q, c, s);
+                rotate_rows(&mut a, p, q, c, s);
+                rotate_cols(&mut v, p, q, c, s);
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[(i, i)].total_cmp(&a[(j, j)]));
+    let values = order.iter().map(|&i| a[(i, i)]).collect();
+    let mut vectors: Matrix<f64> = Matrix::new(n, n);
+    for (dst, &src) in order.iter().enumerate() {
+        vectors.get_col_mut(dst).copy_from_slice(v.get_col(src));
+    }
+    SymmetricEigen { values, vectors }
+}
+
+/// Replaces columns `p` and `q` of `m` with `c·p - s·q` and `s·p + c·q`.
+fn rotate_cols(m: &mut Matrix<f64>, p: usize, q: usize, c: f64, s: f64) {
+    for row in 0..m.rowc() {
+        let (mp, mq) = (m[(row, p)], m[(row, q)]);
+        m[(row, p)] = c * mp - s * mq;
+        m[(row, q)] = s * mp + c * mq;
+    }
+}
+
+/// Replaces rows `p` and `q` of `m` with `c·p - s·q` and `s·p + c·q`.
+fn rotate_rows(m: &mut Matrix<f64>, p: usize, q: usize, c: f64, s: f64) {
+    for col in 0..m.colc() {
+        let (mp, mq) = (m[(p, col)], m[(q, col)]);
+        m[(p, col)] = c * mp - s * mq;
+        m[(q, col)] = s * mp + c * mq;
+    }
+}
+
+/// The most QR steps [`eigenvalues`] takes to split off each row, which is far more than
+/// it ever needs short of a matrix built to defeat it.
+pub const QR_MAX_ITERATIONS: usize = 500;
+
+/// Computes the eigenvalues of the square matrix `a`, which need not be symmetric, as
+/// `(real, imaginary)` pairs. Complex eigenvalues come in conjugate pairs.
+///
+/// This is the shifted QR algorithm: the matrix is repeatedly factored and multiplied
+/// back together in the opposite order, which preserves its eigenvalues while driving
+/// it towards upper triangular form. Whenever the last row or last two rows split off
+/// from the rest, their eigenvalues are read off and the search continues on the rest.
+/// Returns `None` if some row fails to split off within [`QR_MAX_ITERATIONS`].
+pub fn eigenvalues(a: &Matrix<f64>) -> Option<Vec<(f64, f64)>> {
+    let n = a.rowc();
+    assert!(a.colc() == n);
+    let mut values: Vec<(f64, f64)> = Vec::with_capacity(n);
+    let mut a = hessenberg(a);
+    let scale = a.content.iter().fold(0f64, |acc, e| acc.max(e.abs())).max(f64::MIN_POSITIVE);
+    let negligible = |x: f64| x.abs() <= scale * 1e-14;
+
+    let mut size = n;
+    let mut iterations = 0;
+    while size > 0 {
+        if size == 1 {
+            values.push((a[(0, 0)], 0.0));
+            break;
+        }
+        let last = size - 1;
+        if negligible(a[(last, last - 1)]) {
+            values.push((a[(last, last)], 0.0));
+            size -= 1;
+            iterations = 0;
+            continue;
+        }
+        if size == 2 || negligible(a[(last - 1, last - 2)]) {
+            values.extend(eigenvalues_2x2(a[(last - 1, last - 1)], a[(last - 1, last)],
+                a[(last, last - 1)], a[(last, last)]));
+            size -= 2;
+            iterations = 0;
+            continue;
+        }
+        // The trailing block has not split off, so its eigenvalues are not yet those of
+        // the matrix.
+        if iterations >= QR_MAX_ITERATIONS { return None; }
+
+        // The Wilkinson shift: whichever eigenvalue of the trailing 2x2 block is nearer
+        // its bottom right entry, or that entry itself if the block's are complex. Every
+        // tenth iteration the shift is perturbed to break cycles.
+        let corner = a[(last, last)];
+        let mut shift = match eigenvalues_2x2(a[(last - 1, last - 1)], a[(last - 1, last)],
+            a[(last, last - 1)], corner)
+        {
+            [(l1, 0.0), (l2, 0.0)] => if (l1 - corner).abs() < (l2 - corner).abs() { l1 } else { l2 },
+            _ => corner
+        };
+        if iterations % 10 == 9 { shift += a[(last, last - 1)].abs(); }
+
+        let mut active: Matrix<f64> = Matrix::new(size, size);
+        for col in 0..size {
+            for row in 0..size {
+                let shifted = if row == col { shift } else { 0.0 };
+                active[(row, col)] = a[(row, col)] - shifted;
+            }
+        }
+        let Qr { q, r } = qr(&active);
+        let active = &r * &q;
+        for col in 0..size {
+            for row in 0..size {
+                let shifted = if row == col { shift } else { 0.0 };
+                a[(row, col)] = active[(row, col)] + shifted;
+            }
+        }
+        iterations += 1;
+    }
+
+    values.reverse();
+    Some(values)
+}
+
+/// Reduces the square matrix `a` to upper Hessenberg form, with zeros below the first
+/// subdiagonal, by Householder similarity transforms, which leave its eigenvalues alone.
+/// The QR algorithm preserves this form, so a row can be tested for having split off from
+/// the rest by looking at a single subdiagonal entry.
+fn hessenberg(a: &Matrix<f64>) -> Matrix<f64> {
+    let n = a.rowc();
+    let mut a = a.clone();
+    for k in 0..n.saturating_sub(2) {
+        let x = &a.get_col(k)[(k + 1)..];
+        let norm = x.iter().map(|e| e * e).sum::<f64>().sqrt();
+        if norm == 0.0 { continue; }
+        let alpha = if x[0] > 0.0 { -norm } else { norm };
+        let mut v: Vec<f64> = x.to_vec();
+        v[0] -= alpha;
+        let v_norm = v.iter().map(|e| e * e).sum::<f64>().sqrt();
+        if v_norm == 0.0 { continue; }
+        v.iter_mut().for_each(|e| *e /= v_norm);
+
+        // H = I - 2vvᵀ acts on rows and columns k+1 onwards, applied as HAH.
+        for col in 0..n {
+            let slice = &mut a.get_col_mut(col)[(k + 1)..];
+            let d = 2.0 * dot(&v, slice);
+            slice.iter_mut().zip(&v).for_each(|(e, vi)| *e -= d * vi);
+        }
+        for row in 0..n {
+            let d = 2.0 * v.iter().enumerate().map(|(i, vi)| a[(row, k + 1 + i)] * vi).sum::<f64>();
+            for (i, vi) in v.iter().enumerate() {
+                a[(row, k + 1 + i)] -= d * vi;
+            }
+        }
+        for row in (k + 2)..n { a[(row, k)] = 0.0; }
+    }
+    a
+}
+
+/// The eigenvalues of the 2x2 matrix `[[a, b], [c, d]]`.
+fn eigenvalues_2x2(a: f64, b: f64, c: f64, d: f64) -> [(f64, f64); 2] {
+    let half_trace = (a + d) / 2.0;
+    let discriminant = ((a - d) / 2.0).powi(2) + b * c;
+    if discriminant >= 0.0 {
+        let root = discriminant.sqrt();
+        [(half_trace + root, 0.0), (half_trace - root, 0.0)]
+    } else {
+        let root = (-discriminant).sqrt();
+        [(half_trace, root), (half_trace, -root)]
+    }
+}
+
+/// The thin singular value decomposition `A = U diag(sigma) Vᵀ` of an `m` by `n` matrix,
+/// where `k = min(m, n)`, `U` is `m` by `k` and `V` is `n` by `k`, both with orthonormal
+/// columns, and the singular values `sigma` are in descending order.
+pub struct Svd { pub u: Matrix<f64>, pub sigma: Vec<f64>, pub v: Matrix<f64> }
+
+/// Computes the singular value decomposition of `a` by one-sided Jacobi rotations. Pairs of
+/// columns are rotated until every column is orthogonal to every other. The columns then
+/// have lengths `sigma` and directions `U`, while the rotations themselves accumulate in `V`.
+pub fn svd(a: &Matrix<f64>) -> Svd {
+    if a.rowc() < a.colc() {
+        let Svd { u, sigma, v } = svd(&a.clone().transpose());
+        return Svd { u: v, sigma, v: u };
+    }
+    let n = a.colc();
+    let mut u = a.clone();
+    let mut v = Matrix::identity(n);
+
+    for _ in 0..100 {
+        let mut rotated = false;
+        for p in 0..n {
+            for q in (p + 1)..n {
+                let alpha = dot(u.get_col(p), u.get_col(p));
+                let beta = dot(u.get_col(q), u.get_col(q));
+                let gamma = dot(u.get_col(p), u.get_col(q));
+                if gamma.abs() <= f64::EPSILON * (alpha * beta).sqrt() || gamma == 0.0 { continue; }
+                rotated = true;
+                let zeta = (beta - alpha) / (2.0 * gamma);
+                let t = zeta.signum() / (zeta.abs() + (zeta * zeta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                rotate_cols(&mut u, p, q, c, s);
+                rotate_cols(&mut v, p, q, c, s);
+            }
+        }
+        if !rotated { break; }
+    }
+
+    let norms: Vec<f64> = (0..n).map(|j| dot(u.get_col(j), u.get_col(j)).sqrt()).collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+
+    let mut sorted_u: Matrix<f64> = Matrix::new(u.rowc(), n);
+    let mut sorted_v: Matrix<f64> = Matrix::new(n, n);
+    for (dst, &src) in order.iter().enumerate() {
+        let norm = norms[src];
+        for (e, x) in sorted_u.get_col_mut(dst).iter_mut().zip(u.get_col(src)) {
+            *e = if norm > 0.0 { x / norm } else { 0.0 };
+        }
+        sorted_v.get_col_mut(dst).copy_from_slice(v.get_col(src));
+    }
+    let sigma = order.iter().map(|&i| norms[i]).collect();
+    Svd { u: sorted_u, sigma, v: sorted_v }
+}
+
+/// The polar decomposition `A = R S` of a square matrix, where `R` is a rotation and `S`
+/// is symmetric. `S` stretches along perpendicular axes and `R` turns the result, much as
+/// a complex number is a magnitude turned by an angle.
+pub struct Polar { pub rotation: Matrix<f64>, pub stretch: Matrix<f64> }
+
+/// Computes the polar decomposition of the square matrix `a` from its singular value
+/// decomposition `U diag(sigma) Vᵀ`, as `R = U Vᵀ` and `S = V diag(sigma) Vᵀ`. When `a`
+/// reverses orientation, `U Vᵀ` would be a reflection rather than a rotation, so the
+/// reflection is moved into `S` along its weakest axis, which leaves `S` with a negative
+/// eigenvalue instead of `R` with a negative determinant.
+pub fn polar(a: &Matrix<f64>) -> Polar {
+    assert_eq!(a.rowc(), a.colc(), "polar decomposition of a non-square matrix");
+    let Svd { mut u, mut sigma, v } = svd(a);
+    let n = a.rowc();
+    if n > 0 && determinant(&(&u * &v.clone().transpose())) < 0.0 {
+        for x in u.get_col_mut(n - 1) { *x = -*x; }
+        sigma[n - 1] = -sigma[n - 1];
+    }
+
+    let vt = v.clone().transpose();
+    let mut scaled = v;
+    for (j, s) in sigma.iter().enumerate() {
+        for x in scaled.get_col_mut(j) { *x *= s; }
+    }
+    Polar { rotation: &u * &vt, stretch: &scaled * &vt }
+}
+
+/// The parts of an affine transform of the plane. Applied to a point, the transform first
+/// scales by `scale`, then shears x by `shear` times y, then reflects across the x-axis if
+/// `reflection` is set, then rotates counterclockwise by `rotation` radians (clockwise on
+/// screen, where y points down), and finally translates by `translation`.
+#[derive(Clone, Copy, Debug)]
+pub struct AffineDecomposition {
+    pub translation: (f64, f64),
+    pub rotation: f64,
+    pub scale: (f64, f64),
+    pub shear: f64,
+    pub reflection: bool,
+    /// The largest and smallest factors by which the transform stretches any direction,
+    /// which are the singular values of its linear part.
+    pub principal_scales: (f64, f64)
+}
+
+/// Decomposes the 3x3 affine matrix `m` into a translation, rotation, reflection, shear and
+/// scale by way of the QR decomposition of its linear part. Returns `None` if the bottom row
+/// of `m` is not `0, 0, 1`, in which case `m` is projective rather than affine. A singular
+/// linear part is reported with a zero scale factor and no shear.
+pub fn decompose_affine(m: &Matrix<f64>) -> Option<AffineDecomposition> {
+    assert!(m.rowc() == 3 && m.colc() == 3);
+    if m[(2, 0)] != 0.0 || m[(2, 1)] != 0.0 || m[(2, 2)] != 1.0 { return None; }
+
+    let linear = Matrix::literal([[m[(0, 0)], m[(0, 1)]], [m[(1, 0)], m[(1, 1)]]]);
+    let Qr { mut q, mut r } = qr(&linear);
+
+    // Flip signs so that the diagonal of R is non-negative. This leaves the product QR alone.
+    for i in 0..2 {
+        if r[(i, i)] < 0.0 {
+            for j in 0..2 { r[(i, j)] = -r[(i, j)]; }
+            for j in 0..2 { q[(j, i)] = -q[(j, i)]; }
+        }
+    }
+    // Q is now a rotation, or a rotation following a reflection across the x-axis.
+    let reflection = determinant(&q) < 0.0;
+    if reflection {
+        for j in 0..2 { q[(j, 1)] = -q[(j, 1)]; }
+    }
+
+    let (sx, sy) = (r[(0, 0)], r[(1, 1)]);
+    let shear = if sy != 0.0 { r[(0, 1)] / sy } else { 0.0 };
+    let sigma = svd(&linear).sigma;
+    Some(AffineDecomposition {
+        translation: (m[(0, 2)], m[(1, 2)]),
+        rotation: q[(1, 0)].atan2(q[(0, 0)]),
+        scale: (sx, sy),
+        shear,
+        reflection,
+        principal_scales: (sigma[0], sigma[1])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &Matrix<f64>, expected: &Matrix<f64>) {
+        assert_eq!(actual.shape(), expected.shape());
+        let error = actual.content.iter().zip(&expected.content).fold(0f64, |acc, (a, e)| acc.max((a - e).abs()));
+        assert!(error < 1e-9, "expected\n{expected}\ngot\n{actual}");
+    }
+
+    fn sample() -> Matrix<f64> {
+        Matrix::literal([[4.0, -2.0, 1.0], [3.0, 6.0, -4.0], [2.0, 1.0, 8.0]])
+    }
+
+    #[test]
+    fn inverse_undoes_multiplication() {
+        let a = sample();
+        let inv = inverse(&a).unwrap();
+        assert_close(&(&a * &inv), &Matrix::identity(3));
+        assert_close(&(&inv * &a), &Matrix::identity(3));
+    }
+
+    #[test]
+    fn solve_leaves_no_residual() {
+        let a = sample();
+        let b = Matrix::literal([[1.0, 0.5], [-2.0, 3.0], [7.0, -1.0]]);
+        let x = solve(&a, &b).unwrap();
+        assert_close(&(&a * &x), &b);
+    }
+
+    #[test]
+    fn determinant_of_reflection_is_negative() {
+        let flip = Matrix::literal([[1.0, 0.0], [0.0, -1.0]]);
+        assert!((determinant(&flip) + 1.0).abs() < 1e-12);
+        // Swapping two rows takes a pivot and flips the sign back.
+        let swap = Matrix::literal([[0.0, 2.0, 0.0], [3.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+        assert!((determinant(&swap) + 6.0).abs() < 1e-12);
+        assert!((determinant(&sample()) - 263.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn singular_matrix_is_rejected() {
+        let a = Matrix::literal([[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 1.0, 1.0]]);
+        assert_eq!(determinant(&a), 0.0);
+        assert!(inverse(&a).is_err());
+        assert!(solve(&a, &Matrix::literal([[1.0], [2.0], [3.0]])).is_err());
+    }
+
+    fn tall() -> Matrix<f64> {
+        Matrix::literal([[2.0, -1.0, 0.5], [1.0, 3.0, -2.0], [0.0, 1.0, 4.0], [-3.0, 0.5, 1.0]])
+    }
+
+    fn diagonal(values: &[f64]) -> Matrix<f64> {
+        let mut m = Matrix::new(values.len(), values.len());
+        for (i, v) in values.iter().enumerate() { m[(i, i)] = *v; }
+        m
+    }
+
+    #[test]
+    fn qr_reconstructs_with_orthogonal_q() {
+        let a = tall();
+        let Qr { q, r } = qr(&a);
+        assert_close(&(&q.clone().transpose() * &q), &Matrix::identity(4));
+        assert_close(&(&q * &r), &a);
+        for col in 0..r.colc() {
+            for row in (col + 1)..r.rowc() { assert!(r[(row, col)].abs() < 1e-12); }
+        }
+    }
+
+    #[test]
+    fn svd_reconstructs_with_orthonormal_factors() {
+        for a in [tall(), tall().transpose()] {
+            let Svd { u, sigma, v } = svd(&a);
+            assert_close(&(&u.clone().transpose() * &u), &Matrix::identity(3));
+            assert_close(&(&v.clone().transpose() * &v), &Matrix::identity(3));
+            assert_close(&(&(&u * &diagonal(&sigma)) * &v.transpose()), &a);
+            assert!(sigma.windows(2).all(|w| w[0] >= w[1]));
+        }
+    }
+
+    #[test]
+    fn symmetric_eigen_diagonalizes() {
+        let a = Matrix::literal([[4.0, 1.0, -2.0], [1.0, 2.0, 0.0], [-2.0, 0.0, 3.0]]);
+        let SymmetricEigen { values, vectors } = symmetric_eigen(&a);
+        assert!(values.windows(2).all(|w| w[0] <= w[1]));
+        assert_close(&(&vectors.clone().transpose() * &vectors), &Matrix::identity(3));
+        assert_close(&(&a * &vectors), &(&vectors * &diagonal(&values)));
+    }
+
+    #[test]
+    fn rotation_has_conjugate_eigenvalues() {
+        let (sin, cos) = 0.7f64.sin_cos();
+        let rotation = Matrix::literal([[cos, -sin], [sin, cos]]);
+        let mut values = eigenvalues(&rotation).unwrap();
+        values.sort_by(|a, b| a.1.total_cmp(&b.1));
+        for ((re, im), expected) in values.into_iter().zip([(cos, -sin), (cos, sin)]) {
+            assert!((re - expected.0).abs() < 1e-9 && (im - expected.1).abs() < 1e-9, "{re} + {im}i");
+        }
+
+        let mut values = eigenvalues(&Matrix::literal([[2.0, 1.0], [0.0, 3.0]])).unwrap();
+        values.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert!((values[0].0 - 2.0).abs() < 1e-9 && (values[1].0 - 3.0).abs() < 1e-9);
+        assert!(values.iter().all(|v| v.1 == 0.0));
+    }
+
+    #[test]
+    fn polar_splits_rotation_from_stretch() {
+        for a in [Matrix::literal([[2.0, 1.0], [-0.5, 1.5]]), Matrix::literal([[0.0, 2.0], [1.0, 0.5]])] {
+            let Polar { rotation, stretch } = polar(&a);
+            assert_close(&(&rotation.clone().transpose() * &rotation), &Matrix::identity(2));
+            assert!((determinant(&rotation) - 1.0).abs() < 1e-9);
+            assert_close(&stretch.clone().transpose(), &stretch);
+            assert_close(&(&rotation * &stretch), &a);
+        }
+    }
+
+    #[test]
+    fn decompose_affine_reassembles() {
+        let samples = [
+            Matrix::literal([[2.0, 0.5, 10.0], [-1.0, 3.0, -4.0], [0.0, 0.0, 1.0]]),
+            Matrix::literal([[0.0, 1.0, 0.0], [1.0, 0.0, 3.0], [0.0, 0.0, 1.0]]),
+            Matrix::literal([[-1.5, 0.2, 1.0], [0.3, 0.8, 2.0], [0.0, 0.0, 1.0]])
+        ];
+        for m in samples {
+            let d = decompose_affine(&m).unwrap();
+            let (sin, cos) = d.rotation.sin_cos();
+            let rotate = Matrix::literal([[cos, -sin], [sin, cos]]);
+            let reflect = diagonal(&[1.0, if d.reflection { -1.0 } else { 1.0 }]);
+            let shear = Matrix::literal([[1.0, d.shear], [0.0, 1.0]]);
+            let scale = diagonal(&[d.scale.0, d.scale.1]);
+            let linear = &(&(&rotate * &reflect) * &shear) * &scale;
+            let expected = Matrix::literal([[m[(0, 0)], m[(0, 1)]], [m[(1, 0)], m[(1, 1)]]]);
+            assert_close(&linear, &expected);
+            assert_eq!(d.translation, (m[(0, 2)], m[(1, 2)]));
+            assert_eq!(d.reflection, determinant(&expected) < 0.0);
+        }
+
+        let projective = Matrix::literal([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.001, 0.0, 1.0]]);
+        assert!(decompose_affine(&projective).is_none());
+    }
+
+    #[test]
+    fn from_cols_rejects_missing_and_ragged_columns() {
+        assert_eq!(Matrix::<f64>::from_cols(&[]), Err(StackError::Empty { line: "column" }));
+        assert_eq!(Matrix::<f64>::from_cols(&[vec![], vec![]]), Err(StackError::Empty { line: "column" }));
+        assert_eq!(Matrix::<f64>::from_rows(&[]), Err(StackError::Empty { line: "row" }));
+        let ragged = Matrix::from_rows(&[vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0]]).unwrap_err();
+        assert_eq!(ragged, StackError::Ragged { line: "row", index: 2, expected: 2, found: 1 });
+        assert_eq!(ragged.to_string(), "row 2 has 1 entries, but row 0 has 2");
+        assert_eq!(Matrix::from_cols(&[vec![1.0, 2.0], vec![3.0, 4.0]]).unwrap(), Matrix::literal([[1.0, 3.0], [2.0, 4.0]]));
+        assert_eq!(Matrix::from_rows(&[vec![1.0, 2.0], vec![3.0, 4.0]]).unwrap(), Matrix::literal([[1.0, 2.0], [3.0, 4.0]]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn identity_needs_a_row() {
+        Matrix::<f64>::identity(0);
+    }
+    /// A matrix of small pseudo-random integers, so that products are exact.
+    fn sample_matrix(rowc: usize, colc: usize, seed: u64) -> Matrix<f64> {
+        let mut m = Matrix::new(rowc, colc);
+        let mut state = seed;
+        for e in m.content.iter_mut() {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            *e = (state >> 59) as f64 - 16.0;
+        }
+        m
+    }
+
+    #[test]
+    fn kernels_agree_with_naive() {
+        // (rows of left, inner, columns of right, threads)
+        let shapes = [
+            (3, 3, 50, 4), (5, 5, 7, 2), (4, 2, 9, 3), (1, 7, 5, 2), (9, 1, 4, 2), (1, 1, 1, 2),
+            // Neither dimension of the blocked kernel is a multiple of the block.
+            (BLOCK + 3, 2 * BLOCK + 5, 3, 2),
+            (6, 7, 3, 8), (3, 3, 0, 4), (6, 6, 0, 2)
+        ];
+        for (i, (rowc, inner, colc, threads)) in shapes.into_iter().enumerate() {
+            let left = sample_matrix(rowc, inner, 2 * i as u64);
+            let right = sample_matrix(inner, colc, 2 * i as u64 + 1);
+            let mut expected = Matrix::new(rowc, colc);
+            matmul_naive(&left, &right, &mut expected);
+
+            let mut output = Matrix::new(rowc, colc);
+            matmul(&left, &right, &mut output);
+            assert!(output == expected, "matmul of {rowc}x{inner} and {inner}x{colc}");
+            let mut output = Matrix::new(rowc, colc);
+            matmul_threaded(&left, &right, &mut output, threads);
+            assert!(output == expected, "matmul_threaded of {rowc}x{inner} and {inner}x{colc} on {threads} threads");
+        }
+    }
+}
@@ -0,0 +1,616 @@
+use std::io::Read;
+use crate::error::AnyError;
+use crate::raster::Raster;
+use crate::raster::Pixel;
+use crate::zlib::InflateError;
+use crate::zlib::zlib_compress;
+use crate::zlib::zlib_decompress;
+
+// Routines for reading and writing PNG files
+// PNG is a sequence of checksummed chunks. The image header (IHDR) comes first,
+// the pixel data is split across one or more IDAT chunks, and IEND comes last.
+// The pixel data is a zlib stream of filtered scanlines, see `zlib.rs`.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The reasons a PNG file can be rejected by [`read_png`].
+#[derive(Debug)]
+pub enum PngError {
+    /// The file does not begin with the PNG signature.
+    BadSignature,
+    /// The CRC of a chunk does not match its contents.
+    BadCrc([u8; 4]),
+    /// The first chunk is not IHDR, or IHDR is malformed.
+    BadHeader,
+    /// The combination of color type and bit depth is not allowed by the standard.
+    UnsupportedFormat { color_type: u8, bit_depth: u8 },
+    UnsupportedInterlace(u8),
+    /// A critical chunk which this decoder does not understand.
+    UnknownCriticalChunk([u8; 4]),
+    /// A scanline begins with a filter type other than 0 through 4.
+    BadFilter(u8),
+    /// A palettized image has no PLTE chunk.
+    MissingPalette,
+    /// A palettized pixel refers to a color which is not in the palette.
+    BadPaletteIndex(u8),
+    /// The width or height is zero.
+    InvalidDimensions { width: u32, height: u32 },
+    /// The image is too large to be held in memory on this platform.
+    DimensionOverflow,
+    /// The compressed image data is corrupt.
+    Zlib(InflateError),
+    /// The file, or the decompressed image data, ends early.
+    Truncated
+}
+
+impl std::fmt::Display for PngError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PngError::BadSignature => write!(f, "not a PNG file"),
+            PngError::BadCrc(ty) => write!(f, "CRC mismatch in {} chunk", String::from_utf8_lossy(ty)),
+            PngError::BadHeader => write!(f, "missing or malformed IHDR chunk"),
+            PngError::UnsupportedFormat { color_type, bit_depth } =>
+                write!(f, "unsupported color type {} with bit depth {}", color_type, bit_depth),
+            PngError::UnsupportedInterlace(m) => write!(f, "unsupported interlace method {}", m),
+            PngError::UnknownCriticalChunk(ty) =>
+                write!(f, "unknown critical chunk {}", String::from_utf8_lossy(ty)),
+            PngError::BadFilter(t) => write!(f, "invalid scanline filter type {}", t),
+            PngError::MissingPalette => write!(f, "palettized image has no PLTE chunk"),
+            PngError::BadPaletteIndex(i) => write!(f, "palette index {} is out of range", i),
+            PngError::InvalidDimensions { width, height } =>
+                write!(f, "invalid image dimensions: {}x{}", width, height),
+            PngError::DimensionOverflow => write!(f, "image dimensions too large for this platform"),
+            PngError::Zlib(e) => write!(f, "{}", e),
+            PngError::Truncated => write!(f, "file ends before the end of the image data")
+        }
+    }
+}
+
+impl std::error::Error for PngError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PngError::Zlib(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<InflateError> for PngError {
+    fn from(value: InflateError) -> Self {
+        Self::Zlib(value)
+    }
+}
+
+// Color types
+const GRAY: u8 = 0;
+const RGB: u8 = 2;
+const INDEXED: u8 = 3;
+const GRAY_ALPHA: u8 = 4;
+const RGBA: u8 = 6;
+
+/// Computes the CRC-32 (ISO 3309) of a chunk, which covers its type and data
+/// but not its length.
+fn chunk_crc(ty: &[u8; 4], data: &[u8]) -> u32 {
+    crc32_update(crc32_update(0xFFFFFFFF, ty), data) ^ 0xFFFFFFFF
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut n = 0;
+        while n < 256 {
+            let mut c = n as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+                k += 1;
+            }
+            table[n] = c;
+            n += 1;
+        }
+        table
+    };
+    for byte in data {
+        crc = TABLE[((crc ^ u32::from(*byte)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// The contents of the IHDR chunk.
+struct Header { width: usize, height: usize, bit_depth: u8, color_type: u8, interlaced: bool }
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            GRAY | INDEXED => 1,
+            GRAY_ALPHA => 2,
+            RGB => 3,
+            _ => 4
+        }
+    }
+
+    /// The number of bytes between a byte and its counterpart in the previous pixel,
+    /// as used by the filters. Pixels smaller than a byte are rounded up to 1.
+    fn filter_bpp(&self) -> usize {
+        (self.channels() * usize::from(self.bit_depth)).div_ceil(8)
+    }
+
+    /// The number of bytes in a scanline `width` pixels wide, excluding the filter type.
+    fn row_len(&self, width: usize) -> usize {
+        (width * self.channels() * usize::from(self.bit_depth)).div_ceil(8)
+    }
+
+    /// The number of bytes of filtered scanlines, including their filter types, or `None`
+    /// if that does not fit in a `usize`.
+    fn filtered_len(&self) -> Option<usize> {
+        let passes: &[(usize, usize)] = if self.interlaced {
+            &ADAM7_PASSES.map(|(x0, y0, dx, dy)| pass_size(self, x0, y0, dx, dy))
+        } else {
+            &[(self.width, self.height)]
+        };
+        passes.iter().filter(|(width, height)| *width > 0 && *height > 0).try_fold(0usize, |total, (width, height)| {
+            let row_len = width.checked_mul(self.channels() * usize::from(self.bit_depth))?.div_ceil(8);
+            total.checked_add(row_len.checked_add(1)?.checked_mul(*height)?)
+        })
+    }
+}
+
+pub fn read_png<R>(input: &mut R) -> Result<Raster, AnyError>
+where R: std::io::Read
+{
+    // Running off the end of the file is a property of the file, not of the
+    // reader, so report it as such.
+    read_png_unchecked(input).map_err(|e| match e {
+        AnyError::IO(e) if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            AnyError::from(PngError::Truncated),
+        other => other
+    })
+}
+
+fn read_png_unchecked<R>(input: &mut R) -> Result<Raster, AnyError>
+where R: std::io::Read
+{
+    let mut signature = [0u8; 8];
+    input.read_exact(&mut signature)?;
+    if signature != SIGNATURE { return Err(PngError::BadSignature.into()); }
+
+    let (ty, data) = read_chunk(input)?;
+    if &ty != b"IHDR" { return Err(PngError::BadHeader.into()); }
+    let header = parse_header(&data)?;
+
+    let mut palette: Vec<Pixel> = Vec::new();
+    let mut transparency: Vec<u8> = Vec::new();
+    let mut compressed: Vec<u8> = Vec::new();
+    loop {
+        let (ty, data) = read_chunk(input)?;
+        match &ty {
+            b"PLTE" => {
+                palette = data.chunks_exact(3).map(|c| Pixel::rgb(c[0], c[1], c[2])).collect();
+            },
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(&data),
+            b"IEND" => break,
+            // Ancillary chunks are marked by a lowercase first letter and may be ignored.
+            _ if ty[0].is_ascii_lowercase() => {},
+            _ => return Err(PngError::UnknownCriticalChunk(ty).into())
+        }
+    }
+
+    if header.color_type == INDEXED {
+        if palette.is_empty() { return Err(PngError::MissingPalette.into()); }
+        // The tRNS chunk of a palettized image holds one alpha value per palette entry.
+        for (color, alpha) in palette.iter_mut().zip(&transparency) { color.alpha = *alpha; }
+    }
+
+    // The filtered scanlines have a known length, so anything longer is rejected while it is
+    // being decompressed, and anything shorter before the pixels are allocated.
+    let expected = header.filtered_len().ok_or(PngError::DimensionOverflow)?;
+    let filtered = zlib_decompress(&compressed, expected).map_err(PngError::from)?;
+    if filtered.len() < expected { return Err(PngError::Truncated.into()); }
+    let size = header.width.checked_mul(header.height).ok_or(PngError::DimensionOverflow)?;
+    let mut pixels: Vec<Pixel> = Vec::new();
+    pixels.try_reserve_exact(size).map_err(|_| PngError::DimensionOverflow)?;
+    pixels.resize(size, Pixel::transparent());
+    let decoder = PixelDecoder { header: &header, palette: &palette, transparency: &transparency };
+
+    if header.interlaced {
+        decode_adam7(&filtered, &decoder, &mut pixels)?;
+    } else {
+        let rows = unfilter(&filtered, &header, header.width, header.height)?;
+        let row_len = header.row_len(header.width);
+        for y in 0..header.height {
+            let row = &rows[(y * row_len)..((y + 1) * row_len)];
+            for x in 0..header.width {
+                pixels[y * header.width + x] = decoder.decode(row, x)?;
+            }
+        }
+    }
+
+    Ok(Raster { pixels, width: header.width })
+}
+
+fn read_chunk<R>(input: &mut R) -> Result<([u8; 4], Vec<u8>), AnyError>
+where R: std::io::Read
+{
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    let len = u32::from_be_bytes(buf);
+    let mut ty = [0u8; 4];
+    input.read_exact(&mut ty)?;
+
+    // Read incrementally rather than trusting `len` with an allocation up front.
+    let mut data: Vec<u8> = Vec::new();
+    input.by_ref().take(u64::from(len)).read_to_end(&mut data)?;
+    if data.len() as u64 != u64::from(len) { return Err(PngError::Truncated.into()); }
+
+    input.read_exact(&mut buf)?;
+    if chunk_crc(&ty, &data) != u32::from_be_bytes(buf) { return Err(PngError::BadCrc(ty).into()); }
+    Ok((ty, data))
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, PngError> {
+    let [w0, w1, w2, w3, h0, h1, h2, h3, bit_depth, color_type, compression, filter, interlace] =
+        <[u8; 13]>::try_from(data).map_err(|_| PngError::BadHeader)?;
+    let width = u32::from_be_bytes([w0, w1, w2, w3]);
+    let height = u32::from_be_bytes([h0, h1, h2, h3]);
+    if width == 0 || height == 0 { return Err(PngError::InvalidDimensions { width, height }); }
+    if compression != 0 || filter != 0 { return Err(PngError::BadHeader); }
+
+    let allowed = match color_type {
+        GRAY => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+        INDEXED => matches!(bit_depth, 1 | 2 | 4 | 8),
+        RGB | GRAY_ALPHA | RGBA => matches!(bit_depth, 8 | 16),
+        _ => false
+    };
+    if !allowed { return Err(PngError::UnsupportedFormat { color_type, bit_depth }); }
+
+    let interlaced = match interlace {
+        0 => false,
+        1 => true,
+        other => return Err(PngError::UnsupportedInterlace(other))
+    };
+
+    Ok(Header {
+        width: usize::try_from(width).map_err(|_| PngError::DimensionOverflow)?,
+        height: usize::try_from(height).map_err(|_| PngError::DimensionOverflow)?,
+        bit_depth, color_type, interlaced
+    })
+}
+
+/// Reverses the filters applied to `height` scanlines of `width` pixels at the start
+/// of `data`, returning the raw scanlines concatenated without their filter types.
+fn unfilter(data: &[u8], header: &Header, width: usize, height: usize) -> Result<Vec<u8>, PngError> {
+    let row_len = header.row_len(width);
+    let bpp = header.filter_bpp();
+    let mut output: Vec<u8> = vec![0; row_len * height];
+
+    for y in 0..height {
+        let begin = y * (row_len + 1);
+        let filtered = data.get(begin..(begin + row_len + 1)).ok_or(PngError::Truncated)?;
+        let (prev_rows, rest) = output.split_at_mut(y * row_len);
+        let prior: &[u8] = if y == 0 { &[] } else { &prev_rows[((y - 1) * row_len)..] };
+        let row = &mut rest[..row_len];
+
+        for i in 0..row_len {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };         // left
+            let b = if y > 0 { prior[i] } else { 0 };                  // above
+            let c = if i >= bpp && y > 0 { prior[i - bpp] } else { 0 }; // above left
+            let predictor = match filtered[0] {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                other => return Err(PngError::BadFilter(other))
+            };
+            row[i] = filtered[i + 1].wrapping_add(predictor);
+        }
+    }
+    Ok(output)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// The Adam7 passes as (x start, y start, x step, y step).
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4),
+    (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+/// The width and height of the Adam7 pass starting at `x0, y0` and stepping by `dx, dy`.
+fn pass_size(header: &Header, x0: usize, y0: usize, dx: usize, dy: usize) -> (usize, usize) {
+    ((header.width + dx - 1 - x0) / dx, (header.height + dy - 1 - y0) / dy)
+}
+
+/// Decodes Adam7 interlaced image data. Each of the seven passes is a complete
+/// filtered image covering every eighth pixel from a different starting offset.
+fn decode_adam7(data: &[u8], decoder: &PixelDecoder, pixels: &mut [Pixel]) -> Result<(), PngError> {
+    let header = decoder.header;
+
+    let mut offset = 0;
+    for (x0, y0, dx, dy) in ADAM7_PASSES {
+        let (width, height) = pass_size(header, x0, y0, dx, dy);
+        // Empty passes are omitted entirely, including their filter types.
+        if width == 0 || height == 0 { continue; }
+
+        let rows = unfilter(&data[offset.min(data.len())..], header, width, height)?;
+        let row_len = header.row_len(width);
+        for py in 0..height {
+            let row = &rows[(py * row_len)..((py + 1) * row_len)];
+            for px in 0..width {
+                let (x, y) = (x0 + px * dx, y0 + py * dy);
+                pixels[y * header.width + x] = decoder.decode(row, px)?;
+            }
+        }
+        offset += (row_len + 1) * height;
+    }
+    Ok(())
+}
+
+struct PixelDecoder<'a> { header: &'a Header, palette: &'a [Pixel], transparency: &'a [u8] }
+
+impl<'a> PixelDecoder<'a> {
+    /// Reads the `i`th sample of an unfiltered scanline.
+    fn sample(&self, row: &[u8], i: usize) -> u16 {
+        match self.header.bit_depth {
+            16 => u16::from_be_bytes([row[2 * i], row[2 * i + 1]]),
+            8 => u16::from(row[i]),
+            depth => {
+                // Samples smaller than a byte are packed leftmost in the most significant bits.
+                let depth = usize::from(depth);
+                let bit = i * depth;
+                let mask = (1u16 << depth) - 1;
+                (u16::from(row[bit / 8]) >> (8 - depth - bit % 8)) & mask
+            }
+        }
+    }
+
+    /// Rescales a sample to 8 bits.
+    fn scale(&self, sample: u16) -> u8 {
+        let max = (1u32 << self.header.bit_depth) - 1;
+        ((u32::from(sample) * 255 + max / 2) / max) as u8
+    }
+
+    /// The tRNS chunk of a grayscale or truecolor image names a single color, given as
+    /// 16-bit samples, which is fully transparent.
+    fn is_transparent_key(&self, samples: &[u16]) -> bool {
+        let key: Vec<u16> = self.transparency.chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        key.len() == samples.len() && key == samples
+    }
+
+    fn decode(&self, row: &[u8], x: usize) -> Result<Pixel, PngError> {
+        let n = self.header.channels();
+        let s = |c: usize| self.sample(row, x * n + c);
+        let pixel = match self.header.color_type {
+            GRAY => {
+                let v = self.scale(s(0));
+                let alpha = if self.is_transparent_key(&[s(0)]) { 0 } else { u8::MAX };
+                Pixel { red: v, green: v, blue: v, alpha }
+            },
+            RGB => {
+                let alpha = if self.is_transparent_key(&[s(0), s(1), s(2)]) { 0 } else { u8::MAX };
+                Pixel { red: self.scale(s(0)), green: self.scale(s(1)), blue: self.scale(s(2)), alpha }
+            },
+            INDEXED => {
+                let index = s(0) as u8;
+                *self.palette.get(usize::from(index)).ok_or(PngError::BadPaletteIndex(index))?
+            },
+            GRAY_ALPHA => {
+                let v = self.scale(s(0));
+                Pixel { red: v, green: v, blue: v, alpha: self.scale(s(1)) }
+            },
+            _ => Pixel {
+                red: self.scale(s(0)),
+                green: self.scale(s(1)),
+                blue: self.scale(s(2)),
+                alpha: self.scale(s(3))
+            }
+        };
+        Ok(pixel)
+    }
+}
+
+// Routines for writing PNG files
+
+/// Encodes `img` as an 8-bit PNG file. Opaque images are written as RGB, and images
+/// with any transparency as RGBA. Each scanline is filtered with whichever of the
+/// five filters produces the smallest sum of absolute differences, the usual heuristic
+/// for choosing filters which compress well.
+pub fn write_png<W>(output: &mut W, img: &Raster) -> std::io::Result<()>
+where W: std::io::Write
+{
+    let color_type = if img.is_opaque() { RGB } else { RGBA };
+    let channels = if color_type == RGB { 3 } else { 4 };
+
+    output.write_all(&SIGNATURE)?;
+
+    let mut ihdr: Vec<u8> = Vec::with_capacity(13);
+    ihdr.extend(u32::try_from(img.width()).expect("width too large to be measured in u32").to_be_bytes());
+    ihdr.extend(u32::try_from(img.height()).expect("height too large to be measured in u32").to_be_bytes());
+    ihdr.extend([8, color_type, 0, 0, 0]);
+    write_chunk(output, b"IHDR", &ihdr)?;
+
+    let row_len = img.width() * channels;
+    let mut filtered: Vec<u8> = Vec::with_capacity((row_len + 1) * img.height());
+    let mut prior: Vec<u8> = vec![0; row_len];
+    let mut row: Vec<u8> = Vec::with_capacity(row_len);
+    let mut candidate: Vec<u8> = vec![0; row_len];
+    let mut best: Vec<u8> = vec![0; row_len];
+
+    for y in 0..img.height() {
+        row.clear();
+        for x in 0..img.width() {
+            let px = img.get_pixel(x, y);
+            row.extend_from_slice(&[px.red, px.green, px.blue, px.alpha][..channels]);
+        }
+
+        let mut best_type = 0;
+        let mut best_score = u64::MAX;
+        for filter_type in 0..5u8 {
+            for i in 0..row_len {
+                let a = if i >= channels { row[i - channels] } else { 0 };
+                let b = prior[i];
+                let c = if i >= channels { prior[i - channels] } else { 0 };
+                let predictor = match filter_type {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                    _ => paeth(a, b, c)
+                };
+                candidate[i] = row[i].wrapping_sub(predictor);
+            }
+            let score: u64 = candidate.iter().map(|v| u64::from((*v as i8).unsigned_abs())).sum();
+            if score < best_score {
+                best_score = score;
+                best_type = filter_type;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+
+        filtered.push(best_type);
+        filtered.extend_from_slice(&best);
+        std::mem::swap(&mut prior, &mut row);
+    }
+
+    // Split the compressed data across IDAT chunks of a reasonable size.
+    let compressed = zlib_compress(&filtered);
+    for chunk in compressed.chunks(1 << 20) {
+        write_chunk(output, b"IDAT", chunk)?;
+    }
+    write_chunk(output, b"IEND", &[])?;
+    Ok(())
+}
+
+fn write_chunk<W>(output: &mut W, ty: &[u8; 4], data: &[u8]) -> std::io::Result<()>
+where W: std::io::Write
+{
+    let len = u32::try_from(data.len()).expect("chunk too large to be measured in u32");
+    output.write_all(&len.to_be_bytes())?;
+    output.write_all(ty)?;
+    output.write_all(data)?;
+    output.write_all(&chunk_crc(ty, data).to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Result<Raster, AnyError> {
+        read_png(&mut std::io::Cursor::new(bytes))
+    }
+
+    fn encode(img: &Raster) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, img).unwrap();
+        bytes
+    }
+
+    /// A raster whose every pixel differs from its neighbors, so that each filter is tried.
+    fn gradient(width: usize, height: usize, alpha: impl Fn(usize, usize) -> u8) -> Raster {
+        let mut img = Raster::solid(Pixel::transparent(), width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = ((x * 37 + y) as u8, ((y * 53) ^ x) as u8, (x * y + 11) as u8);
+                img.set_pixel(x, y, Pixel { red: r, green: g, blue: b, alpha: alpha(x, y) });
+            }
+        }
+        img
+    }
+
+    /// An 8-bit PNG built from already filtered image data.
+    fn png_from_filtered(width: u32, height: u32, color_type: u8, interlace: u8, filtered: &[u8]) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        let mut ihdr = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([8, color_type, 0, 0, interlace]);
+        write_chunk(&mut bytes, b"IHDR", &ihdr).unwrap();
+        write_chunk(&mut bytes, b"IDAT", &zlib_compress(filtered)).unwrap();
+        write_chunk(&mut bytes, b"IEND", &[]).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn crc32_matches_known_values() {
+        assert_eq!(crc32_update(0xFFFFFFFF, b"123456789") ^ 0xFFFFFFFF, 0xCBF43926);
+        assert_eq!(crc32_update(0xFFFFFFFF, b"") ^ 0xFFFFFFFF, 0);
+        // The CRC which ends every PNG file.
+        assert_eq!(chunk_crc(b"IEND", &[]), 0xAE426082);
+    }
+
+    #[test]
+    fn opaque_images_round_trip_as_rgb() {
+        let img = gradient(23, 17, |_, _| 255);
+        let bytes = encode(&img);
+        assert_eq!(bytes[8 + 8 + 9], RGB);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.width(), img.width());
+        assert_eq!(decoded.pixels, img.pixels);
+    }
+
+    #[test]
+    fn translucent_images_round_trip_as_rgba() {
+        let img = gradient(19, 29, |x, y| (x * 13 + y * 7) as u8);
+        let bytes = encode(&img);
+        assert_eq!(bytes[8 + 8 + 9], RGBA);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.width(), img.width());
+        assert_eq!(decoded.pixels, img.pixels);
+    }
+
+    #[test]
+    fn decodes_adam7_interlacing() {
+        // A 3x3 grayscale image, whose pixel (x, y) is 10 * (3y + x) + 5. The passes with
+        // pixels in it are, in order: (0, 0); (2, 0); (0, 2) and (2, 2); (1, 0) over
+        // (1, 2); and the whole middle row, here with the Sub filter.
+        let value = |x: u8, y: u8| 10 * (3 * y + x) + 5;
+        let filtered = [
+            0, value(0, 0),
+            0, value(2, 0),
+            0, value(0, 2), value(2, 2),
+            0, value(1, 0), 0, value(1, 2),
+            1, value(0, 1), value(1, 1) - value(0, 1), value(2, 1) - value(1, 1)
+        ];
+        let img = decode(&png_from_filtered(3, 3, GRAY, 1, &filtered)).unwrap();
+        for y in 0..3u8 {
+            for x in 0..3u8 {
+                let v = value(x, y);
+                assert_eq!(img.get_pixel(usize::from(x), usize::from(y)), Pixel::rgb(v, v, v), "pixel {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let mut bytes = encode(&gradient(4, 4, |_, _| 255));
+        // The last byte of the CRC of IHDR.
+        bytes[8 + 8 + 13 + 3] ^= 1;
+        assert!(matches!(decode(&bytes), Err(AnyError::Png(PngError::BadCrc(ty))) if &ty == b"IHDR"));
+
+        let bytes = encode(&gradient(4, 4, |_, _| 255));
+        assert!(matches!(decode(&bytes[..bytes.len() - 20]), Err(AnyError::Png(PngError::Truncated))));
+
+        let filtered = [5, 1, 2];
+        assert!(matches!(decode(&png_from_filtered(2, 1, GRAY, 0, &filtered)),
+            Err(AnyError::Png(PngError::BadFilter(5)))));
+    }
+    #[test]
+    fn image_data_must_match_the_header() {
+        // Megabytes of zeros for a 1x1 image are rejected without inflating all of them.
+        let bomb = png_from_filtered(1, 1, GRAY, 0, &vec![0; 1 << 22]);
+        assert!(bomb.len() < 1 << 16);
+        assert!(matches!(decode(&bomb), Err(AnyError::Png(PngError::Zlib(InflateError::TooLong(2))))));
+
+        // A huge image with a single scanline of data fails before the pixels are allocated.
+        let short = png_from_filtered(65535, 65535, RGBA, 0, &[0; 8]);
+        assert!(matches!(decode(&short), Err(AnyError::Png(PngError::Truncated))));
+        let short = png_from_filtered(65535, 65535, RGBA, 1, &[0; 8]);
+        assert!(matches!(decode(&short), Err(AnyError::Png(PngError::Truncated))));
+    }
+}
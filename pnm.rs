@@ -0,0 +1,517 @@
+use crate::error::AnyError;
+use crate::raster::Raster;
+use crate::raster::Pixel;
+
+// Routines for reading and writing Netpbm files
+// The family is made up of PBM (bitmaps, P1/P4), PGM (graymaps, P2/P5), PPM
+// (pixmaps, P3/P6) and PAM (arbitrary tuples, P7). The first of each pair of
+// magic numbers is the "plain" ASCII variant and the second is binary. Every
+// variant has a short whitespace-separated textual header, followed by samples
+// which are scaled against the header's maximum value.
+
+/// The reasons a Netpbm file can be rejected by [`read_pnm`].
+#[derive(Debug)]
+pub enum PnmError {
+    /// The file does not begin with one of the magic numbers `P1` through `P7`.
+    BadMagic([u8; 2]),
+    /// A header field, or a sample in a plain file, is not a valid decimal number.
+    BadNumber,
+    /// The maximum sample value is zero or greater than 65535.
+    BadMaxval(u32),
+    /// A PAM header line which is not understood, or a required field is missing.
+    BadPamHeader(String),
+    /// The PAM tuple type, or its depth, is not one of the standard ones.
+    UnsupportedTupleType { tupltype: String, depth: u32 },
+    /// A sample is greater than the maximum value declared in the header.
+    SampleOutOfRange(u32),
+    /// Either dimension is zero.
+    InvalidDimensions { width: u32, height: u32 },
+    /// The image is too large to be held in memory on this platform.
+    DimensionOverflow,
+    /// The file ended before all of the samples could be read.
+    Truncated
+}
+
+impl std::fmt::Display for PnmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PnmError::BadMagic(m) =>
+                write!(f, "not a Netpbm file (magic {:02x} {:02x})", m[0], m[1]),
+            PnmError::BadNumber => write!(f, "expected a decimal number"),
+            PnmError::BadMaxval(v) => write!(f, "invalid maximum value {}", v),
+            PnmError::BadPamHeader(line) => write!(f, "invalid PAM header: {}", line),
+            PnmError::UnsupportedTupleType { tupltype, depth } =>
+                write!(f, "unsupported tuple type '{}' with depth {}", tupltype, depth),
+            PnmError::SampleOutOfRange(v) => write!(f, "sample {} exceeds the maximum value", v),
+            PnmError::InvalidDimensions { width, height } =>
+                write!(f, "invalid image dimensions: {}x{}", width, height),
+            PnmError::DimensionOverflow => write!(f, "image dimensions too large for this platform"),
+            PnmError::Truncated => write!(f, "file ends before the end of the pixel data")
+        }
+    }
+}
+
+impl std::error::Error for PnmError {}
+
+/// How the samples of a single pixel are laid out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Tuple { Bitmap, Gray, GrayAlpha, Rgb, RgbAlpha }
+
+impl Tuple {
+    fn depth(&self) -> usize {
+        match self {
+            Tuple::Bitmap | Tuple::Gray => 1,
+            Tuple::GrayAlpha => 2,
+            Tuple::Rgb => 3,
+            Tuple::RgbAlpha => 4
+        }
+    }
+}
+
+/// A cursor over the raw bytes of a Netpbm file.
+struct Scanner<'a> { data: &'a [u8], pos: usize }
+
+impl<'a> Scanner<'a> {
+    /// Skips whitespace and `#` comments, which may appear anywhere in a header.
+    fn skip_space(&mut self) {
+        while let Some(b) = self.data.get(self.pos) {
+            if *b == b'#' {
+                while self.data.get(self.pos).is_some_and(|b| *b != b'\n') { self.pos += 1; }
+            } else if b.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<u32, PnmError> {
+        self.skip_space();
+        let begin = self.pos;
+        while self.data.get(self.pos).is_some_and(|b| b.is_ascii_digit()) { self.pos += 1; }
+        if begin == self.pos {
+            return Err(if self.pos >= self.data.len() { PnmError::Truncated } else { PnmError::BadNumber });
+        }
+        std::str::from_utf8(&self.data[begin..self.pos]).ok()
+            .and_then(|s| s.parse::<u32>().ok())
+            .ok_or(PnmError::BadNumber)
+    }
+
+    /// Reads the next line of a PAM header, with comments and surrounding whitespace removed.
+    fn line(&mut self) -> Result<&'a str, PnmError> {
+        let rest = &self.data[self.pos..];
+        let len = rest.iter().position(|b| *b == b'\n').ok_or(PnmError::Truncated)?;
+        self.pos += len + 1;
+        let line = &rest[..len];
+        let line = match line.iter().position(|b| *b == b'#') {
+            Some(i) => &line[..i],
+            None => line
+        };
+        std::str::from_utf8(line).map(str::trim)
+            .map_err(|_| PnmError::BadPamHeader(String::from_utf8_lossy(line).into_owned()))
+    }
+}
+
+struct PnmHeader { width: usize, height: usize, maxval: u32, tuple: Tuple, plain: bool }
+
+pub fn read_pnm<R>(input: &mut R) -> Result<Raster, AnyError>
+where R: std::io::Read
+{
+    let mut data: Vec<u8> = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut scanner = Scanner { data: &data, pos: 0 };
+
+    let magic = match data.get(0..2) {
+        Some([m0, m1]) => [*m0, *m1],
+        _ => return Err(PnmError::Truncated.into())
+    };
+    scanner.pos = 2;
+    let header = match &magic {
+        b"P7" => read_pam_header(&mut scanner)?,
+        [b'P', kind @ b'1'..=b'6'] => read_pnm_header(&mut scanner, *kind)?,
+        _ => return Err(PnmError::BadMagic(magic).into())
+    };
+
+    // Exactly one whitespace character separates the header from binary samples.
+    // The PAM header instead ends with the newline following ENDHDR.
+    if !header.plain && &magic != b"P7" { scanner.pos += 1; }
+
+    let size = header.width.checked_mul(header.height).ok_or(PnmError::DimensionOverflow)?;
+    let mut pixels: Vec<Pixel> = Vec::new();
+    pixels.try_reserve_exact(size).map_err(|_| PnmError::DimensionOverflow)?;
+
+    if header.tuple == Tuple::Bitmap {
+        read_pbm_pixels(&mut scanner, &header, &mut pixels)?;
+    } else {
+        read_samples(&mut scanner, &header, &mut pixels)?;
+    }
+
+    Ok(Raster { pixels, width: header.width })
+}
+
+fn read_pnm_header(scanner: &mut Scanner, kind: u8) -> Result<PnmHeader, PnmError> {
+    let width = scanner.number()?;
+    let height = scanner.number()?;
+    let (tuple, maxval) = match kind {
+        b'1' | b'4' => (Tuple::Bitmap, 1),
+        b'2' | b'5' => (Tuple::Gray, scanner.number()?),
+        _ => (Tuple::Rgb, scanner.number()?)
+    };
+    make_header(width, height, maxval, tuple, kind <= b'3')
+}
+
+fn read_pam_header(scanner: &mut Scanner) -> Result<PnmHeader, PnmError> {
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    let mut tupltype = String::new();
+
+    loop {
+        let line = scanner.line()?;
+        let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let value = value.trim();
+        let number = || value.parse::<u32>().map_err(|_| PnmError::BadNumber);
+        match key {
+            "" => {},
+            "ENDHDR" => break,
+            "WIDTH" => width = Some(number()?),
+            "HEIGHT" => height = Some(number()?),
+            "DEPTH" => depth = Some(number()?),
+            "MAXVAL" => maxval = Some(number()?),
+            // Multiple TUPLTYPE lines are concatenated with a space.
+            "TUPLTYPE" => {
+                if !tupltype.is_empty() { tupltype.push(' '); }
+                tupltype.push_str(value);
+            },
+            _ => return Err(PnmError::BadPamHeader(line.to_string()))
+        }
+    }
+
+    let missing = |field: &str| PnmError::BadPamHeader(format!("missing {}", field));
+    let width = width.ok_or_else(|| missing("WIDTH"))?;
+    let height = height.ok_or_else(|| missing("HEIGHT"))?;
+    let depth = depth.ok_or_else(|| missing("DEPTH"))?;
+    let maxval = maxval.ok_or_else(|| missing("MAXVAL"))?;
+
+    // Samples of a PAM black and white image are 0 for black and 1 for white, the
+    // opposite of PBM, so they are read as a graymap with a maximum value of 1.
+    let tuple = match (tupltype.as_str(), depth) {
+        ("BLACKANDWHITE" | "GRAYSCALE", 1) | ("", 1) => Tuple::Gray,
+        ("BLACKANDWHITE_ALPHA" | "GRAYSCALE_ALPHA", 2) | ("", 2) => Tuple::GrayAlpha,
+        ("RGB", 3) | ("", 3) => Tuple::Rgb,
+        ("RGB_ALPHA", 4) | ("", 4) => Tuple::RgbAlpha,
+        _ => return Err(PnmError::UnsupportedTupleType { tupltype, depth })
+    };
+    make_header(width, height, maxval, tuple, false)
+}
+
+fn make_header(width: u32, height: u32, maxval: u32, tuple: Tuple, plain: bool) -> Result<PnmHeader, PnmError> {
+    if width == 0 || height == 0 { return Err(PnmError::InvalidDimensions { width, height }); }
+    if maxval == 0 || maxval > 65535 { return Err(PnmError::BadMaxval(maxval)); }
+    Ok(PnmHeader {
+        width: usize::try_from(width).map_err(|_| PnmError::DimensionOverflow)?,
+        height: usize::try_from(height).map_err(|_| PnmError::DimensionOverflow)?,
+        maxval, tuple, plain
+    })
+}
+
+/// Reads the pixels of a PBM file, in which 1 is black and 0 is white.
+fn read_pbm_pixels(scanner: &mut Scanner, header: &PnmHeader, pixels: &mut Vec<Pixel>) -> Result<(), PnmError> {
+    let color = |bit: bool| if bit { Pixel::rgb(0, 0, 0) } else { Pixel::rgb(255, 255, 255) };
+
+    if header.plain {
+        // Plain PBM samples are single digits which need not be separated by whitespace.
+        for _ in 0..(header.width * header.height) {
+            scanner.skip_space();
+            match scanner.data.get(scanner.pos) {
+                Some(b'0') => pixels.push(color(false)),
+                Some(b'1') => pixels.push(color(true)),
+                Some(_) => return Err(PnmError::BadNumber),
+                None => return Err(PnmError::Truncated)
+            }
+            scanner.pos += 1;
+        }
+        return Ok(());
+    }
+
+    // Each row is packed most significant bit first and padded to a whole byte.
+    let row_len = header.width.div_ceil(8);
+    for y in 0..header.height {
+        let begin = scanner.pos + y * row_len;
+        let row = scanner.data.get(begin..(begin + row_len)).ok_or(PnmError::Truncated)?;
+        for x in 0..header.width {
+            pixels.push(color(row[x / 8] & (0x80 >> (x % 8)) != 0));
+        }
+    }
+    Ok(())
+}
+
+fn read_samples(scanner: &mut Scanner, header: &PnmHeader, pixels: &mut Vec<Pixel>) -> Result<(), PnmError> {
+    let depth = header.tuple.depth();
+    let wide = header.maxval > 255;
+    let count = header.width * header.height * depth;
+    let mut samples: [u8; 4] = [0; 4];
+    let mut binary = scanner.pos;
+
+    for i in 0..count {
+        let value = if header.plain {
+            scanner.number()?
+        } else if wide {
+            let bytes = scanner.data.get(binary..(binary + 2)).ok_or(PnmError::Truncated)?;
+            binary += 2;
+            u32::from(u16::from_be_bytes([bytes[0], bytes[1]]))
+        } else {
+            let byte = scanner.data.get(binary).ok_or(PnmError::Truncated)?;
+            binary += 1;
+            u32::from(*byte)
+        };
+        if value > header.maxval { return Err(PnmError::SampleOutOfRange(value)); }
+        samples[i % depth] = ((value * 255 + header.maxval / 2) / header.maxval) as u8;
+
+        if i % depth == depth - 1 {
+            pixels.push(match header.tuple {
+                Tuple::Bitmap | Tuple::Gray => Pixel::rgb(samples[0], samples[0], samples[0]),
+                Tuple::GrayAlpha => Pixel { red: samples[0], green: samples[0], blue: samples[0], alpha: samples[1] },
+                Tuple::Rgb => Pixel::rgb(samples[0], samples[1], samples[2]),
+                Tuple::RgbAlpha => Pixel { red: samples[0], green: samples[1], blue: samples[2], alpha: samples[3] }
+            });
+        }
+    }
+    Ok(())
+}
+
+// Routines for writing Netpbm files
+
+/// The member of the Netpbm family to encode an image as.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PnmFormat {
+    /// PBM, black and white. Pixels darker than middle gray become black.
+    Bitmap,
+    /// PGM, grayscale. Colors are converted using the Rec. 601 luma weights.
+    Graymap,
+    /// PPM, color. Alpha is discarded.
+    Pixmap,
+    /// PAM, color with alpha when the image has any transparency.
+    Pam
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PnmWriteOptions {
+    pub format: PnmFormat,
+    /// Write the ASCII variant (P1, P2, P3) instead of binary. Ignored for PAM,
+    /// which has no ASCII variant.
+    pub plain: bool,
+    /// Write samples with 16 bits instead of 8. Ignored for PBM.
+    pub wide: bool
+}
+
+impl PnmWriteOptions {
+    pub fn new(format: PnmFormat) -> Self {
+        Self { format, plain: false, wide: false }
+    }
+}
+
+/// Encodes `img` as a binary PPM file, or as a PAM file when it has any transparency.
+pub fn write_pnm<W>(output: &mut W, img: &Raster) -> std::io::Result<()>
+where W: std::io::Write
+{
+    let format = if img.is_opaque() { PnmFormat::Pixmap } else { PnmFormat::Pam };
+    write_pnm_with(output, img, PnmWriteOptions::new(format))
+}
+
+pub fn write_pnm_with<W>(output: &mut W, img: &Raster, opts: PnmWriteOptions) -> std::io::Result<()>
+where W: std::io::Write
+{
+    let (width, height) = (img.width(), img.height());
+    let plain = opts.plain && opts.format != PnmFormat::Pam;
+    let maxval: u32 = if opts.wide { 65535 } else { 255 };
+
+    let luma = |px: &Pixel| -> u8 {
+        let y = 299 * u32::from(px.red) + 587 * u32::from(px.green) + 114 * u32::from(px.blue);
+        ((y + 500) / 1000) as u8
+    };
+
+    let (tuple, magic) = match (opts.format, plain) {
+        (PnmFormat::Bitmap, true) => (Tuple::Bitmap, "P1"),
+        (PnmFormat::Bitmap, false) => (Tuple::Bitmap, "P4"),
+        (PnmFormat::Graymap, true) => (Tuple::Gray, "P2"),
+        (PnmFormat::Graymap, false) => (Tuple::Gray, "P5"),
+        (PnmFormat::Pixmap, true) => (Tuple::Rgb, "P3"),
+        (PnmFormat::Pixmap, false) => (Tuple::Rgb, "P6"),
+        (PnmFormat::Pam, _) => (if img.is_opaque() { Tuple::Rgb } else { Tuple::RgbAlpha }, "P7")
+    };
+
+    match tuple {
+        _ if magic == "P7" => {
+            let tupltype = if tuple == Tuple::Rgb { "RGB" } else { "RGB_ALPHA" };
+            write!(output, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+                width, height, tuple.depth(), maxval, tupltype)?;
+        },
+        Tuple::Bitmap => write!(output, "{}\n{} {}\n", magic, width, height)?,
+        _ => write!(output, "{}\n{} {}\n{}\n", magic, width, height, maxval)?
+    }
+
+    if tuple == Tuple::Bitmap {
+        for y in 0..height {
+            let bits = (0..width).map(|x| luma(&img.get_pixel(x, y)) < 128);
+            if plain {
+                let row: Vec<&str> = bits.map(|b| if b { "1" } else { "0" }).collect();
+                // Plain files should not have lines longer than 70 characters.
+                for line in row.chunks(35) { writeln!(output, "{}", line.join(" "))?; }
+            } else {
+                let mut row = vec![0u8; width.div_ceil(8)];
+                for (x, bit) in bits.enumerate() {
+                    if bit { row[x / 8] |= 0x80 >> (x % 8); }
+                }
+                output.write_all(&row)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut samples: Vec<u32> = Vec::with_capacity(width * tuple.depth());
+    let mut bytes: Vec<u8> = Vec::new();
+    for y in 0..height {
+        samples.clear();
+        for x in 0..width {
+            let px = img.get_pixel(x, y);
+            match tuple {
+                Tuple::Gray => samples.push(u32::from(luma(&px))),
+                Tuple::Rgb => samples.extend([px.red, px.green, px.blue].map(u32::from)),
+                _ => samples.extend([px.red, px.green, px.blue, px.alpha].map(u32::from))
+            }
+        }
+        // Scale from 8 bits; 65535 is exactly 257 times 255.
+        let scale = maxval / 255;
+        if plain {
+            let row: Vec<String> = samples.iter().map(|s| (s * scale).to_string()).collect();
+            for line in row.chunks(12) { writeln!(output, "{}", line.join(" "))?; }
+        } else {
+            bytes.clear();
+            for s in &samples {
+                if opts.wide { bytes.extend(((s * scale) as u16).to_be_bytes()); }
+                else { bytes.push(*s as u8); }
+            }
+            output.write_all(&bytes)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Result<Raster, AnyError> {
+        read_pnm(&mut std::io::Cursor::new(bytes))
+    }
+
+    fn encode(img: &Raster, format: PnmFormat, plain: bool, wide: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_pnm_with(&mut bytes, img, PnmWriteOptions { format, plain, wide }).unwrap();
+        bytes
+    }
+
+    /// An image 13 pixels wide, so that PBM rows end part way through a byte, and with
+    /// more samples per row than fit on a line of a plain file.
+    fn image(pixel: impl Fn(usize, usize) -> Pixel) -> Raster {
+        let mut img = Raster::solid(Pixel::transparent(), 13, 3);
+        for y in 0..3 {
+            for x in 0..13 { img.set_pixel(x, y, pixel(x, y)); }
+        }
+        img
+    }
+
+    fn bitmap() -> Raster {
+        image(|x, y| if (x + y) % 3 == 0 { Pixel::rgb(0, 0, 0) } else { Pixel::rgb(255, 255, 255) })
+    }
+
+    fn graymap() -> Raster {
+        image(|x, y| { let v = (x * 19 + y * 71) as u8; Pixel::rgb(v, v, v) })
+    }
+
+    fn pixmap() -> Raster {
+        image(|x, y| Pixel::rgb((x * 19) as u8, (y * 71) as u8, (x * y * 5 + 3) as u8))
+    }
+
+    fn assert_round_trip(img: &Raster, format: PnmFormat, plain: bool, wide: bool, magic: &[u8]) {
+        let bytes = encode(img, format, plain, wide);
+        assert_eq!(&bytes[..2], magic);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.width(), img.width());
+        assert_eq!(decoded.pixels, img.pixels, "{}", String::from_utf8_lossy(magic));
+    }
+
+    #[test]
+    fn bitmaps_round_trip() {
+        assert_round_trip(&bitmap(), PnmFormat::Bitmap, true, false, b"P1");
+        assert_round_trip(&bitmap(), PnmFormat::Bitmap, false, false, b"P4");
+    }
+
+    #[test]
+    fn graymaps_round_trip() {
+        for wide in [false, true] {
+            assert_round_trip(&graymap(), PnmFormat::Graymap, true, wide, b"P2");
+            assert_round_trip(&graymap(), PnmFormat::Graymap, false, wide, b"P5");
+        }
+    }
+
+    #[test]
+    fn pixmaps_round_trip() {
+        for wide in [false, true] {
+            assert_round_trip(&pixmap(), PnmFormat::Pixmap, true, wide, b"P3");
+            assert_round_trip(&pixmap(), PnmFormat::Pixmap, false, wide, b"P6");
+        }
+    }
+
+    #[test]
+    fn pam_round_trips_with_alpha() {
+        let img = image(|x, y| Pixel { red: (x * 19) as u8, green: (y * 71) as u8, blue: 200, alpha: (x * 20) as u8 });
+        for wide in [false, true] {
+            let bytes = encode(&img, PnmFormat::Pam, false, wide);
+            let end = bytes.windows(6).position(|w| w == b"ENDHDR").unwrap();
+            let header = String::from_utf8_lossy(&bytes[..end]);
+            assert!(header.contains("TUPLTYPE RGB_ALPHA\n"), "{}", header);
+            assert_round_trip(&img, PnmFormat::Pam, false, wide, b"P7");
+        }
+    }
+
+    #[test]
+    fn wide_samples_are_scaled_by_maxval() {
+        // Two 16-bit samples against a maximum of 1000.
+        let mut bytes = b"P5 2 1 1000\n".to_vec();
+        bytes.extend(500u16.to_be_bytes());
+        bytes.extend(1000u16.to_be_bytes());
+        assert_eq!(decode(&bytes).unwrap().pixels, [Pixel::rgb(128, 128, 128), Pixel::rgb(255, 255, 255)]);
+    }
+
+    #[test]
+    fn headers_allow_comments_and_any_whitespace() {
+        let img = decode(b"P2# a comment right after the magic number\n\t2 #width\r\n  1\n# maxval next\n15 0\n\n 15").unwrap();
+        assert_eq!(img.width(), 2);
+        assert_eq!(img.pixels, [Pixel::rgb(0, 0, 0), Pixel::rgb(255, 255, 255)]);
+
+        // Plain PBM samples need not be separated.
+        let img = decode(b"P1\n# comment\n3 1\n101").unwrap();
+        assert_eq!(img.pixels, [Pixel::rgb(0, 0, 0), Pixel::rgb(255, 255, 255), Pixel::rgb(0, 0, 0)]);
+
+        // Exactly one whitespace character precedes binary samples, even if it is a
+        // space and the first sample is a newline byte.
+        let img = decode(b"P5 1 1 255 \n").unwrap();
+        assert_eq!(img.pixels, [Pixel::rgb(10, 10, 10)]);
+
+        let pam = b"P7\n# comment\nWIDTH 1\n  HEIGHT\t1   \nDEPTH 2\nMAXVAL 255 # trailing\n\
+            TUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\x40\x80";
+        let img = decode(pam).unwrap();
+        assert_eq!(img.pixels, [Pixel { red: 0x40, green: 0x40, blue: 0x40, alpha: 0x80 }]);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(matches!(decode(b"P9 1 1 255\n\0"), Err(AnyError::Pnm(PnmError::BadMagic(_)))));
+        assert!(matches!(decode(b"P2 1 1 0\n0"), Err(AnyError::Pnm(PnmError::BadMaxval(0)))));
+        assert!(matches!(decode(b"P2 0 1 255\n"), Err(AnyError::Pnm(PnmError::InvalidDimensions { .. }))));
+        assert!(matches!(decode(b"P2 1 1 9\n10"), Err(AnyError::Pnm(PnmError::SampleOutOfRange(10)))));
+        assert!(matches!(decode(b"P6 2 2 255\n\0\0\0"), Err(AnyError::Pnm(PnmError::Truncated))));
+        assert!(matches!(decode(b"P7\nWIDTH 1\nHEIGHT 1\nMAXVAL 255\nENDHDR\n\0"),
+            Err(AnyError::Pnm(PnmError::BadPamHeader(_)))));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::transform::Transform2D;
+use crate::color::ColorTransform;
+use crate::error::AnyError;
+use crate::viewbox::ViewBox;
+use crate::rasterize::RasterizeOptions;
+use crate::load_input_img;
+use crate::write_output_img;
+use crate::write_output_img_vb;
+
+// Presets
+// The textbook problem 4.15 in Coding the Matrix by Philip N. Klein
+// has 6 parts. For each part, a different linear transformation must be
+// applied to the input image. 
+//
+// I have written a procedure for each part and placed them all in this file.
+// Each procedure is registered in `PRESETS` below under its own name, so to run
+// one of them type "cargo run -- --preset part4_rotating" on the command line.
+// Running "cargo run" with no arguments runs every part. I reccomend only running
+// one procedure at a time, as all together they might take a while to complete
+// on slower computers. Cargo is the Rust buildtool, you can get it by installing
+// the Rust toolchain. 
+
+/// Where a preset reads its input from and writes its outputs to, and how it rasterizes them.
+pub struct PresetContext<'a> { pub input: &'a str, pub output_dir: &'a str, pub raster: &'a RasterizeOptions }
+
+pub type Preset = fn(&PresetContext) -> Result<(), AnyError>;
+
+pub const PRESETS: &[(&str, Preset)] = &[
+    ("part1_identity", part1_identity),             // outputs to part1_identity.svg/bmp
+    ("part2_translating", part2_translating),       // outputs to part2_translating.svg/bmp
+    ("part3_scaling", part3_scaling),               // outputs to part3_scaling.svg/bmp
+    ("part4_rotating", part4_rotating),             // outputs to part4_rotating.svg/bmp
+    ("part5_reflecting", part5_reflecting),         // outputs to part5_reflecting.svg/bmp
+    ("part6_colortransform", part6_colortransform), // outputs to part6_colortransform.svg/bmp
+    ("part7_stretching", part7_stretching)          // outputs to part7_stretching.svg/bmp
+];
+
+pub fn find_preset(name: &str) -> Option<Preset> {
+    PRESETS.iter().find(|(n, _)| *n == name).map(|(_, p)| *p)
+}
+
+fn part1_identity(ctx: &PresetContext) -> Result<(), AnyError> {
+    let t = Transform2D::identity();
+
+    let mut q = load_input_img(ctx.input)?;
+    t.apply(&mut q);
+
+    write_output_img(&q, ctx, "part1_identity")?;
+    Ok(())
+}
+
+fn part2_translating(ctx: &PresetContext) -> Result<(), AnyError> {
+    let xoffset: f64 = 250.0; // The horizontal offset to apply to each vertex
+    let yoffset: f64 = 100.0; // The vertical offset to apply to each vertex
+    
+    let t = Transform2D::translate(xoffset, yoffset);
+
+    let mut q = load_input_img(ctx.input)?;
+    t.apply(&mut q);
+
+    // Fix the point (0.0, 0.0) as the physical origin so that the translation is apparent. 
+    // This, as opposed to autofitting the viewbox, which would reverse the translation
+    // by changing the coordinate of the physical origin to be equal to the coordinate of
+    // the topleft-most vertex of the quilt.
+    let vb = ViewBox { 
+        min_x: 0.0, min_y: 0.0, 
+        width: (q.pwidth + 1) as f64 + xoffset,
+        height: (q.pheight + 1) as f64 + yoffset
+    };
+    write_output_img_vb(&q, &vb, ctx, "part2_translating")?;
+
+    Ok(())
+}
+
+fn part3_scaling(ctx: &PresetContext) -> Result<(), AnyError> {
+    let xscale: f64 = 3.0;
+    let yscale: f64 = 1.0;
+
+    let t = Transform2D::scale(xscale, yscale);
+
+    let mut q = load_input_img(ctx.input)?;
+    t.apply(&mut q);
+    write_output_img(&q, ctx, "part3_scaling")?;
+    Ok(())    
+}
+
+fn part4_rotating(ctx: &PresetContext) -> Result<(), AnyError> {
+    let theta: f64 = 0.25 * std::f64::consts::PI;
+
+    let t = Transform2D::rotate(theta);
+
+    let mut q = load_input_img(ctx.input)?;
+    t.apply(&mut q);
+    write_output_img(&q, ctx, "part4_rotating")?;
+    Ok(())
+}
+
+fn part5_reflecting(ctx: &PresetContext) -> Result<(), AnyError> {
+    // // reflects across the y-axis (negates x value)
+    // let reflect_y = Transform2D::reflect_across_line(1.0, 0.0, 0.0);
+
+    // reflects across x-axis (negates y value)
+    let reflect_x = Transform2D::reflect_across_line(0.0, 1.0, 0.0).expect("the x-axis is a line");
+
+    let mut q = load_input_img(ctx.input)?;
+    reflect_x.apply(&mut q);
+    write_output_img(&q, ctx, "part5_reflecting")?;
+    Ok(())
+}
+
+fn part6_colortransform(ctx: &PresetContext) -> Result<(), AnyError> {
+    let mut q = load_input_img(ctx.input)?;
+
+    // Each color channel becomes 255 minus itself. The alpha channel is left untouched.
+    let negative_coloring = ColorTransform::negative();
+
+    negative_coloring.apply_to_quilt(&mut q);
+    write_output_img(&q, ctx, "part6_colortransform")?;
+    Ok(())
+}
+
+fn part7_stretching(ctx: &PresetContext) -> Result<(), AnyError> {
+    let mut q = load_input_img(ctx.input)?;
+
+    let t = Transform2D::shear_y(-1.0);
+
+    t.apply(&mut q);
+    write_output_img(&q, ctx, "part7_stretching")?;
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+// Color quantization
+// Formats such as GIF can only hold a palette of up to 256 colors, while a transformed
+// image easily has tens of thousands. A quantizer picks the palette which represents an
+// image best, working from its histogram of distinct colors so that every color is
+// weighted by the number of pixels which have it:
+//
+//  - Median cut starts with a box around every color in RGB space and repeatedly
+//    splits the box with the longest side at the median pixel along that side, until
+//    there are as many boxes as palette entries.
+//  - Octree quantization files every color into a tree which splits RGB space into
+//    eighths at each level, one bit of each channel at a time, and then merges the
+//    leaves holding the fewest pixels into their parents until few enough remain.
+//
+// Either way each palette entry is the average of the colors it stands for, and every
+// pixel is then drawn with the nearest entry.
+
+/// The algorithms which can choose a palette, see the top of this file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Quantizer {
+    #[default]
+    MedianCut,
+    Octree
+}
+
+/// Every distinct color of an image along with the number of pixels which have it, in
+/// order of color so that the same image always yields the same palette.
+pub type Histogram = Vec<([u8; 3], u64)>;
+
+pub fn histogram(colors: impl IntoIterator<Item = [u8; 3]>) -> Histogram {
+    let mut counts: HashMap<[u8; 3], u64> = HashMap::new();
+    for color in colors { *counts.entry(color).or_insert(0) += 1; }
+    let mut histogram: Histogram = counts.into_iter().collect();
+    histogram.sort_unstable();
+    histogram
+}
+
+impl Quantizer {
+    /// Chooses at most `size` colors to represent `histogram`. An image with no more
+    /// than `size` distinct colors is represented exactly.
+    pub fn palette(self, histogram: &Histogram, size: usize) -> Vec<[u8; 3]> {
+        assert!(size >= 1, "a palette needs at least one color");
+        if histogram.len() <= size {
+            return histogram.iter().map(|&(color, _)| color).collect();
+        }
+        match self {
+            Quantizer::MedianCut => median_cut(histogram, size),
+            Quantizer::Octree => octree(histogram, size)
+        }
+    }
+}
+
+/// The index of the color of `palette` closest to `color` by euclidean distance.
+pub fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> usize {
+    let distance = |entry: &[u8; 3]| -> i32 {
+        (0..3).map(|c| (i32::from(entry[c]) - i32::from(color[c])).pow(2)).sum()
+    };
+    (0..palette.len()).min_by_key(|&i| distance(&palette[i])).expect("empty palette")
+}
+
+/// The average of `colors`, weighting each by its count.
+fn average(colors: &[([u8; 3], u64)]) -> [u8; 3] {
+    let total: u64 = colors.iter().map(|&(_, count)| count).sum();
+    std::array::from_fn(|c| {
+        let sum: u64 = colors.iter().map(|&(color, count)| u64::from(color[c]) * count).sum();
+        ((sum + total / 2) / total) as u8
+    })
+}
+
+fn median_cut(histogram: &Histogram, size: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![histogram.clone()];
+    while boxes.len() < size {
+        // The channel along which each box is longest, and how long it is.
+        let longest_side = |colors: &[([u8; 3], u64)]| -> (usize, u8) {
+            (0..3)
+                .map(|c| {
+                    let low = colors.iter().map(|(color, _)| color[c]).min().unwrap_or(0);
+                    let high = colors.iter().map(|(color, _)| color[c]).max().unwrap_or(0);
+                    (c, high - low)
+                })
+                .max_by_key(|&(_, length)| length)
+                .expect("three channels")
+        };
+        let widest = boxes.iter().enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| (i, longest_side(colors)))
+            .max_by_key(|&(_, (_, length))| length);
+        let Some((i, (channel, _))) = widest else { break; };
+
+        let mut colors = boxes.swap_remove(i);
+        // Ties along the channel are broken by the whole color, so that which colors end
+        // up on either side of the split never depends on the order they arrived in.
+        colors.sort_unstable_by_key(|&(color, _)| (color[channel], color));
+        // Split where half of the pixels of the box lie on either side, while leaving
+        // at least one color in each half.
+        let total: u64 = colors.iter().map(|&(_, count)| count).sum();
+        let mut seen = 0;
+        let median = colors.iter()
+            .position(|&(_, count)| { seen += count; 2 * seen >= total })
+            .unwrap_or(0);
+        let upper = colors.split_off((median + 1).clamp(1, colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|colors| average(colors)).collect()
+}
+
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    /// The number of pixels in the subtree rooted at this node.
+    pixels: u64,
+    /// The sum of the colors of those pixels, kept only by leaves.
+    sum: [u64; 3],
+    leaf: bool
+}
+
+/// The depth of the leaves of a full octree, at which every bit of a color is used.
+const OCTREE_DEPTH: usize = 8;
+
+fn octree(histogram: &Histogram, size: usize) -> Vec<[u8; 3]> {
+    let node = |leaf| OctreeNode { children: [None; 8], pixels: 0, sum: [0; 3], leaf };
+    let mut nodes = vec![node(false)];
+    // The internal nodes at each depth, any of which can be merged into a leaf.
+    let mut reducible: [Vec<usize>; OCTREE_DEPTH] = std::array::from_fn(|_| Vec::new());
+    reducible[0].push(0);
+    let mut leaves = 0;
+
+    for &(color, count) in histogram {
+        let mut current = 0;
+        for depth in 0..OCTREE_DEPTH {
+            nodes[current].pixels += count;
+            let bit = 7 - depth;
+            let octant = (0..3).fold(0, |acc, c| acc << 1 | usize::from(color[c] >> bit & 1));
+            current = match nodes[current].children[octant] {
+                Some(child) => child,
+                None => {
+                    let leaf = depth + 1 == OCTREE_DEPTH;
+                    nodes.push(node(leaf));
+                    let child = nodes.len() - 1;
+                    nodes[current].children[octant] = Some(child);
+                    if leaf { leaves += 1; } else { reducible[depth + 1].push(child); }
+                    child
+                }
+            };
+        }
+        let leaf = &mut nodes[current];
+        leaf.pixels += count;
+        for (sum, &v) in leaf.sum.iter_mut().zip(&color) { *sum += u64::from(v) * count; }
+    }
+
+    // Merge the deepest nodes first, and among those the ones with the fewest pixels,
+    // which are the cheapest to lose.
+    for level in &mut reducible {
+        level.sort_unstable_by_key(|&i| std::cmp::Reverse(nodes[i].pixels));
+    }
+    while leaves > size {
+        let Some(level) = reducible.iter_mut().rev().find(|level| !level.is_empty()) else { break; };
+        let parent = level.pop().expect("non-empty level");
+        let children: Vec<usize> = nodes[parent].children.iter().flatten().copied().collect();
+        for &child in &children {
+            for c in 0..3 { nodes[parent].sum[c] += nodes[child].sum[c]; }
+        }
+        nodes[parent].children = [None; 8];
+        nodes[parent].leaf = true;
+        leaves -= children.len() - 1;
+    }
+
+    let mut palette = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(i) = stack.pop() {
+        let n = &nodes[i];
+        if n.leaf {
+            palette.push(std::array::from_fn(|c| ((n.sum[c] + n.pixels / 2) / n.pixels) as u8));
+        } else {
+            stack.extend(n.children.iter().flatten());
+        }
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUANTIZERS: [Quantizer; 2] = [Quantizer::MedianCut, Quantizer::Octree];
+
+    fn distance(a: [u8; 3], b: [u8; 3]) -> f64 {
+        (0..3).map(|c| (f64::from(a[c]) - f64::from(b[c])).powi(2)).sum::<f64>().sqrt()
+    }
+
+    #[test]
+    fn few_colors_are_kept_exactly() {
+        let colors: Vec<[u8; 3]> = (0..=255).map(|i: u8| [i, i.wrapping_mul(7), 255 - i]).collect();
+        let hist = histogram(colors.iter().copied().chain(colors.iter().copied().take(10)));
+        let mut expected = colors.clone();
+        expected.sort_unstable();
+        for quantizer in QUANTIZERS {
+            assert_eq!(quantizer.palette(&hist, 256), expected, "{quantizer:?}");
+        }
+    }
+
+    #[test]
+    fn clusters_get_an_entry_each() {
+        // Four clusters of 125 colors spread out along red, which differ in the top two
+        // bits of red and agree in the top two bits of green and blue.
+        let clusters: Vec<Vec<[u8; 3]>> = [16u8, 80, 144, 208].iter().map(|&red| {
+            let spread = |center: u8| (0..5).map(move |i| center - 6 + 3 * i);
+            spread(red).flat_map(|r| spread(48).flat_map(move |g| spread(48).map(move |b| [r, g, b]))).collect()
+        }).collect();
+        let hist = histogram(clusters.iter().flatten().copied());
+        assert_eq!(hist.len(), 500);
+        let mut expected: Vec<[u8; 3]> = clusters.iter()
+            .map(|cluster| average(&cluster.iter().map(|&c| (c, 1)).collect::<Vec<_>>()))
+            .collect();
+        expected.sort_unstable();
+        for quantizer in QUANTIZERS {
+            let mut palette = quantizer.palette(&hist, 4);
+            palette.sort_unstable();
+            assert_eq!(palette, expected, "{quantizer:?}");
+        }
+    }
+
+    #[test]
+    fn many_colors_are_approximated() {
+        let levels = || (0..=255u8).step_by(15);
+        let hist = histogram(levels().flat_map(|r| levels().flat_map(move |g| levels().map(move |b| [r, g, b]))));
+        assert_eq!(hist.len(), 18 * 18 * 18);
+        for quantizer in QUANTIZERS {
+            let palette = quantizer.palette(&hist, 256);
+            assert!(palette.len() <= 256 && palette.len() > 128, "{quantizer:?} chose {} colors", palette.len());
+            assert_eq!(palette, quantizer.palette(&hist, 256));
+            let worst = hist.iter()
+                .map(|&(color, _)| distance(color, palette[nearest(&palette, color)]))
+                .fold(0.0, f64::max);
+            assert!(worst < 64.0, "{quantizer:?} is off by {worst}");
+        }
+    }
+}
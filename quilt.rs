@@ -7,7 +7,7 @@ pub fn knit(image: Raster) -> Quilt {
     let pixel_count = image.width() * image.height();
     
     let mut locmat: Matrix<f64> = Matrix::new(3, vertex_count);
-    let mut colmat: Matrix<u8> = Matrix::new(4, pixel_count);
+    let mut colmat: Matrix<u8> = Matrix::new(5, pixel_count);
 
     let mut vi = 0;
     for vy in 0..(image.height() + 1) {
@@ -28,7 +28,8 @@ pub fn knit(image: Raster) -> Quilt {
             vec[0] = color.red;
             vec[1] = color.green;
             vec[2] = color.blue;
-            vec[3] = 1;
+            vec[3] = color.alpha;
+            vec[4] = 1;
             pi += 1;
         }
     }
@@ -36,7 +37,15 @@ pub fn knit(image: Raster) -> Quilt {
     Quilt { locmat, colmat, pwidth: image.width(), pheight: image.height() }
 }
 
-/// An image represented as a quilt of colored parallelograms of uniform dimension.
+/// An image represented as a quilt of colored quadrilaterals. Affine transforms keep
+/// every tile the same parallelogram, while projective transforms do not.
+///
+/// Each column of `locmat` is the homogeneous coordinate `(x, y, w)` of a vertex, which
+/// lies at `(x / w, y / w)` on the plane. [`knit`] produces `w = 1` throughout, and it
+/// stays that way unless the bottom row of a transform is something other than `0, 0, 1`.
+/// Each column of `colmat` is the color `(red, green, blue, alpha, 1)` of a tile. The
+/// trailing `1` allows affine color transforms to be expressed as a 5x5 matrix and is
+/// distinct from `alpha`.
 ///
 /// ```
 /// p1 ------------ p2
@@ -48,6 +57,7 @@ pub fn knit(image: Raster) -> Quilt {
 /// p3 ------------ p4
 /// ```
 ///
+#[derive(Clone)]
 pub struct Quilt {
     pub locmat: Matrix<f64>,
     pub colmat: Matrix<u8>,
@@ -71,7 +81,7 @@ pub struct Tile<'a> {
 impl Quilt {
     pub fn tiles<'a>(&'a self) -> TileIterator<'a> {
         let initial = Tile { quilt: self, px: 0, py: 0, pi: 0, uli: 0 };
-        return TileIterator { tile: initial };
+        TileIterator { tile: initial }
     }
     pub fn p1<'a>(&'a self) -> Vertex<'a> {
         let colv_ref = self.locmat.get_col(0);
@@ -91,11 +101,33 @@ impl Quilt {
         let colv_ref = self.locmat.get_col(i);
         Vertex { colv_ref }
     }
+    /// The vertex at column `vx` and row `vy` of the vertex grid, which is one
+    /// larger in each dimension than the grid of tiles.
+    pub fn vertex<'a>(&'a self, vx: usize, vy: usize) -> Vertex<'a> {
+        let colv_ref = self.locmat.get_col(vy * (self.pwidth + 1) + vx);
+        Vertex { colv_ref }
+    }
+    /// The point at the middle of the image as [`knit`] lays it out, before any transform
+    /// is applied. Pass it to [`crate::transform::Transform2D::rotate_about`] to rotate the
+    /// image in place.
+    pub fn center(&self) -> (f64, f64) {
+        (self.pwidth as f64 / 2.0, self.pheight as f64 / 2.0)
+    }
+    /// The color of the tile at column `px` and row `py`.
+    pub fn tile_color<'a>(&'a self, px: usize, py: usize) -> TileColor<'a> {
+        let colv_ref = self.colmat.get_col(py * self.pwidth + px);
+        TileColor { colv_ref }
+    }
 }
 
 impl<'a> Vertex<'a> {
-    pub fn vx(&self) -> f64 { self.colv_ref[0] }
-    pub fn vy(&self) -> f64 { self.colv_ref[1] }
+    pub fn vx(&self) -> f64 { self.colv_ref[0] / self.colv_ref[2] }
+    pub fn vy(&self) -> f64 { self.colv_ref[1] / self.colv_ref[2] }
+    /// The homogeneous coordinate of this vertex, before the divide by `w`.
+    pub fn homogeneous(&self) -> [f64; 3] { [self.colv_ref[0], self.colv_ref[1], self.colv_ref[2]] }
+    /// Determines whether this vertex lies in front of the horizon of a perspective
+    /// transform. Vertices behind it have a negative `w` and are not drawn.
+    pub fn is_visible(&self) -> bool { self.colv_ref[2] > 0.0 }
 }
 
 impl<'a> PointLike2D for Vertex<'a> {
@@ -107,30 +139,35 @@ impl<'a> TileColor<'a> {
     pub fn red(&self) -> u8 { self.colv_ref[0] }
     pub fn green(&self) -> u8 { self.colv_ref[1] }
     pub fn blue(&self) -> u8 { self.colv_ref[2] }
+    pub fn alpha(&self) -> u8 { self.colv_ref[3] }
 }
 
 impl<'a> Tile<'a> {
     pub fn p1(&self) -> Vertex<'a> {
         let colv_ref = self.quilt.locmat.get_col(self.uli);
-        return Vertex { colv_ref };
+        Vertex { colv_ref }
     }
     pub fn p2(&self) -> Vertex<'a> {
         let colv_ref = self.quilt.locmat.get_col(self.uli + 1);
-        return Vertex { colv_ref };
+        Vertex { colv_ref }
     }
     pub fn p3(&self) -> Vertex<'a> {
         let colv_ref = self.quilt.locmat.get_col(self.uli + 
             self.quilt.pwidth + 1);
-        return Vertex { colv_ref };
+        Vertex { colv_ref }
     }
     pub fn p4(&self) -> Vertex<'a> {
         let colv_ref = self.quilt.locmat.get_col(self.uli + 
             self.quilt.pwidth + 2);
-        return Vertex { colv_ref };
+        Vertex { colv_ref }
+    }
+    /// Determines whether every corner of this tile is visible, see [`Vertex::is_visible`].
+    pub fn is_visible(&self) -> bool {
+        self.p1().is_visible() && self.p2().is_visible() && self.p3().is_visible() && self.p4().is_visible()
     }
     pub fn color(&self) -> TileColor<'a> {
         let colv_ref = self.quilt.colmat.get_col(self.pi);
-        return TileColor { colv_ref }
+        TileColor { colv_ref }
     }
 }
 
@@ -152,7 +189,7 @@ impl<'a> Iterator for TileIterator<'a> {
         }       
         self.tile.pi += 1;
         self.tile.uli += 1;
-        return Some(elapsed);
+        Some(elapsed)
     }
 }
 
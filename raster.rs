@@ -1,5 +1,7 @@
-#[derive(Clone, Copy)]
-pub struct Pixel { pub red: u8, pub green: u8, pub blue: u8 }
+/// A color with straight (not premultiplied) alpha. An `alpha` of 0 is fully
+/// transparent and 255 is fully opaque.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pixel { pub red: u8, pub green: u8, pub blue: u8, pub alpha: u8 }
 
 pub struct Raster { pub pixels: Vec<Pixel>, pub width: usize }
 
@@ -24,11 +26,20 @@ impl Raster {
         let pixels = vec![color; width * height];
         Self { pixels, width }
     }
+
+    /// Determines whether every pixel in this raster is fully opaque.
+    pub fn is_opaque(&self) -> bool {
+        self.pixels.iter().all(|px| px.alpha == u8::MAX)
+    }
 }
 
 
 impl Pixel {
-    pub fn black() -> Self {
-        Self { red: 0, green: 0, blue: 0 }
+    pub fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue, alpha: u8::MAX }
+    }
+
+    pub fn transparent() -> Self {
+        Self { red: 0, green: 0, blue: 0, alpha: 0 }
     }
 }
@@ -5,67 +5,160 @@ use crate::quilt::TileColor;
 use crate::raster::Pixel;
 use crate::raster::Raster;
 use crate::viewbox::ViewBox;
-use crate::viewbox::fit_vb;
+use crate::filter::Filter;
+use crate::homography::apply_3x3;
+use crate::homography::invert_3x3;
 use crate::geom::euclidean_distance_2d;
 use crate::geom::vec_dif_2d;
-use crate::geom::unit_vec;
 use crate::geom::vec_sum_2d;
 use crate::geom::vec_scale_2d;
 
-pub fn rasterize_autofit_autoconfig(quilt: &Quilt) -> Raster {
-    let vb = fit_vb(quilt);
-    rasterize_autoconfig(&quilt, &vb)
- }
+/// The algorithm used to turn a [`Quilt`] into a [`Raster`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RasterMethod {
+    /// Scan every tile in steps of `scan_px` and write each pixel landed on. Depending on
+    /// the transform this can leave gaps between tiles or write a pixel more than once.
+    Forward,
+    /// Map the center of every output pixel through the inverse of the quilt's transform
+    /// and sample the quilt there with a [`Filter`]. Every output pixel is written exactly
+    /// once, so the result agrees with the SVG rendering of the quilt.
+    ///
+    /// The transform is recovered from the first tile of the quilt, which assumes that the
+    /// quilt was produced by [`crate::quilt::knit`] and then transformed by a matrix, affine
+    /// or projective. A transform which collapses the quilt onto a line covers no pixels.
+    Inverse,
+    /// Fill every tile as a polygon, scanline by scanline, with the top-left fill rule: a
+    /// pixel belongs to a tile when its center lies inside the tile, or on its top or
+    /// left edge. Tiles which share an edge therefore never both claim a pixel along it,
+    /// nor both miss one, regardless of the transform.
+    Scanline
+}
+
+/// Parameters of [`rasterize`].
+#[derive(Clone, Copy, Debug)]
+pub struct RasterizeOptions {
+    pub method: RasterMethod,
+    /// Only affects [`RasterMethod::Inverse`].
+    pub filter: Filter,
+    /// The color of any space in the viewbox not covered by the quilt.
+    pub bg_color: Pixel,
+    /// The sampling step of [`RasterMethod::Forward`] in output pixels.
+    pub scan_px: f64,
+    /// The number of output pixels per unit distance.
+    pub pixel_density: f64,
+    /// The quilt is rendered at `antialias` times the resolution along each axis and then
+    /// averaged back down, so a pixel straddling the edge of a tile takes on a blend of the
+    /// colors on either side in proportion to how much of it each covers. A value of 1
+    /// disables anti-aliasing.
+    pub antialias: usize
+}
 
-pub fn rasterize_autoconfig(quilt: &Quilt, vb: &ViewBox) -> Raster {
-   rasterize(quilt, vb, Pixel::black(), 1f64, 1f64)
+impl Default for RasterizeOptions {
+    fn default() -> Self {
+        Self {
+            method: RasterMethod::Inverse,
+            filter: Filter::Nearest,
+            bg_color: Pixel::transparent(),
+            scan_px: 1.0,
+            pixel_density: 1.0,
+            antialias: 1
+        }
+    }
 }
 
 /// Rasterizes the segment of `quilt` specified by the [`ViewBox`] `vb`. Any space in the
-/// viewbox not intersecting the quilt will be filled with the color `bg_color.` 
+/// viewbox not intersecting the quilt will be filled with the color `opts.bg_color`.
 /// The resultant [`Raster`] will have an aspect ratio equivalent to that of `vb`.
 ///
 /// The [`Raster`] will be `pixel_density * vb.width` pixels wide and `pixel_density * vb.height`
 /// pixels tall. Put directly, `pixel_density` is the number of pixels per unit distance. 
-pub fn rasterize(quilt: &Quilt, vb: &ViewBox, bg_color: Pixel, scan_px: f64, pixel_density: f64) -> Raster {
-    let mut raster = Raster::solid(
-        /* color  = */ bg_color, 
-        /* width  = */ (vb.width * pixel_density).ceil() as usize,
-        /* height = */ (vb.height * pixel_density).ceil() as usize
-    );
+pub fn rasterize(quilt: &Quilt, vb: &ViewBox, opts: &RasterizeOptions) -> Raster {
+    let width = (vb.width * opts.pixel_density).ceil() as usize;
+    let height = (vb.height * opts.pixel_density).ceil() as usize;
+    let n = opts.antialias.max(1);
+
+    if n == 1 {
+        return rasterize_sized(quilt, vb, opts, width, height, opts.pixel_density);
+    }
+    let fine = rasterize_sized(quilt, vb, opts, width * n, height * n, opts.pixel_density * n as f64);
+    downsample(&fine, n, width, height)
+}
+
+fn rasterize_sized(quilt: &Quilt, vb: &ViewBox, opts: &RasterizeOptions, width: usize, height: usize,
+    pixel_density: f64) -> Raster
+{
+    let mut raster = Raster::solid(opts.bg_color, width, height);
     
-    for tile in quilt.tiles() {
-        rasterize_tile(&mut raster, vb, tile, scan_px, pixel_density);
+    match opts.method {
+        RasterMethod::Forward => {
+            for tile in quilt.tiles() {
+                rasterize_tile(&mut raster, vb, tile, opts.scan_px, pixel_density);
+            }
+        },
+        RasterMethod::Inverse => rasterize_inverse(&mut raster, quilt, vb, opts.filter, pixel_density),
+        RasterMethod::Scanline => {
+            for tile in quilt.tiles() {
+                rasterize_tile_scanline(&mut raster, vb, tile, pixel_density);
+            }
+        }
     }
 
-    return raster;
+    raster
 }
 
-fn rasterize_tile<'a>(raster: &mut Raster, vb: &ViewBox, tile: Tile<'a>, scan_px: f64, pixel_density: f64) {
-    let v1 = unit_vec(vec_dif_2d(tile.p2(), tile.p1()));
-    let v2 = unit_vec(vec_dif_2d(tile.p3(), tile.p1()));
-
-    // The position of `p1` relative to the origin of the viewbox.
-    let p1_rel = vec_dif_2d(tile.p1(), (vb.min_x, vb.min_y));
+/// Averages every `n` by `n` block of `fine` into a single pixel. Colors are weighted by
+/// their alpha so that transparent samples only ever lower the opacity of the result.
+fn downsample(fine: &Raster, n: usize, width: usize, height: usize) -> Raster {
+    let mut raster = Raster::solid(Pixel::transparent(), width, height);
+    let samples = (n * n) as f64;
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = [0f64; 4];
+            for sy in (y * n)..(y * n + n) {
+                for sx in (x * n)..(x * n + n) {
+                    let px = fine.get_pixel(sx, sy);
+                    let alpha = px.alpha as f64;
+                    acc[0] += px.red as f64 * alpha;
+                    acc[1] += px.green as f64 * alpha;
+                    acc[2] += px.blue as f64 * alpha;
+                    acc[3] += alpha;
+                }
+            }
+            if acc[3] == 0.0 { continue; }
+            let unmultiply = |c: f64| (c / acc[3]).round().clamp(0.0, 255.0) as u8;
+            raster.set_pixel(x, y, Pixel {
+                red: unmultiply(acc[0]),
+                green: unmultiply(acc[1]),
+                blue: unmultiply(acc[2]),
+                alpha: (acc[3] / samples).round() as u8
+            });
+        }
+    }
+    raster
+}
 
-    let d1_dist = euclidean_distance_2d(tile.p1(), tile.p2());
-    let d2_dist = euclidean_distance_2d(tile.p1(), tile.p3());
+fn rasterize_tile<'a>(raster: &mut Raster, vb: &ViewBox, tile: Tile<'a>, scan_px: f64, pixel_density: f64) {
+    if !tile.is_visible() { return; }
+    let (p1, p2, p3, p4) = (tile.p1().to_tuple(), tile.p2().to_tuple(), tile.p3().to_tuple(), tile.p4().to_tuple());
 
     // The scan distance expressed not in pixels but instead in coordinate distance.
     let scan = scan_px / pixel_density;
 
+    // Under a perspective transform opposite edges of a tile differ in length, so
+    // the step along each direction is chosen to suit the longer of the two.
+    let d1_step = scan / euclidean_distance_2d(p1, p2).max(euclidean_distance_2d(p3, p4));
+    let d2_step = scan / euclidean_distance_2d(p1, p3).max(euclidean_distance_2d(p2, p4));
+
     let mut pos_d1 = 0f64;
     let mut pos_d2 = 0f64;
 
-    while pos_d1 < d1_dist {
-        while pos_d2 < d2_dist {
-            let (x, y) = vec_sum_2d(
-                p1_rel,
-                vec_sum_2d(
-                    vec_scale_2d(v1, pos_d1), 
-                    vec_scale_2d(v2, pos_d2)
-                )
-            ).to_tuple();
+    while pos_d1 < 1.0 {
+        // The points at `pos_d1` along the top and bottom edges.
+        let top = vec_sum_2d(p1, vec_scale_2d(vec_dif_2d(p2, p1), pos_d1));
+        let bottom = vec_sum_2d(p3, vec_scale_2d(vec_dif_2d(p4, p3), pos_d1));
+        while pos_d2 < 1.0 {
+            let point = vec_sum_2d(top, vec_scale_2d(vec_dif_2d(bottom, top), pos_d2));
+            let (x, y) = vec_dif_2d(point, (vb.min_x, vb.min_y));
 
             if x >= 0f64 && x < vb.width && y >= 0f64 && y < vb.height {
                 let x = (x * pixel_density) as usize;
@@ -73,10 +166,174 @@ fn rasterize_tile<'a>(raster: &mut Raster, vb: &ViewBox, tile: Tile<'a>, scan_px
                 raster.set_pixel(x, y, Pixel::from(tile.color()));
             }
                       
-            pos_d2 += scan;
+            pos_d2 += d2_step;
         }
         pos_d2 = 0f64;
-        pos_d1 += scan;
+        pos_d1 += d1_step;
+    }
+}
+
+fn rasterize_tile_scanline<'a>(raster: &mut Raster, vb: &ViewBox, tile: Tile<'a>, pixel_density: f64) {
+    if !tile.is_visible() { return; }
+    let to_px = |p: (f64, f64)| ((p.0 - vb.min_x) * pixel_density, (p.1 - vb.min_y) * pixel_density);
+    let polygon = [
+        to_px(tile.p1().to_tuple()),
+        to_px(tile.p2().to_tuple()),
+        to_px(tile.p4().to_tuple()),
+        to_px(tile.p3().to_tuple())
+    ];
+    let color = Pixel::from(tile.color());
+    let (width, height) = (raster.width(), raster.height());
+    scan_convert(&polygon, width, height, |x, y| raster.set_pixel(x, y, color));
+}
+
+/// A non-horizontal polygon edge, oriented so that `top` has the lesser y-coordinate.
+#[derive(Clone, Copy)]
+struct Edge { top: (f64, f64), bottom: (f64, f64), slope: f64 }
+
+impl Edge {
+    fn x_at(&self, y: f64) -> f64 { self.top.0 + (y - self.top.1) * self.slope }
+}
+
+/// Calls `plot` for every pixel of a `width` by `height` raster whose center lies inside
+/// `polygon`, following the top-left fill rule. Edges are oriented top to bottom before any
+/// intersection is computed, so an edge shared by two polygons crosses each scanline at
+/// exactly the same point in both, whichever direction each polygon traverses it.
+fn scan_convert(polygon: &[(f64, f64)], width: usize, height: usize, mut plot: impl FnMut(usize, usize)) {
+    // The edge table, ordered by the scanline on which each edge begins.
+    let mut table: Vec<Edge> = Vec::with_capacity(polygon.len());
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        if a.1 == b.1 || !a.1.is_finite() || !b.1.is_finite() { continue; }
+        let (top, bottom) = if a.1 < b.1 { (a, b) } else { (b, a) };
+        table.push(Edge { top, bottom, slope: (bottom.0 - top.0) / (bottom.1 - top.1) });
+    }
+    if table.is_empty() { return; }
+    table.sort_by(|e1, e2| e1.top.1.total_cmp(&e2.top.1));
+
+    let y_min = table[0].top.1;
+    let y_max = table.iter().map(|e| e.bottom.1).fold(f64::NEG_INFINITY, f64::max);
+    let first = (y_min - 0.5).ceil().clamp(0.0, height as f64) as usize;
+    let last = (y_max - 0.5).ceil().clamp(0.0, height as f64) as usize;
+
+    let mut active: Vec<Edge> = Vec::new();
+    let mut crossings: Vec<f64> = Vec::new();
+    let mut next = 0;
+    for y in first..last {
+        let yc = y as f64 + 0.5;
+        // Edges include their top end and exclude their bottom end.
+        while next < table.len() && table[next].top.1 <= yc {
+            active.push(table[next]);
+            next += 1;
+        }
+        active.retain(|e| e.bottom.1 > yc);
+
+        crossings.clear();
+        crossings.extend(active.iter().map(|e| e.x_at(yc)));
+        crossings.sort_by(f64::total_cmp);
+
+        // Spans include their left end and exclude their right end.
+        for span in crossings.chunks_exact(2) {
+            let start = (span[0] - 0.5).ceil().clamp(0.0, width as f64) as usize;
+            let end = (span[1] - 0.5).ceil().clamp(0.0, width as f64) as usize;
+            for x in start..end { plot(x, y); }
+        }
+    }
+}
+
+fn rasterize_inverse(raster: &mut Raster, quilt: &Quilt, vb: &ViewBox, filter: Filter, pixel_density: f64) {
+    if quilt.pwidth == 0 || quilt.pheight == 0 { return; }
+
+    // Column (vx, vy) of `locmat` is the quilt's transform applied to (vx, vy, 1), so the
+    // columns of the transform can be read straight off the vertices of the first tile.
+    let (c00, c10, c01) = (quilt.vertex(0, 0).homogeneous(), quilt.vertex(1, 0).homogeneous(),
+        quilt.vertex(0, 1).homogeneous());
+    let transform: [[f64; 3]; 3] = std::array::from_fn(|r| [c10[r] - c00[r], c01[r] - c00[r], c00[r]]);
+    let Some(inverse) = invert_3x3(&transform) else { return; };
+
+    let mut weights: Vec<(usize, f64)> = Vec::new();
+    for y in 0..raster.height() {
+        for x in 0..raster.width() {
+            let center = (
+                vb.min_x + (x as f64 + 0.5) / pixel_density,
+                vb.min_y + (y as f64 + 0.5) / pixel_density
+            );
+            let [hu, hv, hw] = apply_3x3(&inverse, center);
+            let (u, v) = (hu / hw, hv / hw);
+            if !(u >= 0.0 && v >= 0.0 && u < quilt.pwidth as f64 && v < quilt.pheight as f64) {
+                continue;
+            }
+            // A perspective transform maps points from behind the horizon onto the plane
+            // as well, mirrored. They are recognized by their negative `w`.
+            if apply_3x3(&transform, (u, v))[2] <= 0.0 { continue; }
+
+            let color = match filter {
+                Filter::Nearest => Pixel::from(quilt.tile_color(u as usize, v as usize)),
+                _ => {
+                    // The number of tiles spanned by one output pixel along each axis of the
+                    // grid, from the derivative of (u, v) with respect to (x, y). When the image
+                    // is shrunk the filter is widened by this much so that every tile under a
+                    // pixel contributes to it, rather than only those nearest its center.
+                    let du = [(inverse[0][0] - u * inverse[2][0]) / hw, (inverse[0][1] - u * inverse[2][1]) / hw];
+                    let dv = [(inverse[1][0] - v * inverse[2][0]) / hw, (inverse[1][1] - v * inverse[2][1]) / hw];
+                    let scale = (
+                        ((du[0].powi(2) + du[1].powi(2)).sqrt() / pixel_density).max(1.0),
+                        ((dv[0].powi(2) + dv[1].powi(2)).sqrt() / pixel_density).max(1.0)
+                    );
+                    sample(quilt, filter, (u, v), scale, &mut weights)
+                }
+            };
+            raster.set_pixel(x, y, color);
+        }
+    }
+}
+
+/// Reconstructs the color of `quilt` at the grid coordinate `(u, v)`, with `filter` stretched
+/// by `scale` tiles along each axis. Tiles beyond the edge of the quilt take the color of the
+/// nearest edge tile. Colors are weighted with premultiplied alpha so that transparent tiles
+/// do not bleed their color into their neighbours.
+fn sample(quilt: &Quilt, filter: Filter, (u, v): (f64, f64), scale: (f64, f64),
+    weights_u: &mut Vec<(usize, f64)>) -> Pixel
+{
+    // Tile centers lie at half-integer grid coordinates.
+    let (cu, cv) = (u - 0.5, v - 0.5);
+    let (ru, rv) = (filter.support() * scale.0, filter.support() * scale.1);
+
+    weights_u.clear();
+    for i in ((cu - ru).ceil() as isize)..=((cu + ru).floor() as isize) {
+        let w = filter.weight((i as f64 - cu) / scale.0);
+        if w != 0.0 { weights_u.push((i.clamp(0, quilt.pwidth as isize - 1) as usize, w)); }
+    }
+
+    let mut acc = [0f64; 4];
+    let mut total = 0f64;
+    for j in ((cv - rv).ceil() as isize)..=((cv + rv).floor() as isize) {
+        let wv = filter.weight((j as f64 - cv) / scale.1);
+        if wv == 0.0 { continue; }
+        let py = j.clamp(0, quilt.pheight as isize - 1) as usize;
+        for &(px, wu) in weights_u.iter() {
+            let w = wu * wv;
+            let color = quilt.tile_color(px, py);
+            let alpha = color.alpha() as f64;
+            acc[0] += w * color.red() as f64 * alpha;
+            acc[1] += w * color.green() as f64 * alpha;
+            acc[2] += w * color.blue() as f64 * alpha;
+            acc[3] += w * alpha;
+            total += w;
+        }
+    }
+
+    if total <= 0.0 {
+        return Pixel::from(quilt.tile_color(u as usize, v as usize));
+    }
+    let alpha = acc[3] / total;
+    if alpha <= 0.0 { return Pixel::transparent(); }
+    let unmultiply = |c: f64| (c / acc[3]).round().clamp(0.0, 255.0) as u8;
+    Pixel {
+        red: unmultiply(acc[0]),
+        green: unmultiply(acc[1]),
+        blue: unmultiply(acc[2]),
+        alpha: alpha.round().clamp(0.0, 255.0) as u8
     }
 }
 
@@ -85,7 +342,139 @@ impl<'a> From<TileColor<'a>> for Pixel {
         Pixel {
             red: value.red(),
             green: value.green(),
-            blue: value.blue()
+            blue: value.blue(),
+            alpha: value.alpha()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilt::knit;
+    use crate::transform::Transform2D;
+    use crate::viewbox::fit_vb;
+
+    /// An image whose every pixel has a color of its own, the `i`th in reading order being
+    /// `(i, 0, 0)`.
+    fn numbered_quilt(width: usize, height: usize) -> Quilt {
+        let mut img = Raster::solid(Pixel::transparent(), width, height);
+        for y in 0..height {
+            for x in 0..width { img.set_pixel(x, y, Pixel::rgb((y * width + x) as u8, 0, 0)); }
+        }
+        knit(img)
+    }
+
+    #[test]
+    fn scanline_tiles_cover_the_hull_exactly_once() {
+        let mut quilt = numbered_quilt(9, 7);
+        Transform2D::scale(3.0, 2.5)
+            .then(Transform2D::rotate(0.5))
+            .then(Transform2D::shear_x(0.4))
+            .then(Transform2D::translate(20.3, 5.7))
+            .apply(&mut quilt);
+        let vb = fit_vb(&quilt).unwrap();
+        let (width, height) = (vb.width.ceil() as usize, vb.height.ceil() as usize);
+
+        let mut counts = vec![0u8; width * height];
+        let to_px = |p: (f64, f64)| (p.0 - vb.min_x, p.1 - vb.min_y);
+        for tile in quilt.tiles() {
+            let polygon = [tile.p1(), tile.p2(), tile.p4(), tile.p3()].map(|p| to_px(p.to_tuple()));
+            scan_convert(&polygon, width, height, |x, y| counts[y * width + x] += 1);
+        }
+
+        // The quilt is a parallelogram, and a point is inside it when it lies on the same
+        // side of every edge.
+        let hull = [quilt.p1(), quilt.p2(), quilt.p4(), quilt.p3()].map(|p| to_px(p.to_tuple()));
+        let side = |a: (f64, f64), b: (f64, f64), p: (f64, f64)| (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0);
+        let orientation = side(hull[0], hull[1], hull[2]).signum();
+        let mut inside = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let center = (x as f64 + 0.5, y as f64 + 0.5);
+                let distances: Vec<f64> = (0..4).map(|i| orientation * side(hull[i], hull[(i + 1) % 4], center)).collect();
+                let count = counts[y * width + x];
+                if distances.iter().all(|d| *d > 1e-6) {
+                    assert_eq!(count, 1, "pixel {x}, {y} inside the quilt");
+                    inside += 1;
+                } else if distances.iter().any(|d| *d < -1e-6) {
+                    assert_eq!(count, 0, "pixel {x}, {y} outside the quilt");
+                } else {
+                    assert!(count <= 1, "pixel {x}, {y} on the edge of the quilt");
+                }
+            }
+        }
+        // Shearing and rotating leave the area at 3 * 2.5 times that of the image.
+        assert!((inside as f64 - 9.0 * 7.0 * 7.5).abs() < 60.0, "{inside} pixels inside");
+    }
+
+    #[test]
+    fn scanline_output_matches_golden() {
+        let mut quilt = numbered_quilt(3, 2);
+        let center = quilt.center();
+        Transform2D::scale(2.0, 2.0)
+            .then(Transform2D::rotate_about((2.0 * center.0, 2.0 * center.1), std::f64::consts::PI / 6.0))
+            .apply(&mut quilt);
+        let vb = fit_vb(&quilt).unwrap();
+        let opts = RasterizeOptions { method: RasterMethod::Scanline, ..RasterizeOptions::default() };
+        let img = rasterize(&quilt, &vb, &opts);
+
+        // Each pixel as the number of the tile drawn there, or `.` where none is.
+        let rows: Vec<String> = (0..img.height())
+            .map(|y| (0..img.width()).map(|x| match img.get_pixel(x, y) {
+                px if px.alpha == 0 => '.',
+                px => char::from(b'0' + px.red)
+            }).collect())
+            .collect();
+        let golden = [
+            "..0.....",
+            ".0011...",
+            ".30112..",
+            "3344122.",
+            "..4455..",
+            "....55..",
+            "........",
+        ];
+        assert_eq!(rows, golden, "\n{}", rows.join("\n"));
+    }
+
+    #[test]
+    fn inverse_nearest_reproduces_an_untransformed_image() {
+        let quilt = numbered_quilt(7, 5);
+        let img = rasterize(&quilt, &fit_vb(&quilt).unwrap(), &RasterizeOptions::default());
+        assert_eq!((img.width(), img.height()), (7, 5));
+        for y in 0..5 {
+            for x in 0..7 { assert_eq!(img.get_pixel(x, y), Pixel::rgb((y * 7 + x) as u8, 0, 0)); }
+        }
+    }
+
+    #[test]
+    fn filters_keep_a_flat_color() {
+        let color = Pixel { red: 200, green: 100, blue: 50, alpha: 255 };
+        for (sx, sy) in [(2.5, 1.5), (0.3, 0.4)] {
+            let mut quilt = knit(Raster::solid(color, 6, 5));
+            Transform2D::scale(sx, sy).then(Transform2D::rotate(0.3)).apply(&mut quilt);
+            let vb = fit_vb(&quilt).unwrap();
+            for filter in [Filter::Nearest, Filter::Bilinear, Filter::CatmullRom, Filter::Mitchell, Filter::Lanczos3] {
+                let opts = RasterizeOptions { filter, pixel_density: 4.0, ..RasterizeOptions::default() };
+                let img = rasterize(&quilt, &vb, &opts);
+                let covered: Vec<Pixel> = img.pixels.into_iter().filter(|px| px.alpha > 0).collect();
+                assert!(!covered.is_empty());
+                assert!(covered.iter().all(|&px| px == color), "{filter:?} at scale {sx}, {sy}");
+            }
+        }
+    }
+
+    #[test]
+    fn antialiasing_blends_half_covered_pixels() {
+        let mut quilt = knit(Raster::solid(Pixel::rgb(255, 0, 0), 1, 1));
+        Transform2D::translate(0.5, 0.0).apply(&mut quilt);
+        let vb = ViewBox { min_x: 0.0, min_y: 0.0, width: 2.0, height: 1.0 };
+        for method in [RasterMethod::Inverse, RasterMethod::Scanline] {
+            let aliased = rasterize(&quilt, &vb, &RasterizeOptions { method, ..RasterizeOptions::default() });
+            assert_eq!(aliased.pixels.iter().map(|px| px.alpha).collect::<Vec<_>>(), [255, 0], "{method:?}");
+            let smooth = rasterize(&quilt, &vb, &RasterizeOptions { method, antialias: 4, ..RasterizeOptions::default() });
+            assert_eq!(smooth.pixels, [Pixel { red: 255, green: 0, blue: 0, alpha: 128 }; 2], "{method:?}");
         }
     }
 }
@@ -0,0 +1,284 @@
+use crate::quilt::Quilt;
+use crate::raster::Pixel;
+use crate::raster::Raster;
+use crate::viewbox::ViewBox;
+use crate::viewbox::fit_vb_all;
+use crate::rasterize::rasterize;
+use crate::rasterize::RasterizeOptions;
+use crate::error::AnyError;
+
+// Compositing
+// A `Scene` stacks several quilts, each transformed on its own, and renders them into a
+// single image. Layers are drawn from the lowest `z` to the highest, and layers with
+// equal `z` in the order they were added. Each layer is rasterized onto a transparent
+// canvas of its own and then composited onto everything beneath it:
+//
+//  1. The blend mode mixes the layer's color with the color beneath it, wherever there
+//     is something beneath it.
+//  2. The Porter-Duff operator decides how much of the blended layer and of the content
+//     beneath survive, according to where each of them is opaque.
+//
+// The arithmetic follows the W3C Compositing and Blending specification, on colors
+// premultiplied by alpha, so that the SVG rendering of a scene matches its raster.
+
+/// A Porter-Duff compositing operator, naming the result of drawing a layer, the source,
+/// onto the layers beneath it, the destination.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CompositeOp {
+    /// The source on top of the destination.
+    #[default]
+    Over,
+    /// The source where the destination is, and nothing elsewhere.
+    In,
+    /// The source where the destination is not, and nothing elsewhere.
+    Out,
+    /// The source where the destination is, on top of the destination.
+    Atop,
+    /// The source where the destination is not, and the destination where the source is
+    /// not.
+    Xor
+}
+
+/// How the color of a layer mixes with the color beneath it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BlendMode {
+    /// The layer's own color.
+    #[default]
+    Normal,
+    /// The product of the colors, which always darkens.
+    Multiply,
+    /// The complement of the product of the complements, which always lightens.
+    Screen,
+    /// Multiply where the color beneath is dark and screen where it is light, which
+    /// increases contrast.
+    Overlay,
+    /// The absolute difference of the colors.
+    Difference
+}
+
+impl BlendMode {
+    /// Blends the source color `cs` onto the backdrop color `cb`, both straight and
+    /// between 0 and 1.
+    pub fn blend(self, cb: f64, cs: f64) -> f64 {
+        let screen = |a: f64, b: f64| a + b - a * b;
+        match self {
+            BlendMode::Normal => cs,
+            BlendMode::Multiply => cs * cb,
+            BlendMode::Screen => screen(cs, cb),
+            BlendMode::Overlay => if cb <= 0.5 { 2.0 * cs * cb } else { screen(cs, 2.0 * cb - 1.0) },
+            BlendMode::Difference => (cs - cb).abs()
+        }
+    }
+
+    /// The name of this mode in CSS `mix-blend-mode`.
+    pub fn css_name(self) -> &'static str {
+        match self {
+            BlendMode::Normal => "normal",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Overlay => "overlay",
+            BlendMode::Difference => "difference"
+        }
+    }
+}
+
+/// A quilt in a scene. The quilt is placed by transforming it, with
+/// [`crate::transform::Transform2D::apply`], before the layer is made.
+#[derive(Clone)]
+pub struct Layer {
+    pub quilt: Quilt,
+    pub z: i32,
+    /// Multiplies the alpha of every tile, from 0 for invisible to 1 for unchanged.
+    pub opacity: f64,
+    pub operator: CompositeOp,
+    pub blend: BlendMode
+}
+
+impl Layer {
+    /// Whether this layer is composited in the plain way a lone image is drawn.
+    fn is_plain(&self) -> bool {
+        self.opacity == 1.0 && self.operator == CompositeOp::Over && self.blend == BlendMode::Normal
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Scene { layers: Vec<Layer> }
+
+impl Scene {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn push(&mut self, layer: Layer) {
+        self.layers.push(layer);
+    }
+
+    /// The layers from bottom to top.
+    pub fn layers(&self) -> Vec<&Layer> {
+        self.order().into_iter().map(|i| &self.layers[i]).collect()
+    }
+
+    /// The positions of the layers in the order they were pushed, from bottom to top.
+    pub fn order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.layers.len()).collect();
+        order.sort_by_key(|&i| self.layers[i].z);
+        order
+    }
+
+    /// The quilt of the only layer, when the scene amounts to nothing more than drawing
+    /// it. Such a scene renders exactly as the quilt does on its own.
+    pub fn single_quilt(&self) -> Option<&Quilt> {
+        match self.layers.as_slice() {
+            [layer] if layer.is_plain() => Some(&layer.quilt),
+            _ => None
+        }
+    }
+
+    /// Finds the smallest viewbox containing every layer, see [`crate::viewbox::fit_vb`].
+    pub fn fit_vb(&self) -> Result<ViewBox, AnyError> {
+        fit_vb_all(self.layers.iter().map(|layer| &layer.quilt))
+    }
+
+    /// Rasterizes the region `vb` of the scene. The layers are rendered with `opts` onto
+    /// transparent canvases, and the composited result is drawn over `opts.bg_color`.
+    pub fn rasterize(&self, vb: &ViewBox, opts: &RasterizeOptions) -> Raster {
+        let width = (vb.width * opts.pixel_density).ceil() as usize;
+        let height = (vb.height * opts.pixel_density).ceil() as usize;
+        let layer_opts = RasterizeOptions { bg_color: Pixel::transparent(), ..*opts };
+
+        let mut canvas = vec![[0.0; 4]; width * height];
+        for layer in self.layers() {
+            let raster = rasterize(&layer.quilt, vb, &layer_opts);
+            for (dst, src) in canvas.iter_mut().zip(&raster.pixels) {
+                let src = premultiply(*src, layer.opacity);
+                *dst = composite(src, *dst, layer.operator, layer.blend);
+            }
+        }
+
+        let bg = premultiply(opts.bg_color, 1.0);
+        let pixels = canvas.into_iter()
+            .map(|c| unpremultiply(composite(c, bg, CompositeOp::Over, BlendMode::Normal)))
+            .collect();
+        Raster { pixels, width }
+    }
+}
+
+/// The color of `px` between 0 and 1, premultiplied by its alpha scaled by `opacity`.
+fn premultiply(px: Pixel, opacity: f64) -> [f64; 4] {
+    let alpha = f64::from(px.alpha) / 255.0 * opacity;
+    let channel = |c: u8| f64::from(c) / 255.0 * alpha;
+    [channel(px.red), channel(px.green), channel(px.blue), alpha]
+}
+
+fn unpremultiply([r, g, b, a]: [f64; 4]) -> Pixel {
+    if a <= 0.0 { return Pixel::transparent(); }
+    let channel = |c: f64| (c / a * 255.0).round().clamp(0.0, 255.0) as u8;
+    Pixel { red: channel(r), green: channel(g), blue: channel(b), alpha: (a * 255.0).round().clamp(0.0, 255.0) as u8 }
+}
+
+/// Composites the premultiplied source `src` onto the premultiplied destination `dst`.
+fn composite(src: [f64; 4], dst: [f64; 4], operator: CompositeOp, blend: BlendMode) -> [f64; 4] {
+    let (alpha_s, alpha_d) = (src[3], dst[3]);
+    // The fractions of the source and of the destination which survive.
+    let (fa, fb) = match operator {
+        CompositeOp::Over => (1.0, 1.0 - alpha_s),
+        CompositeOp::In => (alpha_d, 0.0),
+        CompositeOp::Out => (1.0 - alpha_d, 0.0),
+        CompositeOp::Atop => (alpha_d, 1.0 - alpha_s),
+        CompositeOp::Xor => (1.0 - alpha_d, 1.0 - alpha_s)
+    };
+    let straight = |c: f64, alpha: f64| if alpha > 0.0 { c / alpha } else { 0.0 };
+    let mut out = [0.0, 0.0, 0.0, alpha_s * fa + alpha_d * fb];
+    for i in 0..3 {
+        let (cs, cd) = (straight(src[i], alpha_s), straight(dst[i], alpha_d));
+        // Where the destination is partly transparent the source shows through unblended.
+        let blended = (1.0 - alpha_d) * cs + alpha_d * blend.blend(cd, cs);
+        out[i] = alpha_s * fa * blended + dst[i] * fb;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilt::knit;
+
+    const OPERATORS: [CompositeOp; 5] = [CompositeOp::Over, CompositeOp::In, CompositeOp::Out, CompositeOp::Atop, CompositeOp::Xor];
+
+    fn assert_close(a: [f64; 4], b: [f64; 4]) {
+        assert!(a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-12), "{a:?} != {b:?}");
+    }
+
+    fn premultiplied([r, g, b]: [f64; 3], alpha: f64) -> [f64; 4] {
+        [r * alpha, g * alpha, b * alpha, alpha]
+    }
+
+    #[test]
+    fn porter_duff_operators() {
+        let (cs, cd) = ([0.9, 0.2, 0.4], [0.1, 0.6, 0.8]);
+        for alpha_s in [0.0, 0.5, 1.0] {
+            for alpha_d in [0.0, 0.25, 1.0] {
+                for op in OPERATORS {
+                    // The areas covered by the source only, the destination only and both.
+                    let (s, d, both) = (alpha_s * (1.0 - alpha_d), alpha_d * (1.0 - alpha_s), alpha_s * alpha_d);
+                    let (s_only, d_only, s_both, d_both) = match op {
+                        CompositeOp::Over => (s, d, both, 0.0),
+                        CompositeOp::In => (0.0, 0.0, both, 0.0),
+                        CompositeOp::Out => (s, 0.0, 0.0, 0.0),
+                        CompositeOp::Atop => (0.0, d, both, 0.0),
+                        CompositeOp::Xor => (s, d, 0.0, 0.0)
+                    };
+                    let alpha = s_only + d_only + s_both + d_both;
+                    let expected = std::array::from_fn(|i| match i {
+                        3 => alpha,
+                        _ => (s_only + s_both) * cs[i] + d_only * cd[i]
+                    });
+                    let out = composite(premultiplied(cs, alpha_s), premultiplied(cd, alpha_d), op, BlendMode::Normal);
+                    assert_close(out, expected);
+                }
+            }
+        }
+        let out = composite(premultiplied([1.0, 0.0, 0.0], 0.5), premultiplied([0.0, 0.0, 1.0], 1.0), CompositeOp::Over, BlendMode::Normal);
+        assert_eq!(unpremultiply(out), Pixel { red: 128, green: 0, blue: 128, alpha: 255 });
+    }
+
+    #[test]
+    fn blend_modes_follow_the_w3c_formulas() {
+        let cases = [
+            (BlendMode::Normal, 0.3, 0.6, 0.6),
+            (BlendMode::Multiply, 0.5, 0.4, 0.2),
+            (BlendMode::Screen, 0.5, 0.4, 0.7),
+            // Overlay is the hard light of the backdrop, multiplying below one half.
+            (BlendMode::Overlay, 0.25, 0.6, 0.3),
+            (BlendMode::Overlay, 0.75, 0.6, 0.8),
+            (BlendMode::Difference, 0.2, 0.7, 0.5),
+            (BlendMode::Difference, 0.7, 0.2, 0.5)
+        ];
+        for (mode, cb, cs, expected) in cases {
+            assert!((mode.blend(cb, cs) - expected).abs() < 1e-12, "{mode:?}");
+            let opaque = composite([cs, cs, cs, 1.0], [cb, cb, cb, 1.0], CompositeOp::Over, mode);
+            assert_close(opaque, [expected, expected, expected, 1.0]);
+            // Over a half transparent backdrop, half of the source is drawn unblended.
+            let out = composite([cs, cs, cs, 1.0], premultiplied([cb; 3], 0.5), CompositeOp::Over, mode);
+            let mixed = 0.5 * cs + 0.5 * expected;
+            assert_close(out, [mixed, mixed, mixed, 1.0]);
+        }
+    }
+
+    #[test]
+    fn equal_z_keeps_the_order_of_pushing() {
+        let mut scene = Scene::new();
+        // Each layer's opacity records when it was pushed.
+        let opacity = |i: usize| 1.0 / (i + 1) as f64;
+        for (i, z) in [1, 0, 1, 0, 1, -3].into_iter().enumerate() {
+            scene.push(Layer {
+                quilt: knit(Raster::solid(Pixel::rgb(0, 0, 0), 1, 1)),
+                z,
+                opacity: opacity(i),
+                operator: CompositeOp::Over,
+                blend: BlendMode::Normal
+            });
+        }
+        let pushed: Vec<f64> = [5, 1, 3, 0, 2, 4].into_iter().map(opacity).collect();
+        assert_eq!(scene.layers().iter().map(|layer| layer.opacity).collect::<Vec<_>>(), pushed);
+        assert!(scene.single_quilt().is_none());
+    }
+}
@@ -1,17 +1,229 @@
 use crate::quilt::Quilt;
 use crate::viewbox::ViewBox;
-use crate::viewbox::fit_vb;
+use crate::scene::Scene;
+use crate::scene::CompositeOp;
+use crate::scene::BlendMode;
+use crate::transform::Transform2D;
+use crate::matrix::decompose_affine;
+use std::time::Duration;
 
-pub fn render_svg_autofit<W>(output: &mut W, quilt: &Quilt) -> std::io::Result<()>
+pub fn render_svg<W>(output: &mut W, quilt: &Quilt, vb: &ViewBox) -> std::io::Result<()>
+where W: std::io::Write
+{   
+    write_svg_open(output, vb)?;
+    write_tiles(output, quilt)?;
+    write!(output, "</svg>")?;
+    Ok(())
+}
+
+/// Renders every layer of `scene` into a single SVG. Each layer's tiles are written once
+/// into `<defs>`, and every compositing step is a group drawing the result of the step
+/// before it together with the next layer, so that later steps can refer back to it.
+/// Opacity and blend modes map onto their SVG equivalents. Porter-Duff operators are
+/// built from masks, which match the raster exactly wherever the layers are opaque or
+/// absent, and approximately along anti-aliased edges.
+pub fn render_scene_svg<W>(output: &mut W, scene: &Scene, vb: &ViewBox) -> std::io::Result<()>
 where W: std::io::Write
 {
-    let vb = fit_vb(quilt);
-    render_svg(output, quilt, &vb)
+    write_scene(output, scene, vb, None)
 }
 
-pub fn render_svg<W>(output: &mut W, quilt: &Quilt, vb: &ViewBox) -> std::io::Result<()>
+/// The transforms of an animation, see [`render_animated_svg`].
+pub struct Motion<'a> {
+    /// The transform of each layer, in the order the layers were pushed, in each frame.
+    pub frames: &'a [Vec<Transform2D>],
+    /// How long each frame is shown for.
+    pub delays: &'a [Duration]
+}
+
+/// Renders an animation of `scene`, whose quilts are drawn untransformed, in which each
+/// layer moves through its transforms in `motion`. Every layer is written only once and
+/// moved by SMIL `<animateTransform>` elements, which browsers play on their own, so the
+/// file is hardly larger than a still image. The animation eases smoothly from each frame
+/// to the next rather than jumping, and starts over after the last frame.
+///
+/// SVG can only animate translations, rotations, skews and scales, so every transform
+/// must be affine, and is broken into those by [`decompose_affine`].
+pub fn render_animated_svg<W>(output: &mut W, scene: &Scene, motion: &Motion, vb: &ViewBox) -> std::io::Result<()>
 where W: std::io::Write
-{   
+{
+    let invalid = |msg: &str| Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg.to_string()));
+    if motion.frames.is_empty() { return invalid("an animation needs at least one frame"); }
+    if motion.frames.len() != motion.delays.len() { return invalid("every frame of an animation needs a delay"); }
+    if motion.frames.iter().any(|layers| layers.len() != scene.layers().len()) {
+        return invalid("every frame of an animation needs a transform for each layer");
+    }
+    if motion.delays.iter().all(Duration::is_zero) { return invalid("an animation must last some time"); }
+
+    // Every transform is broken down before anything is written, so that a transform SVG
+    // cannot animate leaves nothing half written.
+    let animations = (0..motion.frames[0].len())
+        .map(|i| animate(&motion.frames.iter().map(|layers| &layers[i]).collect::<Vec<_>>(), motion.delays))
+        .collect::<std::io::Result<Vec<Animated>>>()?;
+
+    match scene.single_quilt() {
+        // A lone image drawn plainly needs none of the compositing of a scene.
+        Some(quilt) => {
+            write_svg_open(output, vb)?;
+            write!(output, "{}", animations[0].open)?;
+            write_tiles(output, quilt)?;
+            write!(output, "{}</g></svg>", animations[0].motion)?;
+            Ok(())
+        },
+        None => write_scene(output, scene, vb, Some(&animations))
+    }
+}
+
+fn write_scene<W>(output: &mut W, scene: &Scene, vb: &ViewBox, animations: Option<&[Animated]>) -> std::io::Result<()>
+where W: std::io::Write
+{
+    let region = format!("x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"", vb.min_x, vb.min_y, vb.width, vb.height);
+    write_svg_open(output, vb)?;
+    write!(output, "<defs>")?;
+
+    // Masks are luminance masks, so a layer's silhouette is painted white for a mask which
+    // keeps what lies inside it, and black on white for a mask which keeps what lies outside.
+    for (id, value) in [("white", 1), ("black", 0)] {
+        write!(output, "<filter id=\"{}\" {} filterUnits=\"userSpaceOnUse\">", id, region)?;
+        write!(output, "<feColorMatrix type=\"matrix\" values=\"0 0 0 0 {v} 0 0 0 0 {v} 0 0 0 0 {v} 0 0 0 1 0\"/>",
+            v = value)?;
+        write!(output, "</filter>")?;
+    }
+
+    write!(output, "<g id=\"step0\"/>")?;
+    let layers = scene.layers();
+    for (k, (layer, i)) in layers.iter().zip(scene.order()).enumerate() {
+        let (src, dst, step) = (format!("layer{}", k), format!("step{}", k), format!("step{}", k + 1));
+
+        write!(output, "<g id=\"{}\"", src)?;
+        if layer.opacity != 1.0 { write!(output, " opacity=\"{}\"", layer.opacity)?; }
+        write!(output, ">")?;
+        match animations {
+            // The opacity belongs to the outer group, so that it is not replaced by the
+            // transform the animation sets on the inner one.
+            Some(animations) => {
+                write!(output, "{}", animations[i].open)?;
+                write_tiles(output, &layer.quilt)?;
+                write!(output, "{}</g>", animations[i].motion)?;
+            },
+            None => write_tiles(output, &layer.quilt)?
+        }
+        write!(output, "</g>")?;
+
+        for id in [&src, &dst] {
+            write!(output, "<mask id=\"inside-{}\" maskUnits=\"userSpaceOnUse\" {}>", id, region)?;
+            write!(output, "<use href=\"#{}\" filter=\"url(#white)\"/></mask>", id)?;
+            write!(output, "<mask id=\"outside-{}\" maskUnits=\"userSpaceOnUse\" {}>", id, region)?;
+            write!(output, "<rect {} fill=\"white\"/><use href=\"#{}\" filter=\"url(#black)\"/></mask>", region, id)?;
+        }
+
+        let blend = match layer.blend {
+            BlendMode::Normal => String::new(),
+            mode => format!(" style=\"mix-blend-mode:{}\"", mode.css_name())
+        };
+        write!(output, "<g id=\"{}\" style=\"isolation:isolate\">", step)?;
+        match layer.operator {
+            CompositeOp::Over => write!(output,
+                "<use href=\"#{dst}\"/><g{blend}><use href=\"#{src}\"/></g>")?,
+            CompositeOp::In => write!(output,
+                "<g mask=\"url(#inside-{dst})\"><g mask=\"url(#inside-{src})\">\
+                <use href=\"#{dst}\"/><g{blend}><use href=\"#{src}\"/></g></g></g>")?,
+            CompositeOp::Out => write!(output,
+                "<g mask=\"url(#outside-{dst})\"><use href=\"#{src}\"/></g>")?,
+            CompositeOp::Atop => write!(output,
+                "<use href=\"#{dst}\"/><g mask=\"url(#inside-{dst})\"{blend}><use href=\"#{src}\"/></g>")?,
+            CompositeOp::Xor => write!(output,
+                "<g mask=\"url(#outside-{src})\"><use href=\"#{dst}\"/></g>\
+                <g mask=\"url(#outside-{dst})\"><use href=\"#{src}\"/></g>")?
+        }
+        write!(output, "</g>")?;
+    }
+
+    write!(output, "</defs><use href=\"#step{}\"/></svg>", layers.len())?;
+    Ok(())
+}
+
+/// The markup which moves a layer, see [`animate`].
+struct Animated {
+    /// Opens the group holding the layer, placed by its first transform, which is where the
+    /// layer rests in viewers which do not play animations.
+    open: String,
+    /// The animation of the transform of that group, which goes at the end of it.
+    motion: String
+}
+
+/// Animates the transform of a group through `path`, where frame `i` is reached once the
+/// delays of the frames before it have passed. Each transform becomes `translate rotate
+/// skewX scale`, and each of those four is animated on its own, adding up to the whole
+/// transform. Fails if any transform is not affine.
+fn animate(path: &[&Transform2D], delays: &[Duration]) -> std::io::Result<Animated> {
+    let mut parts = Vec::with_capacity(path.len());
+    for transform in path {
+        match decompose_affine(transform.matrix()) {
+            Some(p) => parts.push(p),
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                "SVG cannot animate perspective transforms, write the animation as GIF instead"))
+        }
+    }
+
+    let m = path[0].matrix();
+    let open = format!("<g transform=\"matrix({} {} {} {} {} {})\">",
+        m[(0, 0)], m[(1, 0)], m[(0, 1)], m[(1, 1)], m[(0, 2)], m[(1, 2)]);
+    // The last frame is held for its delay before the animation starts over.
+    let total: f64 = delays.iter().map(Duration::as_secs_f64).sum();
+    let mut key_times = Vec::with_capacity(delays.len() + 1);
+    let mut elapsed = 0.0;
+    for delay in delays {
+        key_times.push(format!("{}", elapsed / total));
+        elapsed += delay.as_secs_f64();
+    }
+    key_times.push(String::from("1"));
+
+    // A reflection across the x-axis after a shear is the same as the opposite shear
+    // before the reflection, which is a negative vertical scale. The reflection is also
+    // a half turn and a negative horizontal scale, and whichever of the two keeps the
+    // angle closest to the frame before is taken, so that a flip between frames squeezes
+    // the image flat rather than spinning it around. Angles are likewise taken the short
+    // way around, rather than spinning back from 179 degrees to -179.
+    let turn = |from: f64, to: f64| (to - from + 180.0).rem_euclid(360.0) - 180.0;
+    let mut angles: Vec<f64> = Vec::with_capacity(parts.len());
+    let mut skews = Vec::with_capacity(parts.len());
+    let mut scales = Vec::with_capacity(parts.len());
+    for p in &parts {
+        let angle = p.rotation.to_degrees();
+        let prev = angles.last().copied().unwrap_or(angle);
+        let (angle, skew, scale) = match p.reflection {
+            false => (angle, p.shear, p.scale),
+            true if turn(prev, angle).abs() <= turn(prev, angle + 180.0).abs() =>
+                (angle, -p.shear, (p.scale.0, -p.scale.1)),
+            true => (angle + 180.0, -p.shear, (-p.scale.0, p.scale.1))
+        };
+        angles.push(prev + turn(prev, angle));
+        skews.push(skew.atan().to_degrees());
+        scales.push(scale);
+    }
+
+    let components: [(&str, Vec<String>); 4] = [
+        ("translate", parts.iter().map(|p| format!("{} {}", p.translation.0, p.translation.1)).collect()),
+        ("rotate", angles.iter().map(|a| format!("{}", a)).collect()),
+        ("skewX", skews.iter().map(|k| format!("{}", k)).collect()),
+        ("scale", scales.iter().map(|(x, y)| format!("{} {}", x, y)).collect())
+    ];
+    let mut motion = String::new();
+    for (k, (kind, mut values)) in components.into_iter().enumerate() {
+        values.push(values[values.len() - 1].clone());
+        // The first replaces the transform the element rests at, and the rest add on.
+        let additive = if k == 0 { "replace" } else { "sum" };
+        motion += &format!("<animateTransform attributeName=\"transform\" type=\"{}\" values=\"{}\" keyTimes=\"{}\" \
+            dur=\"{}s\" additive=\"{}\" repeatCount=\"indefinite\"/>",
+            kind, values.join(";"), key_times.join(";"), total, additive);
+    }
+    Ok(Animated { open, motion })
+}
+
+fn write_svg_open<W>(output: &mut W, vb: &ViewBox) -> std::io::Result<()>
+where W: std::io::Write
+{
     write!(output, "<svg xmlns=\"http://www.w3.org/2000/svg\" ")?; 
     write!(output, "viewBox=\"{} {} {} {}\" ", vb.min_x, vb.min_y, vb.width, vb.height)?;
 
@@ -22,17 +234,83 @@ where W: std::io::Write
     // the SVG might choose to scale the image down/up based on its own whims.
     write!(output, "width=\"{}\" height=\"{}\" ", vb.width, vb.height)?;  
     write!(output, ">")?;
+    Ok(())
+}
 
-    for tile in quilt.tiles() {
+fn write_tiles<W>(output: &mut W, quilt: &Quilt) -> std::io::Result<()>
+where W: std::io::Write
+{
+    for tile in quilt.tiles().filter(|tile| tile.is_visible()) {
         write!(output, "<polygon ")?;
         write!(output, "points=\"{},{} {},{} {},{} {},{}\" ",
             tile.p1().vx(), tile.p1().vy(), tile.p3().vx(), tile.p3().vy(),
             tile.p4().vx(), tile.p4().vy(), tile.p2().vx(), tile.p2().vy())?;
         write!(output, "fill=\"rgb({}, {}, {})\" ", 
             tile.color().red(), tile.color().green(), tile.color().blue())?;
+        // Opaque tiles are by far the most common, so omit the attribute for them
+        // to keep the file small.
+        if tile.color().alpha() != u8::MAX {
+            write!(output, "fill-opacity=\"{}\" ", f64::from(tile.color().alpha()) / 255.0)?;
+        }
         write!(output, "stroke=\"none\" ")?;
         write!(output, "/>")?;
     }
-    write!(output, "</svg>")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quilt::knit;
+    use crate::raster::Pixel;
+    use crate::raster::Raster;
+    use crate::scene::Layer;
+
+    fn scene() -> Scene {
+        let mut scene = Scene::new();
+        let quilt = knit(Raster::solid(Pixel::rgb(10, 20, 30), 1, 1));
+        scene.push(Layer { quilt, z: 0, opacity: 1.0, operator: CompositeOp::Over, blend: BlendMode::Normal });
+        scene
+    }
+
+    fn render(frames: &[Vec<Transform2D>], delays: &[Duration]) -> std::io::Result<String> {
+        let vb = ViewBox { min_x: -2.0, min_y: -2.0, width: 4.0, height: 4.0 };
+        let mut bytes = Vec::new();
+        render_animated_svg(&mut bytes, &scene(), &Motion { frames, delays }, &vb)?;
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn rotation_is_animated_between_keyframes() {
+        let frames = [vec![Transform2D::identity()], vec![Transform2D::rotate(std::f64::consts::FRAC_PI_2)]];
+        let svg = render(&frames, &[Duration::from_millis(500), Duration::from_millis(1500)]).unwrap();
+        let animation = |kind: &str| {
+            let begin = svg.find(&format!("type=\"{}\"", kind)).unwrap();
+            &svg[begin..(begin + svg[begin..].find("/>").unwrap())]
+        };
+        assert_eq!(animation("rotate"),
+            "type=\"rotate\" values=\"0;90;90\" keyTimes=\"0;0.25;1\" dur=\"2s\" additive=\"sum\" repeatCount=\"indefinite\"");
+        assert!(animation("translate").starts_with("type=\"translate\" values=\"0 0;0 0;0 0\" keyTimes=\"0;0.25;1\""));
+        // A rotation decomposes into a scale which is 1 up to rounding.
+        let scale = animation("scale");
+        let values = &scale[(scale.find("values=\"").unwrap() + 8)..scale.find("\" keyTimes").unwrap()];
+        for v in values.split([';', ' ']) {
+            assert!((v.parse::<f64>().unwrap() - 1.0).abs() < 1e-12, "{}", scale);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_motion() {
+        let second = Duration::from_secs(1);
+        let frame = || vec![Transform2D::identity()];
+        for (frames, delays) in [
+            (vec![], vec![]),
+            (vec![frame(), frame()], vec![second]),
+            (vec![frame(), vec![]], vec![second, second]),
+            (vec![frame(), frame()], vec![Duration::ZERO, Duration::ZERO])
+        ] {
+            let err = render(&frames, &delays).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{}", err);
+        }
+    }
+}
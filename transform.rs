@@ -0,0 +1,195 @@
+use crate::matrix::Matrix;
+use crate::matrix::SingularMatrix;
+use crate::matrix::inverse;
+use crate::matrix::matmul_replace;
+use crate::matrix::matmul_replace_threaded;
+use crate::quilt::Quilt;
+
+// Transforms of the plane
+// `Transform2D` wraps the 3x3 matrix which acts on the homogeneous vertex coordinates
+// of a quilt. The constructors build the primitive transforms and `then` chains them
+// in the order they should happen, so rotating an image about its center is
+//
+//     Transform2D::rotate_about(q.center(), theta).apply(&mut q);
+//
+// rather than a translation to the origin, a rotation and a translation back, each
+// written out by hand and multiplied together in reverse.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform2D { matrix: Matrix<f64> }
+
+impl Transform2D {
+    pub fn identity() -> Self {
+        Self { matrix: Matrix::identity(3) }
+    }
+
+    /// The transform whose matrix is `rows`, given in row-major order.
+    pub fn from_rows(rows: [[f64; 3]; 3]) -> Self {
+        Self { matrix: Matrix::literal(rows) }
+    }
+
+    pub fn matrix(&self) -> &Matrix<f64> { &self.matrix }
+
+    /// Whether this transform has no perspective, that is the bottom row of its matrix is
+    /// `0, 0, 1`.
+    pub fn is_affine(&self) -> bool {
+        let m = &self.matrix;
+        m[(2, 0)] == 0.0 && m[(2, 1)] == 0.0 && m[(2, 2)] == 1.0
+    }
+
+    /// Offsets every point by `(dx, dy)`.
+    pub fn translate(dx: f64, dy: f64) -> Self {
+        Self::from_rows([
+            [1.0, 0.0, dx],
+            [0.0, 1.0, dy],
+            [0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// Scales about the origin by `sx` horizontally and `sy` vertically.
+    pub fn scale(sx: f64, sy: f64) -> Self {
+        Self::from_rows([
+            [sx, 0.0, 0.0],
+            [0.0, sy, 0.0],
+            [0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// Scales about `point` rather than the origin, leaving `point` where it is.
+    pub fn scale_about(point: (f64, f64), sx: f64, sy: f64) -> Self {
+        Self::scale(sx, sy).about(point)
+    }
+
+    /// Rotates about the origin by `theta` radians. The y-axis of an image points down,
+    /// so positive angles turn clockwise on screen.
+    pub fn rotate(theta: f64) -> Self {
+        Self::from_rows([
+            [theta.cos(), -theta.sin(), 0.0],
+            [theta.sin(), theta.cos(), 0.0],
+            [0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// Rotates about `point` rather than the origin, leaving `point` where it is.
+    pub fn rotate_about(point: (f64, f64), theta: f64) -> Self {
+        Self::rotate(theta).about(point)
+    }
+
+    /// Reflects across the line `a x + b y + c = 0`. Gives `None` when `a` and `b` are
+    /// both zero, as the equation then describes no line.
+    pub fn reflect_across_line(a: f64, b: f64, c: f64) -> Option<Self> {
+        let n = a * a + b * b;
+        if n <= 0.0 { return None; }
+        // A point moves twice its signed distance from the line along the normal (a, b).
+        Some(Self::from_rows([
+            [1.0 - 2.0 * a * a / n, -2.0 * a * b / n, -2.0 * a * c / n],
+            [-2.0 * a * b / n, 1.0 - 2.0 * b * b / n, -2.0 * b * c / n],
+            [0.0, 0.0, 1.0]
+        ]))
+    }
+
+    /// Shifts every point horizontally by `k` times its y coordinate.
+    pub fn shear_x(k: f64) -> Self {
+        Self::from_rows([
+            [1.0, k, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// Shifts every point vertically by `k` times its x coordinate.
+    pub fn shear_y(k: f64) -> Self {
+        Self::from_rows([
+            [1.0, 0.0, 0.0],
+            [k, 1.0, 0.0],
+            [0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// The transform which applies `self` and then `next`.
+    pub fn then(self, next: Transform2D) -> Self {
+        Self { matrix: &next.matrix * &self.matrix }
+    }
+
+    /// This transform conjugated so that it happens about `point` instead of the origin.
+    fn about(self, (x, y): (f64, f64)) -> Self {
+        Self::translate(-x, -y).then(self).then(Self::translate(x, y))
+    }
+
+    /// The transform which undoes this one. Fails when this transform collapses the
+    /// plane onto a line or a point, as a scale by zero does.
+    pub fn inverse(&self) -> Result<Self, SingularMatrix> {
+        Ok(Self { matrix: inverse(&self.matrix)? })
+    }
+
+    /// Moves every vertex of `quilt` by this transform.
+    pub fn apply(&self, quilt: &mut Quilt) {
+        matmul_replace(&self.matrix, &mut quilt.locmat);
+    }
+
+    /// As `apply` but multiplies on `threads` threads, which pays off for large images.
+    pub fn apply_threaded(&self, quilt: &mut Quilt, threads: usize) {
+        matmul_replace_threaded(&self.matrix, &mut quilt.locmat, threads);
+    }
+}
+
+impl Default for Transform2D {
+    fn default() -> Self { Self::identity() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_to(t: &Transform2D, (x, y): (f64, f64)) -> (f64, f64) {
+        let m = t.matrix();
+        let w = m[(2, 0)] * x + m[(2, 1)] * y + m[(2, 2)];
+        ((m[(0, 0)] * x + m[(0, 1)] * y + m[(0, 2)]) / w, (m[(1, 0)] * x + m[(1, 1)] * y + m[(1, 2)]) / w)
+    }
+
+    fn assert_identity(t: &Transform2D) {
+        let identity = Matrix::<f64>::identity(3);
+        for r in 0..3 {
+            for c in 0..3 {
+                assert!((t.matrix()[(r, c)] - identity[(r, c)]).abs() < 1e-12, "not the identity:\n{}", t.matrix());
+            }
+        }
+    }
+
+    #[test]
+    fn pivots_stay_put() {
+        let center = (12.5, -3.0);
+        for t in [Transform2D::rotate_about(center, 1.1), Transform2D::scale_about(center, 3.0, 0.5)] {
+            let (x, y) = apply_to(&t, center);
+            assert!((x - center.0).abs() < 1e-12 && (y - center.1).abs() < 1e-12);
+        }
+        let (x, y) = apply_to(&Transform2D::rotate(std::f64::consts::FRAC_PI_2), (1.0, 0.0));
+        assert!(x.abs() < 1e-12 && (y - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reflecting_twice_changes_nothing() {
+        let reflect = Transform2D::reflect_across_line(3.0, 4.0, -5.0).unwrap();
+        assert_identity(&reflect.clone().then(reflect.clone()));
+        // Points on the line stay where they are.
+        let (x, y) = apply_to(&reflect, (3.0, -1.0));
+        assert!((x - 3.0).abs() < 1e-12 && (y + 1.0).abs() < 1e-12);
+        assert_eq!(Transform2D::reflect_across_line(0.0, 0.0, 1.0), None);
+    }
+
+    #[test]
+    fn then_applies_its_argument_second() {
+        let shift_then_double = Transform2D::translate(1.0, 0.0).then(Transform2D::scale(2.0, 1.0));
+        let double_then_shift = Transform2D::scale(2.0, 1.0).then(Transform2D::translate(1.0, 0.0));
+        assert_eq!(apply_to(&shift_then_double, (0.0, 0.0)), (2.0, 0.0));
+        assert_eq!(apply_to(&double_then_shift, (0.0, 0.0)), (1.0, 0.0));
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let t = Transform2D::rotate(0.4).then(Transform2D::shear_x(0.7)).then(Transform2D::translate(-3.0, 8.0));
+        assert_identity(&t.inverse().unwrap().then(t.clone()));
+        assert_identity(&t.clone().then(t.inverse().unwrap()));
+        assert!(Transform2D::scale(0.0, 1.0).inverse().is_err());
+    }
+}
@@ -1,25 +1,76 @@
 use crate::quilt::Quilt;
-use crate::geom::PointLike2D;
+use crate::error::AnyError;
 
+#[derive(Clone, Debug)]
 pub struct ViewBox { pub min_x: f64, pub min_y: f64, pub width: f64, pub height: f64 }
 
-pub fn fit_vb(quilt: &Quilt) -> ViewBox {
-    let corners = [quilt.p1(), quilt.p2(), quilt.p3(), quilt.p4()];
-    
-    let min_x = corners.iter().map(|vert| vert.vx())
+impl ViewBox {
+    /// The smallest viewbox containing both `self` and `other`.
+    pub fn union(&self, other: &ViewBox) -> ViewBox {
+        let min_x = self.min_x.min(other.min_x);
+        let min_y = self.min_y.min(other.min_y);
+        let max_x = (self.min_x + self.width).max(other.min_x + other.width);
+        let max_y = (self.min_y + self.height).max(other.min_y + other.height);
+        ViewBox { min_x, min_y, width: max_x - min_x, height: max_y - min_y }
+    }
+}
+
+/// How near the horizon of a perspective transform a viewbox reaches, as a fraction of
+/// the largest `w` among the corners of a quilt. Approaching the horizon, the image
+/// stretches out without bound, so the view has to stop somewhere short of it.
+const HORIZON_MARGIN: f64 = 1.0 / 16.0;
+
+/// Finds the smallest viewbox containing `quilt`. Both affine and projective transforms
+/// map straight lines to straight lines, so the quilt is bounded by its four corners.
+/// A quilt crossing the horizon of a perspective transform is first clipped to the part
+/// in front of it, see [`HORIZON_MARGIN`]. Fails when no part of the quilt is in view.
+pub fn fit_vb(quilt: &Quilt) -> Result<ViewBox, AnyError> {
+    fit_vb_all(std::iter::once(quilt))
+}
+
+/// Finds the smallest viewbox containing every one of `quilts`, as `fit_vb` does for one.
+pub fn fit_vb_all<'a>(quilts: impl IntoIterator<Item = &'a Quilt>) -> Result<ViewBox, AnyError> {
+    let corners: Vec<(f64, f64)> = quilts.into_iter().flat_map(visible_outline).collect();
+    if corners.is_empty() {
+        return Err(AnyError::EmptyImage(String::from("the image lies entirely behind the horizon")));
+    }
+
+    let min_x = corners.iter().map(|&(x, _)| x)
         .reduce(|acc, e| acc.min(e)).unwrap_or(0.0);
 
-    let max_x = corners.iter().map(|vert| vert.vx())
+    let max_x = corners.iter().map(|&(x, _)| x)
         .reduce(|acc, e| acc.max(e)).unwrap_or(0.0);
 
-    let min_y = corners.iter().map(|vert| vert.vy())
+    let min_y = corners.iter().map(|&(_, y)| y)
         .reduce(|acc, e| acc.min(e)).unwrap_or(0.0);
 
-    let max_y = corners.iter().map(|vert| vert.vy())
+    let max_y = corners.iter().map(|&(_, y)| y)
         .reduce(|acc, e| acc.max(e)).unwrap_or(0.0);
     
     let width = max_x - min_x;
     let height = max_y - min_y;
     
-    ViewBox { min_x, min_y, width, height }
+    Ok(ViewBox { min_x, min_y, width, height })
+}
+
+/// The corners of the outline of `quilt` clipped to the half-plane `w >= HORIZON_MARGIN *
+/// w_max`, with the points where its edges cross into that half-plane, after the divide by `w`.
+fn visible_outline(quilt: &Quilt) -> Vec<(f64, f64)> {
+    // Around the outline, rather than in reading order.
+    let outline = [quilt.p1(), quilt.p2(), quilt.p4(), quilt.p3()].map(|vert| vert.homogeneous());
+    let w_max = outline.iter().map(|p| p[2]).fold(f64::NEG_INFINITY, f64::max);
+    if w_max <= 0.0 { return Vec::new(); }
+    let near = HORIZON_MARGIN * w_max;
+
+    let mut points = Vec::with_capacity(8);
+    for (i, a) in outline.iter().enumerate() {
+        let b = &outline[(i + 1) % outline.len()];
+        if a[2] >= near { points.push((a[0] / a[2], a[1] / a[2])); }
+        if (a[2] < near) != (b[2] < near) {
+            let s = (near - a[2]) / (b[2] - a[2]);
+            let [x, y, w]: [f64; 3] = std::array::from_fn(|k| a[k] + s * (b[k] - a[k]));
+            points.push((x / w, y / w));
+        }
+    }
+    points
 }
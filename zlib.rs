@@ -0,0 +1,803 @@
+// DEFLATE compression and decompression, along with the zlib wrapper (RFC 1950/1951).
+// This exists so that PNG images can be read and written without any dependencies.
+//
+// The decompressor follows the structure of Mark Adler's `puff.c`, decoding Huffman
+// codes one bit at a time from their canonical description. The compressor does
+// greedy LZ77 matching over a hash chain, and for each block chooses whichever of
+// stored, fixed Huffman or dynamic Huffman encoding is smallest.
+
+/// The reasons a zlib stream can be rejected by [`zlib_decompress`].
+#[derive(Debug)]
+pub enum InflateError {
+    /// The two byte zlib header is malformed or its check bits are wrong.
+    BadHeader,
+    /// The stream uses a compression method other than DEFLATE.
+    UnsupportedMethod(u8),
+    /// The stream requires a preset dictionary, which is not supported.
+    PresetDictionary,
+    /// Block type 3 is reserved.
+    BadBlockType,
+    /// The length of a stored block does not match its one's complement.
+    StoredLengthMismatch,
+    /// A set of Huffman code lengths does not describe a valid code.
+    BadCodeLengths,
+    /// The bit stream contains a code which is not in the Huffman table.
+    BadCode,
+    /// A back-reference points before the beginning of the output.
+    BadDistance,
+    /// The stream ended before the final block.
+    Truncated,
+    /// The Adler-32 checksum of the decompressed data does not match.
+    ChecksumMismatch,
+    /// The decompressed data is longer than the limit given to [`inflate`].
+    TooLong(usize)
+}
+
+impl std::fmt::Display for InflateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InflateError::BadHeader => write!(f, "invalid zlib header"),
+            InflateError::UnsupportedMethod(m) => write!(f, "unsupported compression method {}", m),
+            InflateError::PresetDictionary => write!(f, "preset dictionaries are not supported"),
+            InflateError::BadBlockType => write!(f, "invalid deflate block type"),
+            InflateError::StoredLengthMismatch => write!(f, "stored block length is corrupt"),
+            InflateError::BadCodeLengths => write!(f, "invalid Huffman code lengths"),
+            InflateError::BadCode => write!(f, "invalid Huffman code"),
+            InflateError::BadDistance => write!(f, "back-reference distance too far back"),
+            InflateError::Truncated => write!(f, "compressed data ends unexpectedly"),
+            InflateError::ChecksumMismatch => write!(f, "Adler-32 checksum mismatch"),
+            InflateError::TooLong(limit) => write!(f, "decompressed data exceeds {} bytes", limit)
+        }
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+/// Computes the Adler-32 checksum of `data`.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // 5552 is the largest number of bytes which can be summed before `b` could overflow.
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses a zlib stream, verifying its header and checksum. Fails as soon as the
+/// output grows past `limit` bytes.
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let (cmf, flg) = match data {
+        [cmf, flg, ..] => (*cmf, *flg),
+        _ => return Err(InflateError::Truncated)
+    };
+    if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 { return Err(InflateError::BadHeader); }
+    if cmf & 0x0F != 8 { return Err(InflateError::UnsupportedMethod(cmf & 0x0F)); }
+    if cmf >> 4 > 7 { return Err(InflateError::BadHeader); }
+    if flg & 0x20 != 0 { return Err(InflateError::PresetDictionary); }
+
+    let (output, consumed) = inflate(&data[2..], limit)?;
+    let trailer = data.get((2 + consumed)..(6 + consumed)).ok_or(InflateError::Truncated)?;
+    let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if adler32(&output) != expected { return Err(InflateError::ChecksumMismatch); }
+    Ok(output)
+}
+
+/// Compresses `data` into a zlib stream.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // CMF: 32K window, DEFLATE. FLG: default compression level, check bits.
+    let mut output = vec![0x78, 0x9C];
+    output.extend(deflate(data));
+    output.extend(adler32(data).to_be_bytes());
+    output
+}
+
+// Tables shared by the compressor and decompressor (RFC 1951 section 3.2.5)
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// The order in which code length code lengths are transmitted.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const MAX_BITS: usize = 15;
+const END_OF_BLOCK: usize = 256;
+
+/// The code lengths of the fixed literal/length and distance codes.
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut litlen = [0u8; 288];
+    litlen[0..144].fill(8);
+    litlen[144..256].fill(9);
+    litlen[256..280].fill(7);
+    litlen[280..288].fill(8);
+    (litlen, [5u8; 30])
+}
+
+// Decompression
+
+struct BitReader<'a> { data: &'a [u8], pos: usize, bitbuf: u32, bitcnt: u32 }
+
+impl<'a> BitReader<'a> {
+    /// Reads `n` bits, least significant first. At most 16 bits may be read at once.
+    fn bits(&mut self, n: u32) -> Result<u32, InflateError> {
+        while self.bitcnt < n {
+            let byte = *self.data.get(self.pos).ok_or(InflateError::Truncated)?;
+            self.pos += 1;
+            self.bitbuf |= u32::from(byte) << self.bitcnt;
+            self.bitcnt += 8;
+        }
+        let value = self.bitbuf & ((1u32 << n) - 1);
+        self.bitbuf >>= n;
+        self.bitcnt -= n;
+        Ok(value)
+    }
+
+    /// Discards the remaining bits of the current byte. Fewer than 8 bits are
+    /// ever buffered between calls to `bits`, so they all belong to that byte.
+    fn align(&mut self) {
+        self.bitbuf = 0;
+        self.bitcnt = 0;
+    }
+}
+
+/// A canonical Huffman code, described by the number of codes of each length
+/// and the symbols ordered by code.
+struct Huffman { count: [u16; MAX_BITS + 1], symbol: Vec<u16> }
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, InflateError> {
+        let mut count = [0u16; MAX_BITS + 1];
+        for len in lengths { count[usize::from(*len)] += 1; }
+
+        // Reject over-subscribed codes. Incomplete codes are permitted since
+        // encoders legitimately produce them when only one distance is used.
+        let mut left: i32 = 1;
+        for n in &count[1..] {
+            left <<= 1;
+            left -= i32::from(*n);
+            if left < 0 { return Err(InflateError::BadCodeLengths); }
+        }
+
+        let mut offs = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS { offs[len + 1] = offs[len] + count[len]; }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (sym, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbol[usize::from(offs[usize::from(*len)])] = sym as u16;
+                offs[usize::from(*len)] += 1;
+            }
+        }
+        Ok(Self { count, symbol })
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<usize, InflateError> {
+        let mut code: i32 = 0;  // the bits read so far
+        let mut first: i32 = 0; // the first code of the current length
+        let mut index: i32 = 0; // the index of that first code in `symbol`
+        for len in 1..=MAX_BITS {
+            code |= input.bits(1)? as i32;
+            let count = i32::from(self.count[len]);
+            if code - count < first {
+                return Ok(usize::from(self.symbol[(index + (code - first)) as usize]));
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(InflateError::BadCode)
+    }
+}
+
+/// Decompresses a raw DEFLATE stream. Returns the output and the number of bytes
+/// of `data` which were consumed. Fails as soon as the output grows past `limit` bytes,
+/// since a few bytes of input can describe an enormous output.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), InflateError> {
+    let mut input = BitReader { data, pos: 0, bitbuf: 0, bitcnt: 0 };
+    let mut output: Vec<u8> = Vec::new();
+    let (fixed_litlen, fixed_dist) = fixed_lengths();
+
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => inflate_stored(&mut input, &mut output, limit)?,
+            1 => {
+                let litlen = Huffman::new(&fixed_litlen)?;
+                let dist = Huffman::new(&fixed_dist)?;
+                inflate_codes(&mut input, &mut output, &litlen, &dist, limit)?;
+            },
+            2 => {
+                let (litlen, dist) = read_dynamic_codes(&mut input)?;
+                inflate_codes(&mut input, &mut output, &litlen, &dist, limit)?;
+            },
+            _ => return Err(InflateError::BadBlockType)
+        }
+        if last { break; }
+    }
+
+    input.align();
+    Ok((output, input.pos))
+}
+
+fn inflate_stored(input: &mut BitReader, output: &mut Vec<u8>, limit: usize) -> Result<(), InflateError> {
+    input.align();
+    let header = input.data.get(input.pos..(input.pos + 4)).ok_or(InflateError::Truncated)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen { return Err(InflateError::StoredLengthMismatch); }
+    input.pos += 4;
+    if output.len() + usize::from(len) > limit { return Err(InflateError::TooLong(limit)); }
+    let end = input.pos + usize::from(len);
+    output.extend_from_slice(input.data.get(input.pos..end).ok_or(InflateError::Truncated)?);
+    input.pos = end;
+    Ok(())
+}
+
+fn read_dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let nlen = input.bits(5)? as usize + 257;
+    let ndist = input.bits(5)? as usize + 1;
+    let ncode = input.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 { return Err(InflateError::BadCodeLengths); }
+
+    let mut clen_lengths = [0u8; 19];
+    for i in CLEN_ORDER.iter().take(ncode) {
+        clen_lengths[*i] = input.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clen_lengths)?;
+
+    // The literal/length and distance code lengths are run-length encoded as a
+    // single sequence, so a repeat may cross from one into the other.
+    let mut lengths: Vec<u8> = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let symbol = clen.decode(input)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or(InflateError::BadCodeLengths)?;
+                (prev, 3 + input.bits(2)? as usize)
+            },
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize)
+        };
+        if lengths.len() + repeat > nlen + ndist { return Err(InflateError::BadCodeLengths); }
+        lengths.resize(lengths.len() + repeat, value);
+    }
+
+    if lengths[END_OF_BLOCK] == 0 { return Err(InflateError::BadCodeLengths); }
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..])?))
+}
+
+fn inflate_codes(input: &mut BitReader, output: &mut Vec<u8>, litlen: &Huffman, dist: &Huffman,
+    limit: usize) -> Result<(), InflateError>
+{
+    loop {
+        let symbol = litlen.decode(input)?;
+        if symbol < 256 {
+            if output.len() == limit { return Err(InflateError::TooLong(limit)); }
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK { return Ok(()); }
+
+        let i = symbol - 257;
+        if i >= LENGTH_BASE.len() { return Err(InflateError::BadCode); }
+        let len = usize::from(LENGTH_BASE[i]) + input.bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+
+        let j = dist.decode(input)?;
+        if j >= DIST_BASE.len() { return Err(InflateError::BadCode); }
+        let distance = usize::from(DIST_BASE[j]) + input.bits(u32::from(DIST_EXTRA[j]))? as usize;
+        if distance > output.len() { return Err(InflateError::BadDistance); }
+        if output.len() + len > limit { return Err(InflateError::TooLong(limit)); }
+
+        // The source and destination may overlap, so copy one byte at a time.
+        let start = output.len() - distance;
+        for k in 0..len { output.push(output[start + k]); }
+    }
+}
+
+// Compression
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 128;
+const HASH_BITS: usize = 15;
+/// The number of LZ77 tokens gathered into each block before it is encoded.
+const BLOCK_TOKENS: usize = 16384;
+
+#[derive(Clone, Copy)]
+enum Token { Literal(u8), Match { len: u16, dist: u16 } }
+
+/// Packs values into bytes least significant bit first, as DEFLATE does. GIF packs its
+/// LZW codes the same way.
+pub struct BitWriter { pub output: Vec<u8>, bitbuf: u64, bitcnt: u32 }
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self { output: Vec::new(), bitbuf: 0, bitcnt: 0 }
+    }
+
+    /// Writes the low `n` bits of `value`, least significant first.
+    pub fn bits(&mut self, value: u32, n: u32) {
+        self.bitbuf |= u64::from(value) << self.bitcnt;
+        self.bitcnt += n;
+        while self.bitcnt >= 8 {
+            self.output.push(self.bitbuf as u8);
+            self.bitbuf >>= 8;
+            self.bitcnt -= 8;
+        }
+    }
+
+    /// Pads the last byte with zero bits.
+    pub fn align(&mut self) {
+        if self.bitcnt > 0 { self.bits(0, 8 - self.bitcnt); }
+    }
+}
+
+/// Compresses `data` into a raw DEFLATE stream.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let tokens = lz77(data);
+    let mut output = BitWriter::new();
+
+    if tokens.is_empty() {
+        // A single empty fixed block.
+        output.bits(1, 1);
+        output.bits(1, 2);
+        let (litlen, _) = fixed_lengths();
+        let codes = canonical_codes(&litlen);
+        output.bits(codes[END_OF_BLOCK], u32::from(litlen[END_OF_BLOCK]));
+        output.align();
+        return output.output;
+    }
+
+    let mut begin = 0; // the offset into `data` of the current block
+    let blocks: Vec<&[Token]> = tokens.chunks(BLOCK_TOKENS).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        let span: usize = block.iter().map(|t| match t {
+            Token::Literal(_) => 1,
+            Token::Match { len, .. } => usize::from(*len)
+        }).sum();
+        let last = i + 1 == blocks.len();
+        write_block(&mut output, block, &data[begin..(begin + span)], last);
+        begin += span;
+    }
+    output.align();
+    output.output
+}
+
+/// Splits `data` into literals and back-references using a hash chain over the
+/// preceding 32K window, taking the longest match found at each position.
+fn lz77(data: &[u8]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut head: Vec<usize> = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev: Vec<usize> = vec![usize::MAX; WINDOW_SIZE];
+
+    let hash = |i: usize| -> usize {
+        let v = (u32::from(data[i]) << 16) | (u32::from(data[i + 1]) << 8) | u32::from(data[i + 2]);
+        (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    };
+    let insert = |head: &mut [usize], prev: &mut [usize], i: usize| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i % WINDOW_SIZE] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(&data[i..(i + max_len)])
+                    .take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len { break; }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // Entries of `prev` are overwritten as the window slides, so a
+                // link which does not point backwards is stale.
+                if next == usize::MAX || next >= candidate { break; }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match { len: best_len as u16, dist: best_dist as u16 });
+            for k in i..(i + best_len) { insert(&mut head, &mut prev, k); }
+            i += best_len;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Finds the index into `LENGTH_BASE` of the length code covering `len`.
+fn length_code(len: u16) -> usize {
+    LENGTH_BASE.iter().rposition(|base| *base <= len).unwrap_or(0)
+}
+
+/// Finds the index into `DIST_BASE` of the distance code covering `dist`.
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.iter().rposition(|base| *base <= dist).unwrap_or(0)
+}
+
+fn write_block(output: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let mut litlen_freq = [0u32; 286];
+    let mut dist_freq = [0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => litlen_freq[usize::from(b)] += 1,
+            Token::Match { len, dist } => {
+                litlen_freq[257 + length_code(len)] += 1;
+                dist_freq[dist_code(dist)] += 1;
+            }
+        }
+    }
+    litlen_freq[END_OF_BLOCK] += 1;
+
+    let dyn_litlen = huffman_lengths(&litlen_freq, MAX_BITS as u8);
+    let dyn_dist = huffman_lengths(&dist_freq, MAX_BITS as u8);
+    let header = DynamicHeader::new(&dyn_litlen, &dyn_dist);
+    let (fixed_litlen, fixed_dist) = fixed_lengths();
+
+    let dynamic_cost = header.cost() + data_cost(&litlen_freq, &dist_freq, &dyn_litlen, &dyn_dist);
+    let fixed_cost = 3 + data_cost(&litlen_freq, &dist_freq, &fixed_litlen, &fixed_dist);
+    // Each stored block carries up to 65535 bytes behind a 3 bit header, alignment
+    // and 4 bytes of lengths.
+    let stored_cost = raw.chunks(65535).map(|c| 3 + 7 + 32 + 8 * c.len() as u64).sum::<u64>();
+
+    if stored_cost < dynamic_cost && stored_cost < fixed_cost {
+        let chunks: Vec<&[u8]> = raw.chunks(65535).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            output.bits(u32::from(last && i + 1 == chunks.len()), 1);
+            output.bits(0, 2);
+            output.align();
+            let len = chunk.len() as u16;
+            output.bits(u32::from(len), 16);
+            output.bits(u32::from(!len), 16);
+            for byte in chunk.iter() { output.bits(u32::from(*byte), 8); }
+        }
+    } else if fixed_cost <= dynamic_cost {
+        output.bits(u32::from(last), 1);
+        output.bits(1, 2);
+        write_tokens(output, tokens, &fixed_litlen, &fixed_dist);
+    } else {
+        output.bits(u32::from(last), 1);
+        output.bits(2, 2);
+        header.write(output);
+        write_tokens(output, tokens, &dyn_litlen, &dyn_dist);
+    }
+}
+
+/// The number of bits needed to encode the symbols counted in the frequency
+/// tables using the given code lengths, including extra bits.
+fn data_cost(litlen_freq: &[u32], dist_freq: &[u32], litlen: &[u8], dist: &[u8]) -> u64 {
+    let mut cost = 0u64;
+    for (sym, freq) in litlen_freq.iter().enumerate() {
+        let extra = if sym > 256 { u64::from(LENGTH_EXTRA[sym - 257]) } else { 0 };
+        cost += u64::from(*freq) * (u64::from(litlen[sym]) + extra);
+    }
+    for (sym, freq) in dist_freq.iter().enumerate() {
+        cost += u64::from(*freq) * (u64::from(dist[sym]) + u64::from(DIST_EXTRA[sym]));
+    }
+    cost
+}
+
+fn write_tokens(output: &mut BitWriter, tokens: &[Token], litlen: &[u8], dist: &[u8]) {
+    let litlen_codes = canonical_codes(litlen);
+    let dist_codes = canonical_codes(dist);
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => {
+                let sym = usize::from(b);
+                output.bits(litlen_codes[sym], u32::from(litlen[sym]));
+            },
+            Token::Match { len, dist: d } => {
+                let i = length_code(len);
+                output.bits(litlen_codes[257 + i], u32::from(litlen[257 + i]));
+                output.bits(u32::from(len - LENGTH_BASE[i]), u32::from(LENGTH_EXTRA[i]));
+                let j = dist_code(d);
+                output.bits(dist_codes[j], u32::from(dist[j]));
+                output.bits(u32::from(d - DIST_BASE[j]), u32::from(DIST_EXTRA[j]));
+            }
+        }
+    }
+    output.bits(litlen_codes[END_OF_BLOCK], u32::from(litlen[END_OF_BLOCK]));
+}
+
+/// Assigns canonical codes to the given code lengths. The codes are returned with
+/// their bits reversed, ready to be written least significant bit first.
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut count = [0u32; MAX_BITS + 1];
+    for len in lengths { count[usize::from(*len)] += 1; }
+    count[0] = 0;
+    let mut code = [0u32; MAX_BITS + 2];
+    for len in 1..=MAX_BITS { code[len + 1] = (code[len] + count[len]) << 1; }
+
+    lengths.iter().map(|len| {
+        let len = usize::from(*len);
+        if len == 0 { return 0; }
+        let c = code[len];
+        code[len] += 1;
+        c.reverse_bits() >> (32 - len)
+    }).collect()
+}
+
+/// Builds Huffman code lengths no longer than `limit` for the given symbol frequencies.
+/// At least two symbols are always given a code so that the result is a complete code.
+fn huffman_lengths(freq: &[u32], limit: u8) -> Vec<u8> {
+    let mut freq: Vec<u32> = freq.to_vec();
+    // Pad out to two used symbols, a lone code of length 1 is not accepted by every decoder.
+    for i in 0..freq.len() {
+        if freq.iter().filter(|f| **f > 0).count() >= 2 { break; }
+        if freq[i] == 0 { freq[i] = 1; }
+    }
+
+    // Build the Huffman tree bottom-up. Leaves are `0..n`, internal nodes follow.
+    let n = freq.len();
+    let mut parent: Vec<usize> = vec![usize::MAX; 2 * n];
+    let mut heap: std::collections::BinaryHeap<std::cmp::Reverse<(u64, usize)>> = freq.iter()
+        .enumerate()
+        .filter(|(_, f)| **f > 0)
+        .map(|(sym, f)| std::cmp::Reverse((u64::from(*f), sym)))
+        .collect();
+    let mut next_node = n;
+    while heap.len() > 1 {
+        let std::cmp::Reverse((fa, a)) = heap.pop().unwrap();
+        let std::cmp::Reverse((fb, b)) = heap.pop().unwrap();
+        parent[a] = next_node;
+        parent[b] = next_node;
+        heap.push(std::cmp::Reverse((fa + fb, next_node)));
+        next_node += 1;
+    }
+
+    let mut lengths: Vec<u8> = vec![0; n];
+    for sym in 0..n {
+        if freq[sym] == 0 { continue; }
+        let mut depth = 0usize;
+        let mut node = sym;
+        while parent[node] != usize::MAX { node = parent[node]; depth += 1; }
+        lengths[sym] = depth.min(usize::from(u8::MAX)) as u8;
+    }
+
+    if lengths.iter().all(|len| *len <= limit) { return lengths; }
+
+    // Some codes are too long. Clamp them, then lengthen the deepest codes which
+    // remain under the limit until the Kraft inequality is satisfied again. The new
+    // lengths are then handed out so that more frequent symbols get shorter codes.
+    let limit = usize::from(limit);
+    let mut count = vec![0u32; limit + 1];
+    for len in &lengths { if *len > 0 { count[usize::from(*len).min(limit)] += 1; } }
+    let mut total: u64 = (1..=limit).map(|len| u64::from(count[len]) << (limit - len)).sum();
+    while total > (1u64 << limit) {
+        count[limit] -= 1;
+        for len in (1..limit).rev() {
+            if count[len] != 0 {
+                count[len] -= 1;
+                count[len + 1] += 2;
+                break;
+            }
+        }
+        total -= 1;
+    }
+
+    let mut by_freq: Vec<usize> = (0..n).filter(|sym| freq[*sym] > 0).collect();
+    by_freq.sort_by(|a, b| freq[*b].cmp(&freq[*a]));
+    let mut syms = by_freq.into_iter();
+    for (len, c) in count.iter().enumerate().skip(1) {
+        for _ in 0..*c { lengths[syms.next().unwrap()] = len as u8; }
+    }
+    lengths
+}
+
+/// The header of a dynamic Huffman block: the code lengths of both codes,
+/// run-length encoded and then themselves Huffman coded.
+struct DynamicHeader { nlen: usize, ndist: usize, ncode: usize, runs: Vec<(u8, u8)>, clen: Vec<u8> }
+
+impl DynamicHeader {
+    fn new(litlen: &[u8], dist: &[u8]) -> Self {
+        // Trailing unused codes need not be transmitted.
+        let nlen = 257.max(litlen.iter().rposition(|len| *len != 0).map_or(0, |i| i + 1));
+        let ndist = 1.max(dist.iter().rposition(|len| *len != 0).map_or(0, |i| i + 1));
+        let lengths: Vec<u8> = litlen[..nlen].iter().chain(&dist[..ndist]).copied().collect();
+
+        // Each run is a code length symbol and the value of its extra bits.
+        let mut runs: Vec<(u8, u8)> = Vec::new();
+        let mut i = 0;
+        while i < lengths.len() {
+            let value = lengths[i];
+            let run = lengths[i..].iter().take_while(|len| **len == value).count();
+            if value == 0 && run >= 11 {
+                let r = run.min(138);
+                runs.push((18, (r - 11) as u8));
+                i += r;
+            } else if value == 0 && run >= 3 {
+                runs.push((17, (run - 3) as u8));
+                i += run;
+            } else if value != 0 && run >= 4 {
+                // The value is sent once, then repeated by code 16.
+                runs.push((value, 0));
+                let r = (run - 1).min(6);
+                runs.push((16, (r - 3) as u8));
+                i += 1 + r;
+            } else {
+                runs.push((value, 0));
+                i += 1;
+            }
+        }
+
+        let mut clen_freq = [0u32; 19];
+        for (sym, _) in &runs { clen_freq[usize::from(*sym)] += 1; }
+        let clen = huffman_lengths(&clen_freq, 7);
+        let ncode = 4.max(CLEN_ORDER.iter().rposition(|i| clen[*i] != 0).map_or(0, |i| i + 1));
+        Self { nlen, ndist, ncode, runs, clen }
+    }
+
+    fn extra_bits(sym: u8) -> u32 {
+        match sym { 16 => 2, 17 => 3, 18 => 7, _ => 0 }
+    }
+
+    fn cost(&self) -> u64 {
+        let runs: u64 = self.runs.iter()
+            .map(|(sym, _)| u64::from(self.clen[usize::from(*sym)]) + u64::from(Self::extra_bits(*sym)))
+            .sum();
+        3 + 5 + 5 + 4 + 3 * self.ncode as u64 + runs
+    }
+
+    fn write(&self, output: &mut BitWriter) {
+        output.bits((self.nlen - 257) as u32, 5);
+        output.bits((self.ndist - 1) as u32, 5);
+        output.bits((self.ncode - 4) as u32, 4);
+        for i in CLEN_ORDER.iter().take(self.ncode) {
+            output.bits(u32::from(self.clen[*i]), 3);
+        }
+        let codes = canonical_codes(&self.clen);
+        for (sym, extra) in &self.runs {
+            let s = usize::from(*sym);
+            output.bits(codes[s], u32::from(self.clen[s]));
+            output.bits(u32::from(*extra), Self::extra_bits(*sym));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes which do not compress, from a xorshift generator.
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed.max(1);
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    fn text() -> Vec<u8> {
+        (0..40).flat_map(|i| format!("the quick brown fox {} jumps over the lazy dog {}. ", i, i * i).into_bytes())
+            .collect()
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..(i + 2)], 16).unwrap()).collect()
+    }
+
+    /// The type of the first block of a raw DEFLATE stream.
+    fn first_block_type(stream: &[u8]) -> u8 {
+        (stream[0] >> 1) & 3
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let stream = deflate(data);
+        let (output, consumed) = inflate(&stream, usize::MAX).expect("inflate");
+        assert_eq!(output, data);
+        assert_eq!(consumed, stream.len());
+        assert_eq!(zlib_decompress(&zlib_compress(data), usize::MAX).expect("zlib"), data);
+        // The output may reach the limit but not pass it.
+        assert_eq!(inflate(&stream, data.len()).expect("limit").0, data);
+        if !data.is_empty() {
+            assert!(matches!(inflate(&stream, data.len() - 1), Err(InflateError::TooLong(_))));
+        }
+        stream
+    }
+
+    #[test]
+    fn empty_input_round_trips() {
+        round_trip(&[]);
+    }
+
+    #[test]
+    fn incompressible_data_is_stored() {
+        let stream = round_trip(&noise(1000, 7));
+        assert_eq!(first_block_type(&stream), 0);
+    }
+
+    #[test]
+    fn stored_data_longer_than_a_stored_block_round_trips() {
+        let stream = round_trip(&noise(70000, 11));
+        assert_eq!(first_block_type(&stream), 0);
+    }
+
+    #[test]
+    fn short_repetitive_data_uses_fixed_codes() {
+        let stream = round_trip(b"abcabcabcabc");
+        assert_eq!(first_block_type(&stream), 1);
+    }
+
+    #[test]
+    fn text_uses_dynamic_codes() {
+        let stream = round_trip(&text());
+        assert_eq!(first_block_type(&stream), 2);
+    }
+
+    #[test]
+    fn data_longer_than_a_block_of_tokens_round_trips() {
+        // Few distinct bytes, so that there are matches, but too random for long ones.
+        let data: Vec<u8> = noise(200000, 3).iter().map(|b| b % 6).collect();
+        assert!(lz77(&data).len() > BLOCK_TOKENS);
+        round_trip(&data);
+    }
+
+    #[test]
+    fn inflates_streams_from_zlib() {
+        // zlib.compress(b"hello"), a single fixed block.
+        assert_eq!(zlib_decompress(&hex("789ccb48cdc9c90700062c0215"), usize::MAX).unwrap(), b"hello");
+        // `text()` compressed by zlib at level 9 as a raw stream, a single dynamic block.
+        let stream = hex("7d94595203310c05afa213a42cc9f6d8c70112200b0c84ac9c9e249f945b9fa94a97467aed77785fc9f7\
+            71fdb295e7fd7cfe94d7f9224936c78faf1f994fabbd1c6e7fd83dfd5e6539bf495a3c7eff0714011d0386401e038e40\
+            1f03993fa98e89828495315191709831f1deb04743a2c2a93a120dd2500e5c13451e646e348763d70cdba80741c2cd34\
+            08bf43321ac44ff96b0d94a139ac8035da872570a3bbf5404e7a95ec41260f8c3dc899e604cfbfc13ec61e1483bb197b\
+            5026c8c7d8834a1e187b50710e7b30e13eecc184770bea80f271f6a09307ce1e74f2cd833e4824b6078590e80579d008\
+            4a4fd54b5071e482d700a2f27196419d5ace5bd4a6743dd641cbbdb7ff00");
+        assert_eq!(first_block_type(&stream), 2);
+        assert_eq!(inflate(&stream, usize::MAX).unwrap().0, text());
+    }
+
+    #[test]
+    fn rejects_corrupt_streams() {
+        let mut stream = zlib_compress(b"hello, hello, hello");
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert!(matches!(zlib_decompress(&stream, usize::MAX), Err(InflateError::ChecksumMismatch)));
+        assert!(matches!(zlib_decompress(&[0x78, 0x9C], usize::MAX), Err(InflateError::Truncated)));
+        assert!(matches!(zlib_decompress(&[0x78, 0x00, 0x03, 0x00], usize::MAX), Err(InflateError::BadHeader)));
+        // A stored block whose length disagrees with its complement.
+        assert!(matches!(inflate(&[0x01, 0x05, 0x00, 0x00, 0x00], usize::MAX), Err(InflateError::StoredLengthMismatch)));
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x00620062);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        // Long enough for the sums to be reduced along the way.
+        assert_eq!(adler32(&[0xFF; 100000]), 0x149A302C);
+    }
+}